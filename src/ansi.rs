@@ -0,0 +1,69 @@
+/// Removes ANSI escape sequences: CSI (`ESC [ ... final`), OSC
+/// (`ESC ] ... BEL` or `ESC ] ... ESC \`) and two-byte `ESC x` sequences.
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                // Parameter and intermediate bytes, then one final byte in @..~.
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Any other escape is a single character long.
+            Some(_) | None => {}
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_ansi;
+
+    #[test]
+    fn strips_escape_sequences() {
+        let cases = [
+            ("plain text", "plain text"),
+            ("\u{1b}[31mred\u{1b}[0m", "red"),
+            ("\u{1b}[1;38;5;208mbold orange\u{1b}[m", "bold orange"),
+            ("a\u{1b}[2Kb", "ab"),
+            // OSC 8 hyperlinks, terminated by BEL or ST.
+            (
+                "\u{1b}]8;;https://example.com\u{7}link\u{1b}]8;;\u{7}",
+                "link",
+            ),
+            ("\u{1b}]0;title\u{1b}\\after", "after"),
+            // Two-byte escapes.
+            ("\u{1b}cclear", "clear"),
+            ("\u{1b}7saved\u{1b}8", "saved"),
+            // Truncated sequences drop what's there.
+            ("end\u{1b}", "end"),
+            ("end\u{1b}[31", "end"),
+            ("ünïcödé \u{1b}[32m✓\u{1b}[0m", "ünïcödé ✓"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(strip_ansi(text), expected, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::pattern::Pattern;
+
+/// Coarse change taxonomy derived from keywords in the commit summary.
+///
+/// Rules are checked in order and the first rule with a keyword that starts
+/// any word of the lowercased summary wins, so `fixes` and `fixed` both match
+/// `fix`. Summaries without a match are classified as `other`.
+#[derive(Clone)]
+pub struct TypeRules {
+    rules: Vec<(String, Vec<String>)>,
+}
+
+impl Default for TypeRules {
+    fn default() -> Self {
+        TypeRules::from_pairs(&[
+            ("bugfix", &["fix", "bug"]),
+            ("feature", &["add", "implement"]),
+            ("refactor", &["refactor", "cleanup"]),
+            ("docs", &["docs", "readme"]),
+            ("test", &["test"]),
+        ])
+    }
+}
+
+impl TypeRules {
+    fn from_pairs(pairs: &[(&str, &[&str])]) -> Self {
+        let rules = pairs
+            .iter()
+            .map(|(kind, keywords)| {
+                (
+                    kind.to_string(),
+                    keywords.iter().map(|k| k.to_string()).collect(),
+                )
+            })
+            .collect();
+        TypeRules { rules }
+    }
+
+    /// Parses `type=keyword,keyword;type=keyword` into an ordered rule list.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for rule in spec.split(';').filter(|r| !r.trim().is_empty()) {
+            let (kind, keywords) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid type rule (expected type=keywords): {}", rule))?;
+            let keywords: Vec<String> = keywords
+                .split(',')
+                .map(|k| k.trim().to_lowercase())
+                .filter(|k| !k.is_empty())
+                .collect();
+            if keywords.is_empty() {
+                return Err(format!("Type rule has no keywords: {}", rule));
+            }
+            rules.push((kind.trim().to_string(), keywords));
+        }
+        Ok(TypeRules { rules })
+    }
+
+    pub fn classify(&self, summary: &str) -> &str {
+        let summary = summary.to_lowercase();
+        let words: Vec<&str> = summary
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect();
+
+        self.rules
+            .iter()
+            .find(|(_, keywords)| {
+                keywords
+                    .iter()
+                    .any(|k| words.iter().any(|w| w.starts_with(k.as_str())))
+            })
+            .map_or("other", |(kind, _)| kind.as_str())
+    }
+}
+
+/// Normalizes a commit summary for grouping near-identical subjects.
+///
+/// The summary is lowercased, ticket references (`#123`, `ABC-123`) are
+/// dropped, every other non-alphanumeric character separates words, and the
+/// remaining words are joined with `-`, so `Fix login (#42)` and
+/// `fix: login` both become `fix-login`.
+pub fn subject_slug(summary: &str) -> String {
+    summary
+        .split_whitespace()
+        .filter(|token| {
+            !is_ticket_ref(token.trim_matches(|c: char| !c.is_alphanumeric() && c != '#'))
+        })
+        .flat_map(|token| token.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// What counts as an issue reference in commit messages. With no patterns
+/// these are `#123` and `ABC-123` words; `--issue-pattern` replaces them
+/// with the given patterns (see `pattern::Pattern` for the syntax), e.g.
+/// `PROJ-\d+` and `#\d+` for a project whose tracker keys are all PROJ.
+#[derive(Clone, Default)]
+pub struct IssuePatterns {
+    patterns: Vec<Pattern>,
+}
+
+impl IssuePatterns {
+    pub fn add(&mut self, pattern: &str) -> Result<(), String> {
+        self.patterns.push(Pattern::parse(pattern)?);
+        Ok(())
+    }
+
+    /// The references in `text`, in order and once each.
+    pub fn find(&self, text: &str) -> Vec<String> {
+        let mut found: Vec<String> = Vec::new();
+        let mut push = |reference: String| {
+            if !found.contains(&reference) {
+                found.push(reference);
+            }
+        };
+        if self.patterns.is_empty() {
+            for token in text.split_whitespace() {
+                let token = token.trim_matches(|c: char| !c.is_alphanumeric() && c != '#');
+                if is_ticket_ref(token) {
+                    push(token.to_string());
+                }
+            }
+        }
+        for pattern in &self.patterns {
+            pattern.find_all(text).into_iter().for_each(&mut push);
+        }
+        found
+    }
+}
+
+pub(crate) fn is_ticket_ref(token: &str) -> bool {
+    if let Some(number) = token.strip_prefix('#') {
+        return !number.is_empty() && number.chars().all(|c| c.is_ascii_digit());
+    }
+    match token.split_once('-') {
+        Some((project, number)) => {
+            !project.is_empty()
+                && project.chars().all(|c| c.is_ascii_alphabetic())
+                && !number.is_empty()
+                && number.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_by_keyword_prefix() {
+        let rules = TypeRules::default();
+        let cases = [
+            ("Fix crash on empty input", "bugfix"),
+            ("fixes #12", "bugfix"),
+            ("Bug: off by one", "bugfix"),
+            ("Add retry to the fetcher", "feature"),
+            ("Implemented caching", "feature"),
+            ("Refactor parser", "refactor"),
+            ("cleanup imports", "refactor"),
+            ("Update README", "docs"),
+            ("tests for glob", "test"),
+            ("Bump version", "other"),
+            // A keyword must start a word, not appear inside one.
+            ("prefix handling", "other"),
+            // The first matching rule wins.
+            ("Add test for the bug", "bugfix"),
+        ];
+        for (summary, kind) in cases {
+            assert_eq!(rules.classify(summary), kind, "{}", summary);
+        }
+    }
+
+    #[test]
+    fn parses_type_keywords() {
+        let rules = TypeRules::parse("perf = speed, FAST; chore=bump").unwrap();
+        assert_eq!(rules.classify("Make it fast"), "perf");
+        assert_eq!(rules.classify("Bump deps"), "chore");
+        assert_eq!(rules.classify("Fix bug"), "other");
+
+        assert!(TypeRules::parse("perf").is_err());
+        assert!(TypeRules::parse("perf=, ,").is_err());
+        assert_eq!(TypeRules::parse("").unwrap().classify("fix"), "other");
+    }
+
+    #[test]
+    fn slugs_drop_ticket_refs_and_punctuation() {
+        let cases = [
+            ("Fix login (#42)", "fix-login"),
+            ("fix: login", "fix-login"),
+            ("ABC-123 Fix login", "fix-login"),
+            ("Don't crash", "don-t-crash"),
+            ("", ""),
+        ];
+        for (summary, slug) in cases {
+            assert_eq!(subject_slug(summary), slug, "{}", summary);
+        }
+    }
+
+    #[test]
+    fn recognises_ticket_refs() {
+        for token in ["#1", "#123", "ABC-1", "jira-42"] {
+            assert!(is_ticket_ref(token), "{}", token);
+        }
+        for token in ["#", "#12a", "ABC-", "-12", "A1-2", "ABC-1x", "plain"] {
+            assert!(!is_ticket_ref(token), "{}", token);
+        }
+    }
+}
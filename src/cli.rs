@@ -0,0 +1,870 @@
+use crate::classify::{IssuePatterns, TypeRules};
+use crate::config::{Config, DEFAULT_CONFIG_FILE};
+use crate::embed::EmbedTarget;
+use crate::logfmt::parse_date;
+use crate::logging::LogFormat;
+use crate::{CommitFilter, ImpactWeights, OnConflict};
+use std::env;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Clone)]
+pub enum Command {
+    Import,
+    CheckDrift,
+    Query(String),
+    AuthorTimeline(String),
+    /// Commits that touched a path, following renames.
+    History(String),
+    Stats(StatsReport),
+    Export(Option<String>),
+    /// `export ownership --author`: the files that author is the top
+    /// contributor of by blame, to `--out` or stdout.
+    ExportOwnership(String, Option<String>),
+    /// Writes the commit graph as DOT or GraphML, to `--out` or stdout.
+    ExportGraph(Option<String>),
+    /// Blames HEAD files matching the `--path` globs (all files when none).
+    Blame,
+    /// Keeps re-importing these repositories (paths or URLs) every
+    /// `--interval-secs`.
+    Watch(Vec<String>),
+    /// Upgrades the database schema without importing anything.
+    Migrate,
+    /// Full-text search over commit messages, or patch text with
+    /// `--patches`. Takes an FTS5 query.
+    Search(String),
+    /// Compares two revisions: merge base, ahead/behind counts and the
+    /// commits only one side has, stored in branch_divergence.
+    Diverge(String, String),
+    /// The commits and/or files whose embeddings are closest to this text,
+    /// `--limit` of them (10 by default).
+    Similar(String),
+    /// Serves the database as a read-only JSON API on `--listen`.
+    Serve,
+    /// Runs an MCP server on stdin/stdout with tools over the database.
+    Mcp,
+    /// Removes refs deleted from the repository and, with `--unreachable`,
+    /// the commits no remaining ref reaches.
+    Prune,
+    /// Re-resolves every commit's author through the current .mailmap.
+    RemapAuthors,
+    /// Fetches pull/merge requests of the project at this remote URL into
+    /// pull_requests.
+    Enrich(String),
+    /// Stores vectors for commit messages and/or text blobs that don't have
+    /// one from `--embed-model` yet.
+    Embed,
+}
+
+/// Built-in reports, run by `stats <name>` or `query <name>`.
+#[derive(Clone)]
+pub enum StatsReport {
+    DuplicateMessages,
+    Freshness,
+    DuplicateRefTargets,
+    TopAuthors,
+    CommitsPerMonth,
+    LargestCommits,
+    MergeRatio,
+    OrphanedRefs,
+    /// Files by how many commits changed them.
+    Hotspots,
+    /// Commits that changed code but no tests, with a sample of them.
+    UntestedChanges,
+    /// Pairs of authors who changed the same files.
+    AuthorNetwork,
+    /// Reflog entries by local weekday and hour.
+    PushActivity,
+    /// Commits by impact_rank, highest first.
+    TopImpact,
+}
+
+impl StatsReport {
+    fn from_name(name: &str) -> Result<StatsReport, String> {
+        match name {
+            "duplicate-messages" => Ok(StatsReport::DuplicateMessages),
+            "freshness" => Ok(StatsReport::Freshness),
+            "duplicate-ref-targets" => Ok(StatsReport::DuplicateRefTargets),
+            "top-authors" => Ok(StatsReport::TopAuthors),
+            "commits-per-month" => Ok(StatsReport::CommitsPerMonth),
+            "largest-commits" => Ok(StatsReport::LargestCommits),
+            "merge-ratio" => Ok(StatsReport::MergeRatio),
+            "orphaned-refs" => Ok(StatsReport::OrphanedRefs),
+            "hotspots" => Ok(StatsReport::Hotspots),
+            "untested-changes" => Ok(StatsReport::UntestedChanges),
+            "author-network" => Ok(StatsReport::AuthorNetwork),
+            "push-activity" => Ok(StatsReport::PushActivity),
+            "top-impact" => Ok(StatsReport::TopImpact),
+            other => Err(format!("Unknown stats report: {}", other)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatsReport::DuplicateMessages => "duplicate-messages",
+            StatsReport::Freshness => "freshness",
+            StatsReport::DuplicateRefTargets => "duplicate-ref-targets",
+            StatsReport::TopAuthors => "top-authors",
+            StatsReport::CommitsPerMonth => "commits-per-month",
+            StatsReport::LargestCommits => "largest-commits",
+            StatsReport::MergeRatio => "merge-ratio",
+            StatsReport::OrphanedRefs => "orphaned-refs",
+            StatsReport::Hotspots => "hotspots",
+            StatsReport::UntestedChanges => "untested-changes",
+            StatsReport::AuthorNetwork => "author-network",
+            StatsReport::PushActivity => "push-activity",
+            StatsReport::TopImpact => "top-impact",
+        }
+    }
+}
+
+/// `--format`: table, json, sql, edgelist, csv, dot or graphml, depending on
+/// the command. Parquet is not supported in this build; it needs the arrow
+/// and parquet crates.
+#[derive(Clone)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Sql,
+    Edgelist,
+    Csv,
+    Dot,
+    GraphMl,
+}
+
+#[derive(Clone)]
+pub struct Cli {
+    pub command: Command,
+    /// A local path, or a URL that is cloned (or fetched) before importing.
+    pub repository_path: String,
+    /// Bare clone used for a URL, defaults to one under the user's cache dir.
+    pub clone_dir: Option<String>,
+    /// Alias the repository is stored under, defaults to its directory name.
+    pub repo_name: Option<String>,
+    pub db_path: String,
+    pub format: OutputFormat,
+    pub refresh_refs_only: bool,
+    pub tag_pattern: Option<String>,
+    /// `--ref-include`, repeatable: only refs matching one are stored and followed.
+    pub ref_include: Vec<String>,
+    /// `--ref-exclude`, repeatable: refs matching one are skipped.
+    pub ref_exclude: Vec<String>,
+    pub type_rules: TypeRules,
+    /// `--issue-pattern`, repeatable; replaces the built-in `#123`/`ABC-123`.
+    pub issue_patterns: IssuePatterns,
+    pub single_transaction: bool,
+    pub alternates: Vec<String>,
+    pub time_budget: Option<Duration>,
+    pub strip_ansi: bool,
+    pub max_db_size_mb: Option<u64>,
+    pub diffs_dir: Option<String>,
+    pub with_patches: bool,
+    /// `export --select-columns`: the columns json and csv exports keep,
+    /// every column when empty.
+    pub select_columns: Vec<String>,
+    /// Flags lockfiles, build output and other generated files in
+    /// commit_files.is_generated.
+    pub detect_generated: bool,
+    /// `--generated-markers`: also flags files whose first line says
+    /// `@generated`. Implies `--detect-generated`.
+    pub generated_markers: bool,
+    /// `stats --exclude-generated`: leaves flagged files out of file reports.
+    pub exclude_generated: bool,
+    /// `stats --resolve-ambiguous-refs`: collapses refs that share a target
+    /// into one row, so release tags on one commit aren't counted twice.
+    pub resolve_ambiguous_refs: bool,
+    /// `--recency-weight`, `--churn-weight` and `--directories-weight`.
+    pub impact_weights: ImpactWeights,
+    pub since_tag: Option<String>,
+    pub tree: bool,
+    pub max_walk_depth: Option<usize>,
+    /// `--since`, `--until` and `--author`.
+    pub filter: CommitFilter,
+    pub quiet_skipped: bool,
+    pub keep_going: bool,
+    pub on_conflict: OnConflict,
+    pub branch: Option<String>,
+    pub limit: Option<usize>,
+    pub tree_every: Option<usize>,
+    /// Globs restricting ingested commits, `blame` and `--with-blobs`, all
+    /// files when empty.
+    pub paths: Vec<String>,
+    pub with_blobs: bool,
+    pub max_blob_size: Option<u64>,
+    pub exclude_binary: bool,
+    /// Also stores the HEAD and branch reflogs.
+    pub with_reflog: bool,
+    /// Also stores what each stash changes in stash_files.
+    pub stash_diffs: bool,
+    /// Stores line counts of every tag (matching `--tag-pattern`) and of
+    /// the `--loc-ref` refs in loc_snapshots.
+    pub loc_snapshots: bool,
+    pub loc_refs: Vec<String>,
+    /// Hides the progress bars.
+    pub quiet: bool,
+    pub jobs: usize,
+    pub fast: bool,
+    pub watch_interval: Duration,
+    /// `export-graph --merges-only`.
+    pub merges_only: bool,
+    /// `export-graph --ref`.
+    pub graph_ref: Option<String>,
+    /// Also imports initialized submodules as repositories of their own.
+    pub recurse_submodules: bool,
+    /// `search --patches`.
+    pub search_patches: bool,
+    /// `--embedder`: `hash` or an embeddings endpoint URL.
+    pub embedder: String,
+    pub embed_model: Option<String>,
+    /// `embed` and `similar` `--commits` and/or `--files`; commits when
+    /// neither is given.
+    pub embed_targets: Vec<EmbedTarget>,
+    /// Reports what an import would add instead of importing.
+    pub dry_run: bool,
+    /// `prune --unreachable`.
+    pub prune_unreachable: bool,
+    /// `prune --archive`: sets archived_at on unreachable commits rather
+    /// than deleting them.
+    pub archive: bool,
+    /// `-v` per step: 1 adds debug and 2 trace events to the log.
+    pub verbosity: u8,
+    pub log_format: LogFormat,
+    /// `serve --listen`, 127.0.0.1:8080 by default.
+    pub listen: String,
+    /// `enrich --token`; GITHUB_TOKEN or GITLAB_TOKEN when not given.
+    pub token: Option<String>,
+    /// `enrich --api-url`, replacing the forge's API root.
+    pub api_url: Option<String>,
+    /// `--config`, read instead of git_info_llama.toml.
+    config: Option<String>,
+    /// Whether the repository and database came from the command line rather
+    /// than their defaults, so a config file doesn't replace them.
+    repository_given: bool,
+    db_given: bool,
+}
+
+impl Cli {
+    /// Parses the command line on top of the config file (`--config`, or
+    /// git_info_llama.toml in the current directory when present), whose
+    /// settings act as defaults that flags override. Imports, blame,
+    /// check-drift and watch without a repository argument run once per
+    /// `[[repository]]` in the config.
+    pub fn parse() -> Result<Vec<Cli>, String> {
+        let args: Vec<String> = env::args().skip(1).collect();
+        let cli = Cli::parse_from(args.iter().cloned())?;
+        let config_path = cli.config.clone().or_else(|| {
+            Path::new(DEFAULT_CONFIG_FILE)
+                .is_file()
+                .then(|| String::from(DEFAULT_CONFIG_FILE))
+        });
+        let clis = match config_path {
+            Some(path) => cli.with_config(&Config::load(Path::new(&path))?, &args)?,
+            None => vec![cli],
+        };
+        if clis
+            .iter()
+            .any(|cli| matches!(&cli.command, Command::Watch(locations) if locations.is_empty()))
+        {
+            return Err(String::from("watch requires at least one repository"));
+        }
+        Ok(clis)
+    }
+
+    /// The options a submodule is imported with: the superproject's, minus
+    /// those naming its refs, and without opening a second transaction.
+    pub fn for_submodule(&self) -> Cli {
+        Cli {
+            repo_name: None,
+            branch: None,
+            since_tag: None,
+            single_transaction: false,
+            ..self.clone()
+        }
+    }
+
+    /// Re-parses `args` with the config's flags in front of them.
+    fn with_config(&self, config: &Config, args: &[String]) -> Result<Vec<Cli>, String> {
+        let mut defaults = config.global.clone();
+        match self.command {
+            Command::Import | Command::Blame | Command::Watch(_) => {
+                defaults.extend(config.ingest.iter().cloned())
+            }
+            Command::Export(_) | Command::ExportOwnership(..) | Command::ExportGraph(_) => {
+                defaults.extend(config.export.iter().cloned())
+            }
+            _ => {}
+        }
+
+        let per_repository = match &self.command {
+            Command::Import
+            | Command::CheckDrift
+            | Command::Blame
+            | Command::Prune
+            | Command::RemapAuthors => !self.repository_given,
+            Command::Watch(locations) => locations.is_empty(),
+            _ => false,
+        };
+        let repositories: Vec<&[String]> = if per_repository && !config.repositories.is_empty() {
+            config.repositories.iter().map(Vec::as_slice).collect()
+        } else {
+            vec![&[]]
+        };
+
+        repositories
+            .into_iter()
+            .map(|repository| {
+                let merged = defaults.iter().chain(repository).chain(args).cloned();
+                let mut cli = Cli::parse_from(merged)?;
+                // A positional database on the command line beats `db`.
+                if self.db_given {
+                    cli.db_path = self.db_path.clone();
+                }
+                if let Command::Watch(locations) = &mut cli.command {
+                    if locations.is_empty() && cli.repository_given {
+                        locations.push(cli.repository_path.clone());
+                    }
+                }
+                Ok(cli)
+            })
+            .collect()
+    }
+
+    pub fn parse_from<I: Iterator<Item = String>>(mut args: I) -> Result<Cli, String> {
+        let mut positional = Vec::new();
+        let mut query = None;
+        let mut db_path = None;
+        let mut format = None;
+        let mut out = None;
+        let mut refresh_refs_only = false;
+        let mut tag_pattern = None;
+        let mut ref_include = Vec::new();
+        let mut ref_exclude = Vec::new();
+        let mut type_rules = TypeRules::default();
+        let mut issue_patterns = IssuePatterns::default();
+        let mut check_drift = false;
+        let mut single_transaction = false;
+        let mut alternates = Vec::new();
+        let mut time_budget = None;
+        let mut strip_ansi = false;
+        let mut max_db_size_mb = None;
+        let mut diffs_dir = None;
+        let mut with_patches = false;
+        let mut detect_generated = false;
+        let mut generated_markers = false;
+        let mut exclude_generated = false;
+        let mut resolve_ambiguous_refs = false;
+        let mut select_columns = Vec::new();
+        let mut impact_weights = ImpactWeights::default();
+        let mut since_tag = None;
+        let mut tree = false;
+        let mut max_walk_depth = None;
+        let mut filter = CommitFilter::default();
+        let mut quiet_skipped = false;
+        let mut keep_going = false;
+        let mut on_conflict = OnConflict::default();
+        let mut repo_path = None;
+        let mut repo_name = None;
+        let mut clone_dir = None;
+        let mut paths = Vec::new();
+        let mut with_blobs = false;
+        let mut max_blob_size = None;
+        let mut exclude_binary = false;
+        let mut with_reflog = false;
+        let mut stash_diffs = false;
+        let mut loc_snapshots = false;
+        let mut loc_refs = Vec::new();
+        let mut quiet = false;
+        let mut jobs = 1;
+        let mut fast = false;
+        let mut watch_interval = Duration::from_secs(60);
+        let mut merges_only = false;
+        let mut graph_ref = None;
+        let mut config = None;
+        let mut recurse_submodules = false;
+        let mut search_patches = false;
+        let mut embedder = String::from("hash");
+        let mut embed_model = None;
+        let mut embed_targets = Vec::new();
+        let mut listen = String::from("127.0.0.1:8080");
+        let mut token = None;
+        let mut api_url = None;
+        let mut verbosity = 0;
+        let mut dry_run = false;
+        let mut prune_unreachable = false;
+        let mut archive = false;
+        let mut log_format = LogFormat::Text;
+        let mut branch = None;
+        let mut limit = None;
+        let mut tree_every = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--query" => query = Some(value_for(&arg, args.next())?),
+                "--out" => out = Some(value_for(&arg, args.next())?),
+                "--db" => db_path = Some(value_for(&arg, args.next())?),
+                "--format" => {
+                    format = Some(match value_for(&arg, args.next())?.as_str() {
+                        "table" => OutputFormat::Table,
+                        "json" => OutputFormat::Json,
+                        "sql" => OutputFormat::Sql,
+                        "edgelist" => OutputFormat::Edgelist,
+                        "csv" => OutputFormat::Csv,
+                        "dot" => OutputFormat::Dot,
+                        "graphml" => OutputFormat::GraphMl,
+                        "parquet" => {
+                            return Err(String::from(
+                                "--format parquet is not supported in this build (it needs the arrow and parquet crates); use --format csv",
+                            ))
+                        }
+                        other => return Err(format!("Unknown output format: {}", other)),
+                    })
+                }
+                "--refresh-refs-only" => refresh_refs_only = true,
+                "--check-drift" => check_drift = true,
+                "--single-transaction" => single_transaction = true,
+                "--strip-ansi" => strip_ansi = true,
+                "--tree" => tree = true,
+                "--quiet-skipped" => quiet_skipped = true,
+                "--keep-going" => keep_going = true,
+                "--on-conflict" => {
+                    on_conflict = match value_for(&arg, args.next())?.as_str() {
+                        "ignore" => OnConflict::Ignore,
+                        "update" => OnConflict::Update,
+                        other => return Err(format!("Unknown conflict policy: {}", other)),
+                    }
+                }
+                "--alternates" => alternates.push(value_for(&arg, args.next())?),
+                "--time-budget-secs" => {
+                    time_budget = Some(Duration::from_secs(number_for(&arg, args.next())?))
+                }
+                "--max-db-size-mb" => max_db_size_mb = Some(number_for(&arg, args.next())?),
+                "--with-patches" => with_patches = true,
+                "--detect-generated" => detect_generated = true,
+                "--recency-weight" => impact_weights.recency = weight_for(&arg, args.next())?,
+                "--churn-weight" => impact_weights.churn = weight_for(&arg, args.next())?,
+                "--directories-weight" => {
+                    impact_weights.directories = weight_for(&arg, args.next())?
+                }
+                "--select-columns" => {
+                    select_columns = value_for(&arg, args.next())?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|column| !column.is_empty())
+                        .map(String::from)
+                        .collect()
+                }
+                "--generated-markers" => {
+                    generated_markers = true;
+                    detect_generated = true;
+                }
+                "--exclude-generated" => exclude_generated = true,
+                "--resolve-ambiguous-refs" | "--dedupe-ref-targets" => {
+                    resolve_ambiguous_refs = true
+                }
+                "--diffs-dir" => diffs_dir = Some(value_for(&arg, args.next())?),
+                "--since-tag" => since_tag = Some(value_for(&arg, args.next())?),
+                "--since" => filter.since = Some(date_for(&arg, args.next())?),
+                "--until" => filter.until = Some(date_for(&arg, args.next())?),
+                "--author" => filter.author = Some(value_for(&arg, args.next())?),
+                "--max-walk-depth" => max_walk_depth = Some(number_for(&arg, args.next())?),
+                "--limit" => limit = Some(number_for(&arg, args.next())?),
+                "--tree-every" => match number_for(&arg, args.next())? {
+                    0 => return Err(String::from("--tree-every must be at least 1")),
+                    every => tree_every = Some(every),
+                },
+                "--repo" => repo_path = Some(value_for(&arg, args.next())?),
+                "--config" => config = Some(value_for(&arg, args.next())?),
+                "--name" => repo_name = Some(value_for(&arg, args.next())?),
+                "--clone-dir" => clone_dir = Some(value_for(&arg, args.next())?),
+                "--path" => paths.push(value_for(&arg, args.next())?),
+                "--with-blobs" => with_blobs = true,
+                "--max-blob-size" => max_blob_size = Some(number_for(&arg, args.next())?),
+                "--exclude-binary" => exclude_binary = true,
+                "--with-reflog" => with_reflog = true,
+                "--stash-diffs" => stash_diffs = true,
+                "--loc-snapshots" => loc_snapshots = true,
+                "--loc-ref" => {
+                    loc_refs.push(value_for(&arg, args.next())?);
+                    loc_snapshots = true;
+                }
+                "--quiet" => quiet = true,
+                "--fast" => fast = true,
+                "--interval-secs" => match number_for(&arg, args.next())? {
+                    0 => return Err(String::from("--interval-secs must be at least 1")),
+                    secs => watch_interval = Duration::from_secs(secs),
+                },
+                "--merges-only" => merges_only = true,
+                "--recurse-submodules" => recurse_submodules = true,
+                "--patches" => search_patches = true,
+                "--embedder" => embedder = value_for(&arg, args.next())?,
+                "--embed-model" => embed_model = Some(value_for(&arg, args.next())?),
+                "--commits" => embed_targets.push(EmbedTarget::Commits),
+                "--files" => embed_targets.push(EmbedTarget::Files),
+                "--listen" => listen = value_for(&arg, args.next())?,
+                "--token" => token = Some(value_for(&arg, args.next())?),
+                "--api-url" => api_url = Some(value_for(&arg, args.next())?),
+                "--dry-run" => dry_run = true,
+                "--unreachable" => prune_unreachable = true,
+                "--archive" => archive = true,
+                "--verbose" | "-v" => verbosity += 1,
+                "-vv" => verbosity += 2,
+                "--log-format" => {
+                    log_format = match value_for(&arg, args.next())?.as_str() {
+                        "text" => LogFormat::Text,
+                        "json" => LogFormat::Json,
+                        other => return Err(format!("Unknown log format: {}", other)),
+                    }
+                }
+                "--ref" => graph_ref = Some(value_for(&arg, args.next())?),
+                "--jobs" => match number_for(&arg, args.next())? {
+                    0 => return Err(String::from("--jobs must be at least 1")),
+                    n => jobs = n,
+                },
+                "--branch" => branch = Some(value_for(&arg, args.next())?),
+                "--tag-pattern" => tag_pattern = Some(value_for(&arg, args.next())?),
+                "--ref-include" => ref_include.push(value_for(&arg, args.next())?),
+                "--ref-exclude" => ref_exclude.push(value_for(&arg, args.next())?),
+                "--type-keywords" => type_rules = TypeRules::parse(&value_for(&arg, args.next())?)?,
+                "--issue-pattern" => issue_patterns.add(&value_for(&arg, args.next())?)?,
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter().peekable();
+        let subcommand = match positional.peek().map(|s| s.as_str()) {
+            Some("author-timeline") => {
+                positional.next();
+                let author = positional
+                    .next()
+                    .ok_or_else(|| String::from("author-timeline requires an author name"))?;
+                Some(Command::AuthorTimeline(author))
+            }
+            Some("history") => {
+                positional.next();
+                let path = positional
+                    .next()
+                    .ok_or_else(|| String::from("history requires a path"))?;
+                Some(Command::History(path))
+            }
+            Some("stats") => {
+                positional.next();
+                let report = positional
+                    .next()
+                    .ok_or_else(|| String::from("stats requires a report name"))?;
+                let report = StatsReport::from_name(&report)?;
+                // `stats top-impact 20` is `stats top-impact --limit 20`.
+                if let StatsReport::TopImpact = report {
+                    if let Some(n) = positional.next_if(|n| n.parse::<usize>().is_ok()) {
+                        limit = Some(number_for("top-impact", Some(n))?);
+                    }
+                }
+                Some(Command::Stats(report))
+            }
+            Some("export") => {
+                positional.next();
+                if positional.peek().map(String::as_str) == Some("ownership") {
+                    positional.next();
+                    let author = filter
+                        .author
+                        .clone()
+                        .ok_or_else(|| String::from("export ownership requires --author"))?;
+                    Some(Command::ExportOwnership(author, out))
+                } else {
+                    Some(Command::Export(out))
+                }
+            }
+            Some("export-graph") => {
+                positional.next();
+                Some(Command::ExportGraph(out.take()))
+            }
+            Some("watch") => {
+                positional.next();
+                // Checked for emptiness once a config file had its say.
+                let locations: Vec<String> = positional.by_ref().collect();
+                if locations.len() > 1 && repo_name.is_some() {
+                    return Err(String::from(
+                        "--name can't be used when watching several repositories",
+                    ));
+                }
+                Some(Command::Watch(locations))
+            }
+            Some("migrate") => {
+                positional.next();
+                // There is no repository to name, so the database may come first.
+                if db_path.is_none() {
+                    db_path = positional.next();
+                }
+                Some(Command::Migrate)
+            }
+            Some("embed") => {
+                positional.next();
+                if db_path.is_none() {
+                    db_path = positional.next();
+                }
+                Some(Command::Embed)
+            }
+            Some("serve") => {
+                positional.next();
+                if db_path.is_none() {
+                    db_path = positional.next();
+                }
+                Some(Command::Serve)
+            }
+            Some("enrich") => {
+                positional.next();
+                let remote = positional
+                    .next()
+                    .ok_or_else(|| String::from("enrich requires a remote URL"))?;
+                if db_path.is_none() {
+                    db_path = positional.next();
+                }
+                Some(Command::Enrich(remote))
+            }
+            Some("mcp") => {
+                positional.next();
+                if db_path.is_none() {
+                    db_path = positional.next();
+                }
+                Some(Command::Mcp)
+            }
+            Some("search") => {
+                positional.next();
+                let query = positional
+                    .next()
+                    .ok_or_else(|| String::from("search requires a query"))?;
+                Some(Command::Search(query))
+            }
+            Some("similar") => {
+                positional.next();
+                let text = positional
+                    .next()
+                    .ok_or_else(|| String::from("similar requires a text to compare with"))?;
+                Some(Command::Similar(text))
+            }
+            Some("diverge") => {
+                positional.next();
+                let (left, right) = positional
+                    .next()
+                    .zip(positional.next())
+                    .ok_or_else(|| String::from("diverge requires two refs"))?;
+                Some(Command::Diverge(left, right))
+            }
+            Some("blame") => {
+                positional.next();
+                Some(Command::Blame)
+            }
+            Some("prune") => {
+                positional.next();
+                Some(Command::Prune)
+            }
+            Some("remap-authors") => {
+                positional.next();
+                Some(Command::RemapAuthors)
+            }
+            Some("ingest") => {
+                positional.next();
+                Some(Command::Import)
+            }
+            Some("refs") => {
+                positional.next();
+                refresh_refs_only = true;
+                Some(Command::Import)
+            }
+            Some("query") => {
+                positional.next();
+                let sql = positional.next().or(query.take()).ok_or_else(|| {
+                    String::from("query requires an SQL statement or report name")
+                })?;
+                // A report name can never be valid SQL, so the two don't clash.
+                match StatsReport::from_name(&sql) {
+                    Ok(report) => Some(Command::Stats(report)),
+                    Err(_) => Some(Command::Query(sql)),
+                }
+            }
+            _ => None,
+        };
+
+        let repo_path = repo_path.or_else(|| positional.next());
+        let repository_given = repo_path.is_some();
+        let repository_path = repo_path.unwrap_or_else(|| String::from("."));
+        let db_path = db_path.or_else(|| positional.next());
+        let db_given = db_path.is_some();
+        let db_path = db_path.unwrap_or_else(|| String::from("git_info_llama.db"));
+
+        if embed_targets.is_empty() {
+            embed_targets.push(EmbedTarget::Commits);
+        }
+        if impact_weights.recency + impact_weights.churn + impact_weights.directories == 0.0 {
+            return Err(String::from("At least one impact weight must be above 0"));
+        }
+        if archive && !prune_unreachable {
+            return Err(String::from("--archive only applies with --unreachable"));
+        }
+
+        let command = match (subcommand, query) {
+            (Some(command), _) => command,
+            (None, Some(sql)) => Command::Query(sql),
+            (None, None) if check_drift => Command::CheckDrift,
+            (None, None) => Command::Import,
+        };
+
+        let format = match (&command, format) {
+            (Command::Export(_), None) => OutputFormat::Sql,
+            (Command::Export(_), Some(format)) => format,
+            (Command::ExportOwnership(..), None) => OutputFormat::Json,
+            (
+                Command::ExportOwnership(..),
+                Some(format @ (OutputFormat::Json | OutputFormat::Csv)),
+            ) => format,
+            (Command::ExportOwnership(..), Some(_)) => {
+                return Err(String::from(
+                    "export ownership only supports --format json or csv",
+                ))
+            }
+            (Command::ExportGraph(_), None) => OutputFormat::Dot,
+            (
+                Command::ExportGraph(_),
+                Some(format @ (OutputFormat::Dot | OutputFormat::GraphMl)),
+            ) => format,
+            (Command::ExportGraph(_), Some(_)) => {
+                return Err(String::from(
+                    "export-graph only supports --format dot or graphml",
+                ))
+            }
+            (Command::Stats(StatsReport::AuthorNetwork), Some(OutputFormat::Dot)) => {
+                OutputFormat::Dot
+            }
+            (_, Some(OutputFormat::Dot | OutputFormat::GraphMl)) => {
+                return Err(String::from(
+                    "--format dot and graphml are only supported by export-graph",
+                ))
+            }
+            (_, Some(OutputFormat::Sql)) => {
+                return Err(String::from("--format sql is only supported by export"))
+            }
+            (_, Some(OutputFormat::Edgelist)) => {
+                return Err(String::from(
+                    "--format edgelist is only supported by export",
+                ))
+            }
+            (_, Some(OutputFormat::Csv)) => {
+                return Err(String::from("--format csv is only supported by export"))
+            }
+            (_, format) => format.unwrap_or(OutputFormat::Table),
+        };
+        if !select_columns.is_empty()
+            && !matches!(
+                (&command, &format),
+                (
+                    Command::Export(_) | Command::ExportOwnership(..),
+                    OutputFormat::Json | OutputFormat::Csv
+                )
+            )
+        {
+            return Err(String::from(
+                "--select-columns only applies to export --format json or csv",
+            ));
+        }
+
+        Ok(Cli {
+            command,
+            repository_path,
+            clone_dir,
+            repo_name,
+            db_path,
+            format,
+            refresh_refs_only,
+            tag_pattern,
+            ref_include,
+            ref_exclude,
+            type_rules,
+            issue_patterns,
+            single_transaction,
+            alternates,
+            time_budget,
+            strip_ansi,
+            max_db_size_mb,
+            diffs_dir,
+            with_patches,
+            select_columns,
+            impact_weights,
+            detect_generated,
+            generated_markers,
+            exclude_generated,
+            resolve_ambiguous_refs,
+            since_tag,
+            tree,
+            max_walk_depth,
+            filter,
+            quiet_skipped,
+            keep_going,
+            on_conflict,
+            branch,
+            limit,
+            tree_every,
+            paths,
+            with_blobs,
+            max_blob_size,
+            exclude_binary,
+            with_reflog,
+            stash_diffs,
+            loc_snapshots,
+            loc_refs,
+            quiet,
+            jobs,
+            fast,
+            watch_interval,
+            merges_only,
+            graph_ref,
+            recurse_submodules,
+            search_patches,
+            embedder,
+            embed_model,
+            embed_targets,
+            listen,
+            token,
+            api_url,
+            dry_run,
+            prune_unreachable,
+            archive,
+            verbosity,
+            log_format,
+            config,
+            repository_given,
+            db_given,
+        })
+    }
+}
+
+fn value_for(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+fn date_for(flag: &str, value: Option<String>) -> Result<i64, String> {
+    let value = value_for(flag, value)?;
+    parse_date(&value).ok_or_else(|| {
+        format!(
+            "Invalid value for {}: {} (expected YYYY-MM-DD or UNIX seconds)",
+            flag, value
+        )
+    })
+}
+
+/// A finite, non-negative impact weight.
+fn weight_for(flag: &str, value: Option<String>) -> Result<f64, String> {
+    let weight: f64 = number_for(flag, value)?;
+    if weight.is_finite() && weight >= 0.0 {
+        Ok(weight)
+    } else {
+        Err(format!("{} must be a non-negative number", flag))
+    }
+}
+
+fn number_for<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value_for(flag, value)?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
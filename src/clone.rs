@@ -0,0 +1,139 @@
+use crate::error::IngestResult;
+use crate::info;
+use git2::{
+    AutotagOption, Config, Cred, CredentialType, FetchOptions, FetchPrune, RemoteCallbacks,
+    Repository,
+};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Branches and tags are fetched into their local names, like
+/// `git clone --mirror` without the other ref namespaces. Configured on the
+/// remote, so no remote-tracking refs are created next to them.
+const REFSPECS: [&str; 2] = ["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"];
+
+/// Whether a repository argument names a remote rather than a local path:
+/// `scheme://...` or scp-like `user@host:path`.
+pub fn is_remote_url(location: &str) -> bool {
+    if location.contains("://") {
+        return true;
+    }
+    match (location.find('@'), location.find(':')) {
+        (Some(at), Some(colon)) => {
+            at < colon && !location[..colon].contains('/') && !Path::new(location).exists()
+        }
+        _ => false,
+    }
+}
+
+/// The last path segment without `.git`, e.g. `repo` for
+/// `https://github.com/org/repo.git`.
+pub fn name_from_url(url: &str) -> String {
+    url.trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .map(|name| name.trim_end_matches(".git").to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("default"))
+}
+
+/// Where `url` is cloned when no `--clone-dir` is given:
+/// `$XDG_CACHE_HOME/git_info_llama/clones/<url>`, falling back to
+/// `~/.cache` and then the temp directory.
+pub fn default_clone_dir(url: &str) -> PathBuf {
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    // Credentials in the URL must not end up in the directory name.
+    let without_userinfo = match url.split_once("://") {
+        Some((_, rest)) => rest.rsplit_once('@').map_or(rest, |(_, host)| host),
+        None => url.rsplit_once('@').map_or(url, |(_, host)| host),
+    };
+    let dir_name: String = without_userinfo
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    cache
+        .join("git_info_llama")
+        .join("clones")
+        .join(format!("{}.git", dir_name))
+}
+
+/// Clones `url` into the bare repository `dir`, or fetches into it when an
+/// earlier run already did, so repeated imports only download new objects.
+/// Refs deleted upstream are pruned.
+pub fn clone_or_fetch(url: &str, dir: &Path) -> IngestResult<Repository> {
+    if dir.exists() {
+        info!("Fetching {} into {}...", url, dir.display());
+        let repo = Repository::open_bare(dir)?;
+        fetch(&repo)?;
+        return Ok(repo);
+    }
+
+    info!("Cloning {} into {}...", url, dir.display());
+    let repo = Repository::init_bare(dir)?;
+    repo.remote_with_fetch("origin", url, REFSPECS[0])?;
+    repo.remote_add_fetch("origin", REFSPECS[1])?;
+    // A failed first clone would otherwise leave an empty repository that
+    // later runs mistake for a finished one.
+    if let Err(e) = fetch(&repo) {
+        fs::remove_dir_all(dir).ok();
+        return Err(e);
+    }
+    Ok(repo)
+}
+
+fn fetch(repo: &Repository) -> IngestResult<()> {
+    let mut remote = repo.find_remote("origin")?;
+    let mut fetch_options = FetchOptions::new();
+    fetch_options
+        .remote_callbacks(credential_callbacks())
+        .prune(FetchPrune::On)
+        .download_tags(AutotagOption::All);
+    remote.fetch::<&str>(&[], Some(&mut fetch_options), None)?;
+
+    // Point HEAD at the remote's default branch, as a clone would. The
+    // advertised refs outlive the fetch's connection.
+    if let Some(branch) = remote.default_branch()?.as_str() {
+        repo.set_head(branch)?;
+    }
+    Ok(())
+}
+
+/// Tries, in order: the SSH agent, git's credential helpers (which cover
+/// tokens stored by `gh` or the OS keychain) and default NTLM/Kerberos
+/// credentials. libgit2 asks again after every rejected credential, so each
+/// source is offered once before giving up.
+fn credential_callbacks<'a>() -> RemoteCallbacks<'a> {
+    let (mut tried_agent, mut tried_helper, mut tried_default) = (false, false, false);
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        if allowed.contains(CredentialType::SSH_KEY) && !tried_agent {
+            tried_agent = true;
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !tried_helper {
+            tried_helper = true;
+            let config = Config::open_default()?;
+            return Cred::credential_helper(&config, url, username);
+        }
+        if allowed.contains(CredentialType::DEFAULT) && !tried_default {
+            tried_default = true;
+            return Cred::default();
+        }
+        Err(git2::Error::from_str(
+            "authentication failed; configure an SSH agent or a git credential helper",
+        ))
+    });
+    callbacks
+}
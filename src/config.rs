@@ -0,0 +1,461 @@
+use std::fs;
+use std::path::Path;
+
+/// Read from the current directory when `--config` isn't given.
+pub const DEFAULT_CONFIG_FILE: &str = "git_info_llama.toml";
+
+/// Settings from a config file, already translated into command-line flags:
+/// `with_blobs = true` becomes `--with-blobs`, `jobs = 4` becomes
+/// `--jobs 4` and `paths = ["src/*", "docs/*"]` becomes two `--path` flags.
+///
+/// ```toml
+/// db = "history.db"
+///
+/// [ingest]
+/// paths = ["src/*", "docs/*"]
+/// max_blob_size = 100000
+///
+/// [export]
+/// format = "csv"
+/// out = "export"
+///
+/// [[repository]]
+/// path = "."
+/// name = "app"
+///
+/// [[repository]]
+/// url = "https://github.com/org/lib.git"
+/// branch = "main"
+/// ```
+pub struct Config {
+    /// Top-level keys, used by every command.
+    pub global: Vec<String>,
+    /// `[ingest]`, used by imports, watch and blame.
+    pub ingest: Vec<String>,
+    /// `[export]`, used by export and export-graph.
+    pub export: Vec<String>,
+    /// One flag list per `[[repository]]`: `path` or `url` become `--repo`,
+    /// every other key a flag as above.
+    pub repositories: Vec<Vec<String>>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config {
+            global: Vec::new(),
+            ingest: Vec::new(),
+            export: Vec::new(),
+            repositories: Vec::new(),
+        };
+        for section in parse_toml(text)? {
+            let args = match (section.name.as_str(), section.is_array) {
+                ("", false) => &mut config.global,
+                ("ingest", false) => &mut config.ingest,
+                ("export", false) => &mut config.export,
+                ("repository", true) => {
+                    config.repositories.push(Vec::new());
+                    config
+                        .repositories
+                        .last_mut()
+                        .ok_or_else(|| String::from("no [[repository]] table to add to"))?
+                }
+                (name, true) => return Err(format!("unknown section [[{}]]", name)),
+                (name, false) => return Err(format!("unknown section [{}]", name)),
+            };
+            for (key, value) in section.entries {
+                match key.as_str() {
+                    "path" | "url" if !section.is_array => {
+                        return Err(format!("{} belongs in a [[repository]] table", key))
+                    }
+                    "db" if section.is_array => {
+                        return Err(String::from("db can't be set per repository"))
+                    }
+                    _ => {}
+                }
+                push_flag(args, &key, value)?;
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn push_flag(args: &mut Vec<String>, key: &str, value: Value) -> Result<(), String> {
+    let flag = match key {
+        "path" | "url" if !matches!(value, Value::Array(_)) => String::from("--repo"),
+        // Arrays are named in the plural, the flag is repeated singular.
+        "paths" => String::from("--path"),
+        "alternates" => String::from("--alternates"),
+        "issue_patterns" => String::from("--issue-pattern"),
+        _ => format!("--{}", key.replace('_', "-")),
+    };
+    match value {
+        Value::Boolean(true) => args.push(flag),
+        Value::Boolean(false) => {}
+        Value::String(s) => args.extend([flag, s]),
+        Value::Integer(i) => args.extend([flag, i.to_string()]),
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    Value::String(s) => args.extend([flag.clone(), s]),
+                    Value::Integer(i) => args.extend([flag.clone(), i.to_string()]),
+                    _ => return Err(format!("{} must be a list of strings or numbers", key)),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+struct Section {
+    /// Empty for the keys before the first header.
+    name: String,
+    /// `[[name]]` rather than `[name]`.
+    is_array: bool,
+    entries: Vec<(String, Value)>,
+}
+
+/// Parses the TOML subset config files need: `[table]` and `[[array]]`
+/// headers, bare or quoted keys, and string, integer, boolean and array
+/// values. Arrays may span lines. Dotted keys, inline tables, floats and
+/// dates are rejected.
+fn parse_toml(text: &str) -> Result<Vec<Section>, String> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        is_array: false,
+        entries: Vec::new(),
+    }];
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let at = |e: String| format!("line {}: {}", index + 1, e);
+        let mut line = strip_comment(line).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            let (name, is_array) = match line.strip_prefix("[[") {
+                Some(rest) => (rest.strip_suffix("]]"), true),
+                None => (
+                    line.strip_prefix('[')
+                        .and_then(|rest| rest.strip_suffix(']')),
+                    false,
+                ),
+            };
+            let name = name
+                .map(str::trim)
+                .filter(|name| is_bare_key(name))
+                .ok_or_else(|| at(format!("invalid table header {}", line)))?;
+            if !is_array && sections.iter().any(|s| s.name == name) {
+                return Err(at(format!("table [{}] defined twice", name)));
+            }
+            sections.push(Section {
+                name: name.to_string(),
+                is_array,
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        // A multi-line array continues until its brackets balance.
+        while bracket_depth(&line) > 0 {
+            let (_, next) = lines
+                .next()
+                .ok_or_else(|| at(String::from("unterminated array")))?;
+            line.push(' ');
+            line.push_str(strip_comment(next).trim());
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| at(format!("expected key = value, found {}", line)))?;
+        let key = parse_key(key.trim()).map_err(at)?;
+        let (value, rest) = parse_value(value.trim()).map_err(at)?;
+        if !rest.trim().is_empty() {
+            return Err(at(format!("unexpected text after value: {}", rest.trim())));
+        }
+        let section = sections
+            .last_mut()
+            .ok_or_else(|| at(format!("{} is outside any table", key)))?;
+        if section.entries.iter().any(|(k, _)| *k == key) {
+            return Err(at(format!("key {} defined twice", key)));
+        }
+        section.entries.push((key, value));
+    }
+    Ok(sections)
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn parse_key(key: &str) -> Result<String, String> {
+    if key.starts_with(['"', '\'']) {
+        let (value, rest) = parse_value(key)?;
+        return match value {
+            Value::String(key) if rest.is_empty() => Ok(key),
+            _ => Err(format!("invalid key {}", key)),
+        };
+    }
+    if key.contains('.') {
+        return Err(format!("dotted keys are not supported: {}", key));
+    }
+    if !is_bare_key(key) {
+        return Err(format!("invalid key {}", key));
+    }
+    Ok(key.to_string())
+}
+
+/// Parses one value off the front of `s`, returning it and what follows.
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(value), &rest[i + 1..])),
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            let hex: String = (0..4)
+                                .filter_map(|_| chars.next())
+                                .map(|(_, c)| c)
+                                .collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| format!("invalid escape \\u{}", hex))?
+                        }
+                        other => return Err(format!("invalid escape \\{}", other.unwrap_or(' '))),
+                    };
+                    value.push(escaped);
+                }
+                c => value.push(c),
+            }
+        }
+        return Err(String::from("unterminated string"));
+    }
+    if let Some(rest) = s.strip_prefix('\'') {
+        let end = rest
+            .find('\'')
+            .ok_or_else(|| String::from("unterminated string"))?;
+        return Ok((Value::String(rest[..end].to_string()), &rest[end + 1..]));
+    }
+    if let Some(mut rest) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            let (item, after) = parse_value(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            match rest.strip_prefix(',') {
+                Some(after) => rest = after,
+                None if rest.starts_with(']') => {}
+                None => return Err(String::from("expected , or ] in array")),
+            }
+        }
+    }
+
+    let end = s
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(s.len());
+    let (token, rest) = s.split_at(end);
+    let value = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => Value::Integer(
+            token
+                .replace('_', "")
+                .parse()
+                .map_err(|_| format!("unsupported value {}", token))?,
+        ),
+    };
+    Ok((value, rest))
+}
+
+/// Drops a `#` comment that isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+/// Open `[` minus closing `]` outside strings after the `=`.
+fn bracket_depth(line: &str) -> i32 {
+    let value = match line.split_once('=') {
+        Some((_, value)) => value,
+        None => return 0,
+    };
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in value.chars() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '[' => depth += 1,
+            None if c == ']' => depth -= 1,
+            None => {}
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_sections_into_flags() {
+        let config = Config::parse(
+            r#"
+db = "history.db"  # where everything goes
+quiet = true
+fast = false
+
+[ingest]
+paths = [
+    "src/*",  # code
+    "docs/*",
+]
+max_blob_size = 100_000
+
+[export]
+format = 'csv'
+
+[[repository]]
+path = "."
+name = "app"
+
+[[repository]]
+url = "https://example.com/org/lib.git#main"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.global, ["--db", "history.db", "--quiet"]);
+        assert_eq!(
+            config.ingest,
+            [
+                "--path",
+                "src/*",
+                "--path",
+                "docs/*",
+                "--max-blob-size",
+                "100000"
+            ]
+        );
+        assert_eq!(config.export, ["--format", "csv"]);
+        assert_eq!(
+            config.repositories,
+            [
+                vec!["--repo", ".", "--name", "app"],
+                vec!["--repo", "https://example.com/org/lib.git#main"],
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_before_the_first_repository_header_are_global() {
+        let config = Config::parse("jobs = 2\n[[repository]]\npath = \"a\"\n").unwrap();
+        assert_eq!(config.global, ["--jobs", "2"]);
+        assert_eq!(config.repositories, [vec!["--repo", "a"]]);
+
+        let error = Config::parse("path = \".\"\n[[repository]]\nname = \"a\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(error, "path belongs in a [[repository]] table");
+    }
+
+    #[test]
+    fn reads_quoted_strings_and_keys() {
+        let config = Config::parse(
+            "\"tag_pattern\" = \"v\\u0031.*\"\nissue_patterns = ['#\\d+', \"a # b\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.global,
+            [
+                "--tag-pattern",
+                "v1.*",
+                "--issue-pattern",
+                "#\\d+",
+                "--issue-pattern",
+                "a # b"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let cases = [
+            (
+                "[ingest]\n[ingest]\n",
+                "line 2: table [ingest] defined twice",
+            ),
+            ("a = 1\na = 2\n", "line 2: key a defined twice"),
+            ("[other]\n", "unknown section [other]"),
+            ("[[other]]\n", "unknown section [[other]]"),
+            ("[ingest\n", "line 1: invalid table header [ingest"),
+            ("a.b = 1\n", "line 1: dotted keys are not supported: a.b"),
+            ("a = \"x\n", "line 1: unterminated string"),
+            ("a = [1,\n", "line 1: unterminated array"),
+            ("a = 1.5\n", "line 1: unsupported value 1.5"),
+            ("a = 1 2\n", "line 1: unexpected text after value: 2"),
+            ("a\n", "line 1: expected key = value, found a"),
+            ("a = \"\\q\"\n", "line 1: invalid escape \\q"),
+            (
+                "[[repository]]\ndb = \"x\"\n",
+                "db can't be set per repository",
+            ),
+            (
+                "paths = [true]\n",
+                "paths must be a list of strings or numbers",
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(
+                Config::parse(text).err().as_deref(),
+                Some(expected),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::error::IngestResult;
+use crate::query::QueryResult;
+use git2::{Oid, Repository};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, Result};
+
+/// How two refs relate: `ahead` commits are reachable only from `left`,
+/// `behind` only from `right`.
+pub struct Divergence {
+    pub left: String,
+    pub right: String,
+    pub left_oid: Oid,
+    pub right_oid: Oid,
+    /// None when the histories are unrelated.
+    pub merge_base: Option<Oid>,
+    pub left_only: Vec<Oid>,
+    pub right_only: Vec<Oid>,
+}
+
+impl Divergence {
+    pub fn ahead(&self) -> usize {
+        self.left_only.len()
+    }
+
+    pub fn behind(&self) -> usize {
+        self.right_only.len()
+    }
+}
+
+/// Resolves both revisions (branch, tag, remote branch or OID) and walks the
+/// commits exclusive to each side, newest first.
+pub fn compute(repo: &Repository, left: &str, right: &str) -> IngestResult<Divergence> {
+    let left_oid = repo.revparse_single(left)?.peel_to_commit()?.id();
+    let right_oid = repo.revparse_single(right)?.peel_to_commit()?.id();
+    let merge_base = match repo.merge_base(left_oid, right_oid) {
+        Ok(oid) => Some(oid),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Divergence {
+        left: left.to_string(),
+        right: right.to_string(),
+        left_oid,
+        right_oid,
+        merge_base,
+        left_only: exclusive_commits(repo, left_oid, right_oid)?,
+        right_only: exclusive_commits(repo, right_oid, left_oid)?,
+    })
+}
+
+fn exclusive_commits(repo: &Repository, from: Oid, hide: Oid) -> IngestResult<Vec<Oid>> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+    walk.push(from)?;
+    walk.hide(hide)?;
+    Ok(walk.collect::<std::result::Result<_, _>>()?)
+}
+
+/// Replaces the stored result for this pair of refs.
+pub fn store(
+    conn: &Connection,
+    repo_id: i64,
+    divergence: &Divergence,
+    computed_at: i64,
+) -> Result<()> {
+    let key = params![repo_id, &divergence.left, &divergence.right];
+    conn.execute(
+        "DELETE FROM branch_divergence_commits WHERE repo_id = ?1 AND left_ref = ?2 AND right_ref = ?3",
+        key,
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO branch_divergence
+         (repo_id, left_ref, right_ref, left_oid, right_oid, merge_base, ahead, behind, computed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            repo_id,
+            &divergence.left,
+            &divergence.right,
+            divergence.left_oid.to_string(),
+            divergence.right_oid.to_string(),
+            divergence.merge_base.map(|oid| oid.to_string()),
+            divergence.ahead() as i64,
+            divergence.behind() as i64,
+            computed_at,
+        ],
+    )?;
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO branch_divergence_commits (repo_id, left_ref, right_ref, side, commit_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for (side, commits) in [
+        ("left", &divergence.left_only),
+        ("right", &divergence.right_only),
+    ] {
+        for oid in commits {
+            stmt.execute(params![
+                repo_id,
+                &divergence.left,
+                &divergence.right,
+                side,
+                oid.to_string()
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+/// The exclusive commits as rows, read from the repository so commits that
+/// were never imported still get a summary.
+pub fn commits_result(repo: &Repository, divergence: &Divergence) -> IngestResult<QueryResult> {
+    let mut rows = Vec::new();
+    for (side, commits) in [
+        (&divergence.left, &divergence.left_only),
+        (&divergence.right, &divergence.right_only),
+    ] {
+        for oid in commits {
+            let commit = repo.find_commit(*oid)?;
+            rows.push(vec![
+                Value::Text(side.clone()),
+                Value::Text(oid.to_string()),
+                Value::Text(commit.author().name().unwrap_or("").to_string()),
+                Value::Integer(commit.time().seconds()),
+                Value::Text(commit.summary().unwrap_or("").to_string()),
+            ]);
+        }
+    }
+    Ok(QueryResult {
+        columns: ["only_in", "commit", "author", "date", "summary"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect(),
+        rows,
+    })
+}
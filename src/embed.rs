@@ -0,0 +1,342 @@
+use crate::http;
+use crate::json::Json;
+use crate::query::QueryResult;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use std::env;
+use std::error::Error;
+
+/// Texts sent to the embedder per request.
+const BATCH_SIZE: usize = 32;
+/// Longer texts are cut, most models can't take much more anyway.
+const MAX_CHARS: usize = 8000;
+/// Dimensions of the built-in `hash` embedder.
+const HASH_DIMENSIONS: usize = 256;
+
+/// Turns texts into vectors. Vectors are only ever compared with others
+/// from the same `model`.
+pub trait Embedder {
+    fn model(&self) -> &str;
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>>;
+}
+
+/// What `embed` and `similar` cover.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EmbedTarget {
+    /// Commit messages.
+    Commits,
+    /// Text blobs stored with `--with-blobs`.
+    Files,
+}
+
+impl EmbedTarget {
+    pub fn kind(self) -> &'static str {
+        match self {
+            EmbedTarget::Commits => "commit",
+            EmbedTarget::Files => "file",
+        }
+    }
+}
+
+/// `hash` for the built-in embedder, or the URL of an OpenAI-compatible
+/// embeddings endpoint (`/v1/embeddings`, or Ollama's `/api/embed`), which
+/// needs a model name. `EMBEDDING_API_KEY` is sent as a bearer token.
+pub fn embedder(spec: &str, model: Option<&str>) -> Result<Box<dyn Embedder>, String> {
+    if spec == "hash" {
+        return Ok(Box::new(HashEmbedder {
+            model: format!("hash-{}", HASH_DIMENSIONS),
+        }));
+    }
+    if spec.starts_with("http://") || spec.starts_with("https://") {
+        let model =
+            model.ok_or_else(|| String::from("--embed-model is required with an embedding URL"))?;
+        return Ok(Box::new(HttpEmbedder {
+            url: spec.to_string(),
+            model: model.to_string(),
+            api_key: env::var("EMBEDDING_API_KEY").ok(),
+        }));
+    }
+    Err(format!(
+        "unknown embedder {}; use hash or an http:// URL",
+        spec
+    ))
+}
+
+/// Feature hashing of lowercased words: no model and no network, so results
+/// only reflect shared vocabulary. Good for trying the pipeline offline.
+struct HashEmbedder {
+    model: String,
+}
+
+impl Embedder for HashEmbedder {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        Ok(texts.iter().map(|text| hash_vector(text)).collect())
+    }
+}
+
+fn hash_vector(text: &str) -> Vec<f32> {
+    let mut vector = vec![0f32; HASH_DIMENSIONS];
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        // FNV-1a; the top bit picks the sign so collisions tend to cancel.
+        let hash = word
+            .to_lowercase()
+            .bytes()
+            .fold(0xcbf29ce484222325u64, |h, b| {
+                (h ^ b as u64).wrapping_mul(0x100000001b3)
+            });
+        let sign = if hash >> 63 == 1 { -1.0 } else { 1.0 };
+        vector[(hash % HASH_DIMENSIONS as u64) as usize] += sign;
+    }
+    normalize(&mut vector);
+    vector
+}
+
+struct HttpEmbedder {
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl Embedder for HttpEmbedder {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let request = Json::Object(vec![
+            (String::from("model"), Json::String(self.model.clone())),
+            (
+                String::from("input"),
+                Json::Array(texts.iter().map(|t| Json::String(t.clone())).collect()),
+            ),
+        ]);
+        let response = http::post_json(&self.url, &request.to_json(), self.api_key.as_deref())?;
+        let response = Json::parse(&response)?;
+
+        // OpenAI: {"data": [{"index": 0, "embedding": [...]}]};
+        // Ollama: {"embeddings": [[...]]}.
+        let vectors: Vec<&Json> = if let Some(data) = response.get("data").and_then(Json::as_array)
+        {
+            let mut items: Vec<&Json> = data.iter().collect();
+            items.sort_by_key(|item| {
+                item.get("index").and_then(Json::as_f64).unwrap_or(0.0) as usize
+            });
+            items
+                .iter()
+                .filter_map(|item| item.get("embedding"))
+                .collect()
+        } else if let Some(embeddings) = response.get("embeddings").and_then(Json::as_array) {
+            embeddings.iter().collect()
+        } else {
+            return Err("embedding response has neither data nor embeddings".into());
+        };
+        if vectors.len() != texts.len() {
+            return Err(format!(
+                "asked for {} embeddings, got {}",
+                texts.len(),
+                vectors.len()
+            )
+            .into());
+        }
+        vectors
+            .into_iter()
+            .map(|vector| {
+                vector
+                    .as_array()
+                    .and_then(|values| {
+                        values
+                            .iter()
+                            .map(|v| v.as_f64().map(|v| v as f32))
+                            .collect()
+                    })
+                    .ok_or_else(|| "embedding is not an array of numbers".into())
+            })
+            .collect()
+    }
+}
+
+/// Embeds every commit message or stored text blob that has no vector from
+/// this model yet, so repeated runs only pay for what's new. Returns how
+/// many vectors were added.
+pub fn embed_missing(
+    conn: &mut Connection,
+    embedder: &dyn Embedder,
+    targets: &[EmbedTarget],
+) -> Result<usize, Box<dyn Error>> {
+    let mut added = 0;
+    for &target in targets {
+        let sql = match target {
+            // Commits shared by several repositories are embedded once.
+            EmbedTarget::Commits => {
+                "SELECT id, MIN(message) FROM commit_details c
+                 WHERE NOT EXISTS (
+                     SELECT 1 FROM embeddings e
+                     WHERE e.kind = 'commit' AND e.object_id = c.id AND e.model = ?1
+                 )
+                 GROUP BY id"
+            }
+            EmbedTarget::Files => {
+                "SELECT blob_oid, content FROM blobs b
+                 WHERE is_binary = 0 AND NOT EXISTS (
+                     SELECT 1 FROM embeddings e
+                     WHERE e.kind = 'file' AND e.object_id = b.blob_oid AND e.model = ?1
+                 )"
+            }
+        };
+        let pending: Vec<(String, String)> = conn
+            .prepare(sql)?
+            .query_map(params![embedder.model()], |row| {
+                let text = match row.get_ref(1)? {
+                    rusqlite::types::ValueRef::Blob(bytes) => {
+                        String::from_utf8_lossy(bytes).into_owned()
+                    }
+                    value => value.as_str().unwrap_or("").to_string(),
+                };
+                Ok((row.get(0)?, text))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        for batch in pending.chunks(BATCH_SIZE) {
+            let texts: Vec<String> = batch.iter().map(|(_, text)| truncate(text)).collect();
+            let vectors = embedder.embed(&texts)?;
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT OR REPLACE INTO embeddings (kind, object_id, model, dimensions, vector, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )?;
+                for ((object_id, _), vector) in batch.iter().zip(&vectors) {
+                    stmt.execute(params![
+                        target.kind(),
+                        object_id,
+                        embedder.model(),
+                        vector.len() as i64,
+                        to_blob(vector),
+                        crate::unix_now(),
+                    ])?;
+                }
+            }
+            tx.commit()?;
+            added += batch.len();
+        }
+    }
+    Ok(added)
+}
+
+/// The `k` stored commits or files closest to `text` by cosine similarity,
+/// best first. Scans every vector of the embedder's model, which stays fast
+/// up to a few hundred thousand vectors.
+pub fn similar(
+    conn: &Connection,
+    embedder: &dyn Embedder,
+    text: &str,
+    targets: &[EmbedTarget],
+    k: usize,
+) -> Result<QueryResult, Box<dyn Error>> {
+    let has_table = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE name = 'embeddings'")?
+        .exists([])?;
+    if !has_table {
+        return Err("this database has no embeddings; run the embed command first".into());
+    }
+    let query = embedder
+        .embed(&[text.to_string()])?
+        .pop()
+        .ok_or("the embedder returned no vector")?;
+
+    let mut scored: Vec<(f32, EmbedTarget, String)> = Vec::new();
+    let mut stmt =
+        conn.prepare("SELECT object_id, vector FROM embeddings WHERE kind = ?1 AND model = ?2")?;
+    for &target in targets {
+        let mut rows = stmt.query(params![target.kind(), embedder.model()])?;
+        while let Some(row) = rows.next()? {
+            let vector = from_blob(row.get_ref(1)?.as_blob()?);
+            if vector.len() == query.len() {
+                scored.push((cosine(&query, &vector), target, row.get(0)?));
+            }
+        }
+    }
+    if scored.is_empty() {
+        return Err(format!(
+            "no embeddings from {} yet; run the embed command first",
+            embedder.model()
+        )
+        .into());
+    }
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.truncate(k);
+
+    let mut rows = Vec::new();
+    for (score, target, object_id) in scored {
+        // Commits show their summary, files a path they appear at.
+        let title: Option<String> = match target {
+            EmbedTarget::Commits => conn.query_row(
+                "SELECT MIN(substr(message, 1, instr(message || char(10), char(10)) - 1))
+                 FROM commit_details WHERE id = ?1",
+                params![object_id],
+                |row| row.get(0),
+            )?,
+            EmbedTarget::Files => conn.query_row(
+                "SELECT MIN(path) FROM tree_files WHERE blob_oid = ?1",
+                params![object_id],
+                |row| row.get(0),
+            )?,
+        };
+        rows.push(vec![
+            Value::Text(target.kind().to_string()),
+            Value::Text(object_id),
+            Value::Real((score as f64 * 10000.0).round() / 10000.0),
+            title.map_or(Value::Null, Value::Text),
+        ]);
+    }
+    Ok(QueryResult {
+        columns: ["kind", "id", "similarity", "title"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect(),
+        rows,
+    })
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norms =
+        a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|y| y * y).sum::<f32>().sqrt();
+    if norms > 0.0 {
+        dot / norms
+    } else {
+        0.0
+    }
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text.to_string(),
+    }
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+}
+
+/// Vectors are stored as little-endian f32s.
+pub fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
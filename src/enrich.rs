@@ -0,0 +1,259 @@
+use crate::http;
+use crate::info;
+use crate::json::Json;
+use crate::logfmt::parse_timestamp;
+use rusqlite::{params, Connection};
+use std::error::Error;
+
+/// Pull requests asked for per API page, the most both forges allow.
+const PAGE_SIZE: usize = 100;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+}
+
+impl Forge {
+    fn name(self) -> &'static str {
+        match self {
+            Forge::GitHub => "github",
+            Forge::GitLab => "gitlab",
+        }
+    }
+}
+
+/// A project on a forge, from its clone URL.
+pub struct Project {
+    pub forge: Forge,
+    pub host: String,
+    /// `owner/repo`, or a GitLab path with subgroups.
+    pub path: String,
+}
+
+/// Reads `https://host/owner/repo(.git)`, `ssh://git@host/owner/repo.git`
+/// or `git@host:owner/repo.git`. Hosts with "github" in their name are
+/// GitHub, anything else is taken to be GitLab.
+pub fn parse_remote(url: &str) -> Result<Project, String> {
+    let rest = match url.split_once("://") {
+        Some((_, rest)) => rest.to_string(),
+        // scp-like git@host:owner/repo.git
+        None => url.replacen(':', "/", 1),
+    };
+    // Users and credentials before the host.
+    let rest = rest.split_once('@').map_or(rest.as_str(), |(_, host)| host);
+    let (host, path) = rest
+        .split_once('/')
+        .ok_or_else(|| format!("no project path in {}", url))?;
+    // Ports belong to git, not to the web API.
+    let host = host.split(':').next().unwrap_or(host).to_string();
+    let path = path
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .to_string();
+    if !path.contains('/') {
+        return Err(format!("no owner/project path in {}", url));
+    }
+    let forge = if host.contains("github") {
+        Forge::GitHub
+    } else {
+        Forge::GitLab
+    };
+    Ok(Project { forge, host, path })
+}
+
+struct PullRequest {
+    number: i64,
+    title: String,
+    state: String,
+    author: Option<String>,
+    reviewers: Vec<String>,
+    labels: Vec<String>,
+    created_at: Option<i64>,
+    merged_at: Option<i64>,
+    merge_commit_id: Option<String>,
+    head_branch: Option<String>,
+    base_branch: Option<String>,
+    url: Option<String>,
+}
+
+/// Fetches the project's pull (merge) requests, newest first and at most
+/// `limit` of them, and upserts them into pull_requests. `api_url` replaces
+/// the forge's default API root (https://api.github.com or
+/// https://<host>/api/v4); requests are plain HTTP, so HTTPS APIs need a
+/// local proxy. Returns how many were stored.
+pub fn enrich(
+    conn: &Connection,
+    project: &Project,
+    api_url: Option<&str>,
+    token: Option<&str>,
+    limit: Option<usize>,
+    fetched_at: i64,
+) -> Result<usize, Box<dyn Error>> {
+    let api = match (api_url, project.forge) {
+        (Some(url), _) => url.trim_end_matches('/').to_string(),
+        (None, Forge::GitHub) => String::from("https://api.github.com"),
+        (None, Forge::GitLab) => format!("https://{}/api/v4", project.host),
+    };
+    let authorization = token.map(|token| match project.forge {
+        Forge::GitHub => ("Authorization", format!("Bearer {}", token)),
+        Forge::GitLab => ("PRIVATE-TOKEN", token.to_string()),
+    });
+    let headers: Vec<(&str, &str)> = authorization
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    let get = |url: &str| -> Result<Json, Box<dyn Error>> {
+        Ok(Json::parse(&http::get_json(url, &headers)?)?)
+    };
+
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut pulls = Vec::new();
+    for page in 1.. {
+        let url = match project.forge {
+            Forge::GitHub => format!(
+                "{}/repos/{}/pulls?state=all&sort=created&direction=desc&per_page={}&page={}",
+                api, project.path, PAGE_SIZE, page
+            ),
+            Forge::GitLab => format!(
+                "{}/projects/{}/merge_requests?state=all&order_by=created_at&sort=desc&per_page={}&page={}",
+                api,
+                project.path.replace('/', "%2F"),
+                PAGE_SIZE,
+                page
+            ),
+        };
+        let items = get(&url)?;
+        let items = items
+            .as_array()
+            .ok_or_else(|| format!("{} did not return a list", url))?;
+        for item in items.iter().take(limit - pulls.len()) {
+            let mut pull = match project.forge {
+                Forge::GitHub => github_pull(item),
+                Forge::GitLab => gitlab_merge_request(item),
+            };
+            // The pull listing only has pending review requests; who actually
+            // reviewed is a request per pull.
+            if project.forge == Forge::GitHub && pull.merged_at.is_some() {
+                let url = format!(
+                    "{}/repos/{}/pulls/{}/reviews",
+                    api, project.path, pull.number
+                );
+                for review in get(&url)?.as_array().unwrap_or(&[]) {
+                    if let Some(login) = text(review, &["user", "login"]) {
+                        if !pull.reviewers.contains(&login) {
+                            pull.reviewers.push(login);
+                        }
+                    }
+                }
+            }
+            pulls.push(pull);
+        }
+        info!(
+            "Fetched {} pull requests from {}...",
+            pulls.len(),
+            project.path
+        );
+        if items.len() < PAGE_SIZE || pulls.len() >= limit {
+            break;
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "INSERT OR REPLACE INTO pull_requests
+         (forge, project, number, title, state, author, reviewers, labels, created_at, merged_at,
+          merge_commit_id, head_branch, base_branch, url, fetched_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+    )?;
+    for pull in &pulls {
+        stmt.execute(params![
+            project.forge.name(),
+            project.path,
+            pull.number,
+            pull.title,
+            pull.state,
+            pull.author,
+            string_array(&pull.reviewers),
+            string_array(&pull.labels),
+            pull.created_at,
+            pull.merged_at,
+            pull.merge_commit_id,
+            pull.head_branch,
+            pull.base_branch,
+            pull.url,
+            fetched_at,
+        ])?;
+    }
+    Ok(pulls.len())
+}
+
+fn github_pull(item: &Json) -> PullRequest {
+    PullRequest {
+        number: number(item, "number"),
+        title: text(item, &["title"]).unwrap_or_default(),
+        // GitHub says closed for merged pulls too.
+        state: match (text(item, &["state"]), item.get("merged_at")) {
+            (_, Some(Json::String(_))) => String::from("merged"),
+            (state, _) => state.unwrap_or_default(),
+        },
+        author: text(item, &["user", "login"]),
+        reviewers: names(item, "requested_reviewers", Some("login")),
+        labels: names(item, "labels", Some("name")),
+        created_at: text(item, &["created_at"]).and_then(|t| parse_timestamp(&t)),
+        merged_at: text(item, &["merged_at"]).and_then(|t| parse_timestamp(&t)),
+        merge_commit_id: text(item, &["merge_commit_sha"]),
+        head_branch: text(item, &["head", "ref"]),
+        base_branch: text(item, &["base", "ref"]),
+        url: text(item, &["html_url"]),
+    }
+}
+
+fn gitlab_merge_request(item: &Json) -> PullRequest {
+    PullRequest {
+        number: number(item, "iid"),
+        title: text(item, &["title"]).unwrap_or_default(),
+        state: text(item, &["state"]).unwrap_or_default(),
+        author: text(item, &["author", "username"]),
+        reviewers: names(item, "reviewers", Some("username")),
+        labels: names(item, "labels", None),
+        created_at: text(item, &["created_at"]).and_then(|t| parse_timestamp(&t)),
+        merged_at: text(item, &["merged_at"]).and_then(|t| parse_timestamp(&t)),
+        // Squashed merge requests land as the squash commit.
+        merge_commit_id: text(item, &["merge_commit_sha"])
+            .or_else(|| text(item, &["squash_commit_sha"])),
+        head_branch: text(item, &["source_branch"]),
+        base_branch: text(item, &["target_branch"]),
+        url: text(item, &["web_url"]),
+    }
+}
+
+/// The string at `path` of nested objects.
+fn text(item: &Json, path: &[&str]) -> Option<String> {
+    path.iter()
+        .try_fold(item, |value, key| value.get(key))?
+        .as_str()
+        .map(str::to_string)
+}
+
+fn number(item: &Json, key: &str) -> i64 {
+    item.get(key).and_then(Json::as_f64).unwrap_or(0.0) as i64
+}
+
+/// The strings of an array, or a field of each of its objects.
+fn names(item: &Json, key: &str, field: Option<&str>) -> Vec<String> {
+    item.get(key)
+        .and_then(Json::as_array)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|value| match field {
+            Some(field) => value.get(field),
+            None => Some(value),
+        })
+        .filter_map(|value| value.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Stored as JSON arrays, like import_runs.args.
+fn string_array(values: &[String]) -> String {
+    Json::Array(values.iter().map(|v| Json::String(v.clone())).collect()).to_json()
+}
@@ -0,0 +1,56 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while reading the repository or writing the
+/// database during an import.
+#[derive(Debug)]
+pub enum IngestError {
+    Git(git2::Error),
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+    /// The database can't be used or upgraded by this build.
+    Schema(String),
+}
+
+pub type IngestResult<T> = Result<T, IngestError>;
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IngestError::Git(e) => write!(f, "git error: {}", e),
+            IngestError::Sqlite(e) => write!(f, "database error: {}", e),
+            IngestError::Io(e) => write!(f, "I/O error: {}", e),
+            IngestError::Schema(message) => write!(f, "schema error: {}", message),
+        }
+    }
+}
+
+impl Error for IngestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IngestError::Git(e) => Some(e),
+            IngestError::Sqlite(e) => Some(e),
+            IngestError::Io(e) => Some(e),
+            IngestError::Schema(_) => None,
+        }
+    }
+}
+
+impl From<git2::Error> for IngestError {
+    fn from(e: git2::Error) -> Self {
+        IngestError::Git(e)
+    }
+}
+
+impl From<rusqlite::Error> for IngestError {
+    fn from(e: rusqlite::Error) -> Self {
+        IngestError::Sqlite(e)
+    }
+}
+
+impl From<io::Error> for IngestError {
+    fn from(e: io::Error) -> Self {
+        IngestError::Io(e)
+    }
+}
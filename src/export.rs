@@ -0,0 +1,381 @@
+use crate::cli::OutputFormat;
+use crate::query::{self, QueryResult};
+use crate::warn;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Exports the database in `format`. `columns` (`--select-columns`) limits
+/// json and csv exports to those columns, all of them when empty.
+pub fn export(
+    conn: &Connection,
+    format: &OutputFormat,
+    out: Option<&str>,
+    columns: &[String],
+) -> Result<(), Box<dyn Error>> {
+    // One CSV file per table, so --out names a directory rather than a file.
+    if let OutputFormat::Csv = format {
+        let dir = out.ok_or("export --format csv requires --out <directory>")?;
+        return csv_tables(conn, Path::new(dir), columns);
+    }
+
+    let mut writer: Box<dyn Write> = match out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    match format {
+        OutputFormat::Sql => sql_dump(conn, &mut writer)?,
+        OutputFormat::Edgelist => edge_list(conn, &mut writer)?,
+        OutputFormat::Json => json_lines(conn, &mut writer, columns)?,
+        _ => return Err("export only supports --format sql, json, csv or edgelist".into()),
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the files whose blame (from the blame command) has the author
+/// matching `author`, a case-insensitive substring of their name or email
+/// like `--author`, as the top contributor by commit count, with their
+/// share of the file's blamed commits and lines. Contributors are told
+/// apart by email; a tie makes each of them a top contributor. `columns`
+/// limits the output as in `export`.
+pub fn ownership(
+    conn: &Connection,
+    author: &str,
+    format: &OutputFormat,
+    out: Option<&str>,
+    columns: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let blamed: i64 = conn.query_row("SELECT COUNT(*) FROM file_blame", [], |row| row.get(0))?;
+    if blamed == 0 {
+        warn!("file_blame is empty; run the blame command first.");
+    }
+    let result = query::run_query_with(
+        conn,
+        "WITH contributions AS (
+             SELECT repo_id, path, lower(author_email) AS email, MAX(author) AS author,
+                    COUNT(DISTINCT commit_id) AS commits, SUM(line_count) AS lines
+             FROM file_blame
+             GROUP BY repo_id, path, lower(author_email)
+         ),
+         files AS (
+             SELECT repo_id, path, MAX(commits) AS top_commits,
+                    SUM(commits) AS total_commits, SUM(lines) AS total_lines
+             FROM contributions
+             GROUP BY repo_id, path
+         )
+         SELECT r.name AS repository, c.path, c.author, c.email,
+                c.commits, f.total_commits,
+                ROUND(100.0 * c.commits / f.total_commits, 1) AS share_percent,
+                c.lines, f.total_lines
+         FROM contributions c
+         JOIN files f ON f.repo_id = c.repo_id AND f.path = c.path
+         JOIN repositories r ON r.repo_id = c.repo_id
+         WHERE c.commits = f.top_commits
+           AND (instr(lower(c.author), lower(?1)) > 0 OR instr(c.email, lower(?1)) > 0)
+         ORDER BY share_percent DESC, repository ASC, c.path ASC",
+        rusqlite::params![author],
+    )?;
+    let result = select_result_columns(result, columns)?;
+
+    let mut writer: Box<dyn Write> = match out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    match format {
+        OutputFormat::Json => writeln!(writer, "{}", query::to_json(&result))?,
+        OutputFormat::Csv => {
+            let header: Vec<String> = result.columns.iter().map(|c| csv_field(c)).collect();
+            write!(writer, "{}\r\n", header.join(","))?;
+            for row in &result.rows {
+                let values: Vec<String> = row.iter().map(csv_value).collect();
+                write!(writer, "{}\r\n", values.join(","))?;
+            }
+        }
+        _ => return Err("export ownership only supports --format json or csv".into()),
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Tables holding rows of their own: not SQLite's, not FTS5 indexes and not
+/// the shadow tables those keep their data in.
+const PLAIN_TABLES: &str = "type = 'table' AND name NOT LIKE 'sqlite_%'
+    AND sql NOT LIKE 'CREATE VIRTUAL TABLE%'
+    AND NOT EXISTS (
+        SELECT 1 FROM sqlite_master v
+        WHERE v.sql LIKE 'CREATE VIRTUAL TABLE%' AND sqlite_master.name LIKE v.name || '\\_%' ESCAPE '\\'
+    )";
+
+/// Writes the schema and every row as plain SQL that any SQLite (or mostly
+/// any SQL engine) can replay into an empty database.
+fn sql_dump(conn: &Connection, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let tables: Vec<(String, String)> = conn
+        .prepare(&format!(
+            "SELECT name, sql FROM sqlite_master WHERE {} ORDER BY name",
+            PLAIN_TABLES
+        ))?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    writeln!(out, "BEGIN TRANSACTION;")?;
+    for (name, create_sql) in &tables {
+        writeln!(out, "{};", create_sql)?;
+
+        let mut stmt = conn.prepare(&format!("SELECT * FROM {}", quote_identifier(name)))?;
+        let column_count = stmt.column_count();
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let values = (0..column_count)
+                .map(|i| row.get::<_, Value>(i).map(|value| sql_literal(&value)))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            writeln!(
+                out,
+                "INSERT INTO {} VALUES({});",
+                quote_identifier(name),
+                values.join(",")
+            )?;
+        }
+    }
+
+    // Explicit indexes only; the ones backing primary keys have no SQL.
+    let mut stmt = conn.prepare(
+        "SELECT sql FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL ORDER BY name",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        writeln!(out, "{};", row.get::<_, String>(0)?)?;
+    }
+
+    // Search indexes are rebuilt from the rows rather than copied, and the
+    // triggers that maintain them and the rollups only start firing once
+    // every row is in.
+    let mut stmt = conn.prepare(
+        "SELECT name, sql FROM sqlite_master WHERE type = 'table' AND sql LIKE 'CREATE VIRTUAL TABLE%' ORDER BY name",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let name = quote_identifier(&row.get::<_, String>(0)?);
+        writeln!(out, "{};", row.get::<_, String>(1)?)?;
+        writeln!(out, "INSERT INTO {}({}) VALUES('rebuild');", name, name)?;
+    }
+    let mut stmt =
+        conn.prepare("SELECT sql FROM sqlite_master WHERE type = 'trigger' ORDER BY name")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        writeln!(out, "{};", row.get::<_, String>(0)?)?;
+    }
+    writeln!(out, "COMMIT;")?;
+
+    Ok(())
+}
+
+/// Writes one `parent child` line per `commit_relation` edge, the plain edge
+/// list format networkx and most graph tools can read. Edges shared by
+/// several repositories are written once.
+fn edge_list(conn: &Connection, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT parent, child FROM commit_relation GROUP BY parent, child ORDER BY MIN(rowid)",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        writeln!(
+            out,
+            "{} {}",
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes commits, parent relations and refs as newline-delimited JSON, one
+/// object per row with a `record` field naming its kind, ready to stream into
+/// tools that don't speak SQL.
+fn json_lines(
+    conn: &Connection,
+    out: &mut dyn Write,
+    columns: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let records = [
+        ("commit", "commit_details", "repo_id, date, id"),
+        ("relation", "commit_relation", "rowid"),
+        ("ref", "ref_details", "repo_id, name"),
+    ];
+    let tables: Vec<String> = records.iter().map(|(_, t, _)| t.to_string()).collect();
+    let selected = select_lists(conn, &tables, columns)?;
+    for (record, table, order) in records {
+        let Some((_, list)) = selected.iter().find(|(name, _)| name == table) else {
+            continue;
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT '{}' AS record, {} FROM {} ORDER BY {}",
+            record, list, table, order
+        ))?;
+        let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let values = (0..columns.len())
+                .map(|i| row.get::<_, Value>(i))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            writeln!(out, "{}", query::row_to_json(&columns, &values))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes every table to `<dir>/<table>.csv` with a header row. NULL is
+/// written as an empty field and blobs as hex.
+fn csv_tables(conn: &Connection, dir: &Path, columns: &[String]) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let tables: Vec<String> = conn
+        .prepare(&format!(
+            "SELECT name FROM sqlite_master WHERE {} ORDER BY name",
+            PLAIN_TABLES
+        ))?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    for (name, list) in select_lists(conn, &tables, columns)? {
+        let mut out = BufWriter::new(File::create(dir.join(format!("{}.csv", name)))?);
+        let mut stmt =
+            conn.prepare(&format!("SELECT {} FROM {}", list, quote_identifier(&name)))?;
+        let header: Vec<String> = stmt.column_names().iter().map(|c| csv_field(c)).collect();
+        write!(out, "{}\r\n", header.join(","))?;
+
+        let column_count = stmt.column_count();
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let values = (0..column_count)
+                .map(|i| row.get::<_, Value>(i).map(|value| csv_value(&value)))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            write!(out, "{}\r\n", values.join(","))?;
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// The select list for each of `tables`: `*` when no columns were chosen,
+/// otherwise the chosen columns the table has, in the order given. Tables
+/// with none of them are left out, and a column no table has is an error.
+fn select_lists(
+    conn: &Connection,
+    tables: &[String],
+    columns: &[String],
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    if columns.is_empty() {
+        return Ok(tables
+            .iter()
+            .map(|t| (t.clone(), String::from("*")))
+            .collect());
+    }
+    let mut lists = Vec::new();
+    let mut found = vec![false; columns.len()];
+    for table in tables {
+        let existing: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")?
+            .query_map([table], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut kept = Vec::new();
+        for (i, column) in columns.iter().enumerate() {
+            if existing.contains(column) {
+                found[i] = true;
+                kept.push(quote_identifier(column));
+            }
+        }
+        if !kept.is_empty() {
+            lists.push((table.clone(), kept.join(", ")));
+        }
+    }
+    let note = format!("not in any of the exported tables: {}", tables.join(", "));
+    unknown_columns(columns, &found, &note)?;
+    Ok(lists)
+}
+
+/// `result` with only `columns`, in that order, or all of it when empty.
+fn select_result_columns(
+    result: QueryResult,
+    columns: &[String],
+) -> Result<QueryResult, Box<dyn Error>> {
+    if columns.is_empty() {
+        return Ok(result);
+    }
+    let indexes: Vec<Option<usize>> = columns
+        .iter()
+        .map(|column| result.columns.iter().position(|c| c == column))
+        .collect();
+    let found: Vec<bool> = indexes.iter().map(Option::is_some).collect();
+    let note = format!("available: {}", result.columns.join(", "));
+    unknown_columns(columns, &found, &note)?;
+    let indexes: Vec<usize> = indexes.into_iter().flatten().collect();
+    Ok(QueryResult {
+        columns: indexes.iter().map(|&i| result.columns[i].clone()).collect(),
+        rows: result
+            .rows
+            .into_iter()
+            .map(|row| indexes.iter().map(|&i| row[i].clone()).collect())
+            .collect(),
+    })
+}
+
+fn unknown_columns(columns: &[String], found: &[bool], note: &str) -> Result<(), String> {
+    let unknown: Vec<&str> = columns
+        .iter()
+        .zip(found)
+        .filter(|(_, &found)| !found)
+        .map(|(column, _)| column.as_str())
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Unknown column(s) for --select-columns: {} ({})",
+            unknown.join(", "),
+            note
+        ))
+    }
+}
+
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => f.to_string(),
+        Value::Text(s) => csv_field(s),
+        Value::Blob(b) => b.iter().map(|byte| format!("{:02X}", byte)).collect(),
+    }
+}
+
+/// Quotes a field per RFC 4180 when it contains a separator, quote or newline.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_literal(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("NULL"),
+        Value::Integer(i) => i.to_string(),
+        // Debug formatting keeps the decimal point so the value stays REAL.
+        Value::Real(f) if f.is_finite() => format!("{:?}", f),
+        Value::Real(_) => String::from("NULL"),
+        Value::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        Value::Blob(b) => {
+            let hex: String = b.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("X'{}'", hex)
+        }
+    }
+}
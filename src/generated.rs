@@ -0,0 +1,75 @@
+use crate::glob::glob_match;
+
+/// Paths of lockfiles, minified bundles, build output and generated code,
+/// matched anywhere in the tree.
+const GENERATED_PATHS: &[&str] = &[
+    "*.lock",
+    "package-lock.json",
+    "*/package-lock.json",
+    "pnpm-lock.yaml",
+    "*/pnpm-lock.yaml",
+    "*.min.js",
+    "*.min.css",
+    "*.map",
+    "dist/*",
+    "*/dist/*",
+    "generated/*",
+    "*/generated/*",
+    "*.pb.go",
+    "*_pb2.py",
+    "*.pb.h",
+    "*.pb.cc",
+];
+
+/// Whether `path` looks like a file people don't write by hand.
+pub fn is_generated_path(path: &str) -> bool {
+    GENERATED_PATHS
+        .iter()
+        .any(|pattern| glob_match(pattern, path))
+}
+
+/// Whether the first line of `content` carries an `@generated` marker, as
+/// written by protoc, Buck and most code generators.
+pub fn has_generated_marker(content: &[u8]) -> bool {
+    let first_line = content.split(|&b| b == b'\n').next().unwrap_or_default();
+    first_line
+        .windows(b"@generated".len())
+        .any(|window| window == b"@generated")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_generated_paths() {
+        let cases = [
+            ("Cargo.lock", true),
+            ("web/yarn.lock", true),
+            ("web/package-lock.json", true),
+            ("static/app.min.js", true),
+            ("dist/app.js", true),
+            ("web/dist/index.html", true),
+            ("src/generated/api.rs", true),
+            ("proto/user.pb.go", true),
+            ("proto/user_pb2.py", true),
+            ("src/main.rs", false),
+            ("src/lock.rs", false),
+            ("distance/app.js", false),
+            ("docs/generated.md", false),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(is_generated_path(path), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn reads_the_marker_from_the_first_line_only() {
+        assert!(has_generated_marker(
+            b"// @generated by protoc\nfn main() {}"
+        ));
+        assert!(has_generated_marker(b"# @generated"));
+        assert!(!has_generated_marker(b"fn main() {}\n// @generated"));
+        assert!(!has_generated_marker(b""));
+    }
+}
@@ -0,0 +1,152 @@
+/// Matches `text` against a shell-style glob where `*` matches any run of
+/// characters (including `/`, so `**` is the same as `*`), `?` matches
+/// exactly one character and `[a-z_]` one character of a class, `[!...]`
+/// or `[^...]` one outside it. The whole text must match. A `[` without a
+/// closing `]` is literal.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = tokens(pattern);
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and the text index it is currently covering.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p].matches(text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == Token::Star {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, covered)) = backtrack {
+            p = star + 1;
+            t = covered + 1;
+            backtrack = Some((star, t));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|token| *token == Token::Star)
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(expected) => c == *expected,
+            Token::Any => true,
+            Token::Star => false,
+            Token::Class { ranges, negated } => {
+                ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+fn tokens(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let token = match chars[i] {
+            '*' => Token::Star,
+            '?' => Token::Any,
+            '[' => match class(&chars[i + 1..]) {
+                Some((token, length)) => {
+                    i += length;
+                    token
+                }
+                None => Token::Char('['),
+            },
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+        i += 1;
+    }
+    tokens
+}
+
+/// Reads a class after its `[`, returning it and how many characters it
+/// took through the `]`. A `]` first in the class is literal.
+fn class(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    let mut i = usize::from(negated);
+    let start = i;
+    let mut ranges = Vec::new();
+    while i < chars.len() {
+        match chars[i] {
+            ']' if i > start => return Some((Token::Class { ranges, negated }, i + 1)),
+            c if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&h| h != ']') => {
+                ranges.push((c, chars[i + 2]));
+                i += 3;
+                continue;
+            }
+            c => ranges.push((c, c)),
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn matches_glob_patterns() {
+        let cases = [
+            // Anchored at both ends.
+            ("src", "src", true),
+            ("src", "src/main.rs", false),
+            ("main.rs", "src/main.rs", false),
+            ("", "", true),
+            ("", "a", false),
+            // `*` spans any run, including none and slashes.
+            ("*", "", true),
+            ("*.rs", "main.rs", true),
+            ("*.rs", "src/lib/main.rs", true),
+            ("*.rs", "main.rs.bak", false),
+            ("src/*", "src/a/b.rs", true),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("refs/pull/*", "refs/pull/12/head", true),
+            ("refs/pull/*", "refs/heads/pull", false),
+            // `**` is the same as `*`.
+            ("src/**/*.rs", "src/a/b/c.rs", true),
+            ("**", "any/thing", true),
+            // `?` is exactly one character.
+            ("v?.0", "v1.0", true),
+            ("v?.0", "v10.0", false),
+            ("v?.0", "v.0", false),
+            // Classes.
+            ("v[0-9].*", "v1.2", true),
+            ("v[0-9].*", "vx.2", false),
+            ("[abc]", "b", true),
+            ("[!abc]", "b", false),
+            ("[^abc]", "d", true),
+            ("[]x]", "]", true),
+            ("[a-]", "-", true),
+            ("file[", "file[", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                glob_match(pattern, text),
+                expected,
+                "{} ~ {}",
+                pattern,
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use crate::cli::OutputFormat;
+use crate::query::{json_string, QueryResult};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+pub struct GraphOptions {
+    /// Keeps only merges, branch points, roots and tips, joining them through
+    /// the linear runs of commits in between.
+    pub merges_only: bool,
+    /// Limits the graph to commits reachable from this ref. Short names are
+    /// tried under refs/heads/, refs/tags/ and refs/remotes/.
+    pub ref_name: Option<String>,
+}
+
+/// The commit DAG, edges pointing from parent to child like the edge list.
+struct Graph {
+    /// Commit OID and summary, oldest first.
+    nodes: Vec<(String, String)>,
+    edges: Vec<(String, String)>,
+}
+
+pub fn export_graph(
+    conn: &Connection,
+    format: &OutputFormat,
+    out: Option<&str>,
+    options: &GraphOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer: Box<dyn Write> = match out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    write_graph(conn, format, &mut writer, options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_graph(
+    conn: &Connection,
+    format: &OutputFormat,
+    out: &mut dyn Write,
+    options: &GraphOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut graph = load_graph(conn, options.ref_name.as_deref())?;
+    if options.merges_only {
+        graph = collapse_to_merges(&graph);
+    }
+    match format {
+        OutputFormat::Dot => write_dot(&graph, out)?,
+        OutputFormat::GraphMl => write_graphml(&graph, out)?,
+        _ => return Err("export-graph only supports --format dot or graphml".into()),
+    }
+    Ok(())
+}
+
+fn load_graph(conn: &Connection, ref_name: Option<&str>) -> Result<Graph, Box<dyn Error>> {
+    // Commits shared by several imported repositories are one node.
+    let mut nodes: Vec<(String, String)> = conn
+        .prepare(
+            "SELECT id, substr(message, 1, instr(message || char(10), char(10)) - 1)
+             FROM commit_details
+             GROUP BY id
+             ORDER BY MIN(date), id",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut edges: Vec<(String, String)> = conn
+        .prepare(
+            "SELECT parent, child FROM commit_relation GROUP BY parent, child ORDER BY MIN(rowid)",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    // Parents outside a bounded walk have no commit_details row but still
+    // anchor their children.
+    let mut known: HashSet<String> = nodes.iter().map(|(id, _)| id.clone()).collect();
+    for (parent, _) in &edges {
+        if known.insert(parent.clone()) {
+            nodes.push((parent.clone(), String::new()));
+        }
+    }
+
+    if let Some(ref_name) = ref_name {
+        let reachable = reachable_from_ref(conn, ref_name)?;
+        if reachable.is_empty() {
+            return Err(format!("ref {} not found in the database", ref_name).into());
+        }
+        nodes.retain(|(id, _)| reachable.contains(id));
+        edges.retain(|(_, child)| reachable.contains(child));
+    }
+
+    Ok(Graph { nodes, edges })
+}
+
+/// Ancestors of the ref's commit, found through commit_relation. Annotated
+/// tags are peeled through the tags table.
+fn reachable_from_ref(conn: &Connection, ref_name: &str) -> rusqlite::Result<HashSet<String>> {
+    conn.prepare(
+        "WITH RECURSIVE reach(id) AS (
+             SELECT COALESCE(t.target_oid, r.resolved_oid, r.id)
+             FROM ref_details r
+             LEFT JOIN tags t ON t.repo_id = r.repo_id AND 'refs/tags/' || t.name = r.name
+             WHERE r.name IN (?1, 'refs/heads/' || ?1, 'refs/tags/' || ?1, 'refs/remotes/' || ?1)
+             UNION
+             SELECT c.parent FROM commit_relation c JOIN reach ON c.child = reach.id
+         )
+         SELECT id FROM reach",
+    )?
+    .query_map(params![ref_name], |row| row.get(0))?
+    .collect()
+}
+
+/// Drops every commit with exactly one parent and one child, and links each
+/// remaining commit to the nearest remaining ancestor on each of its parent
+/// lines. Branch points stay so the two sides of a merge remain distinct.
+fn collapse_to_merges(graph: &Graph) -> Graph {
+    let mut parents: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut children: HashMap<&str, usize> = HashMap::new();
+    for (parent, child) in &graph.edges {
+        parents.entry(child).or_default().push(parent);
+        *children.entry(parent).or_default() += 1;
+    }
+    let kept = |id: &str| {
+        parents.get(id).map_or(0, Vec::len) != 1 || children.get(id).copied().unwrap_or(0) != 1
+    };
+
+    // Both sides of a merge may lead to the same branch point; the parallel
+    // edges are kept so the merge still shows two parents.
+    let mut edges = Vec::new();
+    for (id, _) in graph.nodes.iter().filter(|(id, _)| kept(id)) {
+        for parent in parents.get(id.as_str()).into_iter().flatten() {
+            let mut ancestor = *parent;
+            while !kept(ancestor) {
+                ancestor = parents[ancestor][0];
+            }
+            edges.push((ancestor.to_string(), id.clone()));
+        }
+    }
+
+    Graph {
+        nodes: graph
+            .nodes
+            .iter()
+            .filter(|(id, _)| kept(id))
+            .cloned()
+            .collect(),
+        edges,
+    }
+}
+
+/// Node labels are the abbreviated OID and the summary line.
+fn node_label(id: &str, summary: &str) -> String {
+    let short = &id[..id.len().min(7)];
+    if summary.is_empty() {
+        short.to_string()
+    } else {
+        format!("{} {}", short, summary)
+    }
+}
+
+fn write_dot(graph: &Graph, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "digraph commits {{")?;
+    writeln!(out, "  node [shape=box];")?;
+    for (id, summary) in &graph.nodes {
+        writeln!(
+            out,
+            "  \"{}\" [label=\"{}\"];",
+            id,
+            dot_escape(&node_label(id, summary))
+        )?;
+    }
+    for (parent, child) in &graph.edges {
+        writeln!(out, "  \"{}\" -> \"{}\";", parent, child)?;
+    }
+    writeln!(out, "}}")
+}
+
+fn write_graphml(graph: &Graph, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        out,
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+    )?;
+    writeln!(
+        out,
+        "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>"
+    )?;
+    writeln!(out, "  <graph id=\"commits\" edgedefault=\"directed\">")?;
+    for (id, summary) in &graph.nodes {
+        writeln!(
+            out,
+            "    <node id=\"{}\"><data key=\"label\">{}</data></node>",
+            id,
+            xml_escape(&node_label(id, summary))
+        )?;
+    }
+    for (parent, child) in &graph.edges {
+        writeln!(
+            out,
+            "    <edge source=\"{}\" target=\"{}\"/>",
+            parent, child
+        )?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")
+}
+
+/// Prints the author-network report's (author, coauthor, shared_files)
+/// pairs as a JSON adjacency list, every author mapped to the others they
+/// share files with, most shared first; or as an undirected DOT graph whose
+/// edges are weighted by shared files.
+pub fn print_author_network(result: &QueryResult, format: &OutputFormat) {
+    let text = |value: &Value| match value {
+        Value::Text(s) => s.clone(),
+        _ => String::new(),
+    };
+    let pairs: Vec<(String, String, i64)> = result
+        .rows
+        .iter()
+        .map(|row| {
+            let shared = match row[2] {
+                Value::Integer(n) => n,
+                _ => 0,
+            };
+            (text(&row[0]), text(&row[1]), shared)
+        })
+        .collect();
+
+    if let OutputFormat::Dot = format {
+        println!("graph authors {{");
+        for (author, coauthor, shared) in &pairs {
+            println!(
+                "  \"{}\" -- \"{}\" [weight={}, label=\"{}\"];",
+                dot_escape(author),
+                dot_escape(coauthor),
+                shared,
+                shared
+            );
+        }
+        println!("}}");
+        return;
+    }
+
+    // Rows come most shared first, so each list keeps that order.
+    let mut adjacency: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (author, coauthor, shared) in &pairs {
+        for (from, to) in [(author, coauthor), (coauthor, author)] {
+            adjacency.entry(from).or_default().push(format!(
+                "{{\"author\":{},\"shared_files\":{}}}",
+                json_string(to),
+                shared
+            ));
+        }
+    }
+    let entries: Vec<String> = adjacency
+        .iter()
+        .map(|(author, neighbours)| format!("{}:[{}]", json_string(author), neighbours.join(",")))
+        .collect();
+    println!("{{{}}}", entries.join(","));
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Control characters other than tab aren't allowed in XML 1.0 at all.
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c if (c as u32) < 0x20 && c != '\t' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,250 @@
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// How long a request may wait on the server before giving up.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Header names and values in the order they were sent.
+pub type Headers = Vec<(String, String)>;
+
+/// Sends `body` as JSON to a plain `http://` URL and returns the response
+/// body. Made for local model servers; there is no TLS, so an HTTPS API has
+/// to be reached through a local proxy.
+pub fn post_json(url: &str, body: &str, bearer: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let authorization = bearer.map(|token| format!("Bearer {}", token));
+    let headers: Vec<(&str, &str)> = authorization
+        .iter()
+        .map(|value| ("Authorization", value.as_str()))
+        .collect();
+    request("POST", url, Some(body), &headers)
+}
+
+/// GETs a JSON document from a plain `http://` URL, with extra request
+/// headers such as credentials.
+pub fn get_json(url: &str, headers: &[(&str, &str)]) -> Result<String, Box<dyn Error>> {
+    request("GET", url, None, headers)
+}
+
+fn request(
+    method: &str,
+    url: &str,
+    body: Option<&str>,
+    headers: &[(&str, &str)],
+) -> Result<String, Box<dyn Error>> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None if url.starts_with("https://") => {
+            return Err(
+                "https URLs aren't supported; point at a local http endpoint or proxy".into(),
+            )
+        }
+        None => return Err(format!("not an http:// URL: {}", url).into()),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut stream = TcpStream::connect(&address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    // Some APIs, GitHub's among them, refuse requests without a User-Agent.
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: git_info_llama\r\nAccept: application/json\r\nConnection: close\r\n",
+        method, path, authority
+    );
+    if let Some(body) = body {
+        request.push_str(&format!(
+            "Content-Type: application/json\r\nContent-Length: {}\r\n",
+            body.len()
+        ));
+    }
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body.unwrap_or("").as_bytes())?;
+
+    let mut reader = BufReader::new(stream);
+    let (status_line, headers) = read_head(&mut reader)?;
+    let status: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0);
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let mut response = Vec::new();
+    if header("Transfer-Encoding").is_some_and(|te| te.eq_ignore_ascii_case("chunked")) {
+        read_chunked(&mut reader, &mut response)?;
+    } else if let Some(length) = header("Content-Length").and_then(|l| l.parse().ok()) {
+        response.resize(length, 0);
+        reader.read_exact(&mut response)?;
+    } else {
+        reader.read_to_end(&mut response)?;
+    }
+    let response = String::from_utf8_lossy(&response).into_owned();
+    if !(200..300).contains(&status) {
+        return Err(format!("{} returned HTTP {}: {}", url, status, response.trim()).into());
+    }
+    Ok(response)
+}
+
+/// Reads the start line (a request or status line) and the headers of a
+/// request or response.
+pub fn read_head(reader: &mut impl BufRead) -> Result<(String, Headers), Box<dyn Error>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err("connection closed before the headers".into());
+    }
+    let start_line = line.trim_end().to_string();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = trimmed.split_once(':') {
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    Ok((start_line, headers))
+}
+
+fn read_chunked(reader: &mut impl BufRead, out: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = usize::from_str_radix(line.trim().split(';').next().unwrap_or(""), 16)
+            .map_err(|_| format!("invalid chunk size: {}", line.trim()))?;
+        if size == 0 {
+            return Ok(());
+        }
+        let start = out.len();
+        out.resize(start + size, 0);
+        reader.read_exact(&mut out[start..])?;
+        // The CRLF after each chunk.
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves `response` to one connection on a free local port, handing
+    /// back the URL and a handle yielding the raw request it received.
+    fn serve_once(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v1?x=1", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let (start_line, headers) = read_head(&mut reader).unwrap();
+            let mut request = start_line;
+            for (name, value) in &headers {
+                request.push_str(&format!("\n{}: {}", name, value));
+            }
+            let length = headers
+                .iter()
+                .find(|(name, _)| name == "Content-Length")
+                .map_or(0, |(_, value)| value.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str("\n\n");
+            request.push_str(&String::from_utf8(body).unwrap());
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn reads_content_length_responses() {
+        let (url, server) = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\ncontent-length: 7\r\n\r\n{\"a\":1}",
+        );
+        let response = post_json(&url, "{\"q\":2}", Some("secret")).unwrap();
+        assert_eq!(response, "{\"a\":1}");
+
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("POST /api/v1?x=1 HTTP/1.1\n"),
+            "{}",
+            request
+        );
+        assert!(request.contains("\nAuthorization: Bearer secret"));
+        assert!(request.contains("\nContent-Length: 7"));
+        assert!(request.ends_with("\n\n{\"q\":2}"));
+    }
+
+    #[test]
+    fn reads_chunked_responses() {
+        let (url, server) = serve_once(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: Chunked\r\n\r\n\
+             4\r\n{\"a\"\r\n3;ext=1\r\n:12\r\n1\r\n}\r\n0\r\n\r\n",
+        );
+        let response = get_json(&url, &[("X-Token", "t")]).unwrap();
+        assert_eq!(response, "{\"a\":12}");
+
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("GET /api/v1?x=1 HTTP/1.1\n"),
+            "{}",
+            request
+        );
+        assert!(request.contains("\nX-Token: t"));
+        assert!(!request.contains("Content-Length"));
+    }
+
+    #[test]
+    fn reads_until_close_without_a_length() {
+        let (url, server) = serve_once("HTTP/1.1 200 OK\r\n\r\n[1,2]");
+        assert_eq!(get_json(&url, &[]).unwrap(), "[1,2]");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reports_error_statuses_with_the_body() {
+        let (url, server) =
+            serve_once("HTTP/1.1 404 Not Found\r\nContent-Length: 10\r\n\r\nno such id");
+        let error = get_json(&url, &[]).unwrap_err().to_string();
+        assert_eq!(error, format!("{} returned HTTP 404: no such id", url));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        let error = get_json("https://example.com/", &[]).unwrap_err();
+        assert!(error.to_string().starts_with("https URLs aren't supported"));
+        let error = get_json("ftp://example.com/", &[]).unwrap_err();
+        assert_eq!(error.to_string(), "not an http:// URL: ftp://example.com/");
+    }
+
+    #[test]
+    fn rejects_invalid_chunk_sizes() {
+        let (url, server) =
+            serve_once("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n");
+        let error = get_json(&url, &[]).unwrap_err();
+        assert_eq!(error.to_string(), "invalid chunk size: zz");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,348 @@
+use std::fmt::Write;
+
+/// A parsed JSON document. Objects keep their keys in document order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != text.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// The member `key` of an object, None for anything else.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Serializes without insignificant whitespace.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Number(n) if n.is_finite() => write!(out, "{}", n).unwrap(),
+            Json::Number(_) => out.push_str("null"),
+            Json::String(s) => out.push_str(&crate::query::json_string(s)),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(members) => {
+                out.push('{');
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&crate::query::json_string(key));
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("invalid JSON at byte {}: {}", self.pos, message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .text
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.text.get(self.pos) {
+            None => Err(self.error("unexpected end")),
+            Some(b'n') => self.expect("null", Json::Null),
+            Some(b't') => self.expect("true", Json::Bool(true)),
+            Some(b'f') => self.expect("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return Err(self.error("expected , or ]")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.text.get(self.pos) != Some(&b'"') {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.text.get(self.pos) != Some(&b':') {
+                        return Err(self.error("expected :"));
+                    }
+                    self.pos += 1;
+                    members.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(members));
+                        }
+                        _ => return Err(self.error("expected , or }")),
+                    }
+                }
+            }
+            Some(_) => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self
+            .text
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_digit() || b"+-.eE".contains(c))
+        {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.pos])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .map(Json::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            match self.text.get(self.pos) {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(out).map_err(|_| self.error("invalid UTF-8"));
+                }
+                Some(b'\\') => {
+                    let escaped = match self.text.get(self.pos + 1) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let (c, len) = self.unicode_escape()?;
+                            let mut buf = [0; 4];
+                            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                            self.pos += len;
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    out.push(escaped as u8);
+                    self.pos += 2;
+                }
+                Some(&c) => {
+                    out.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// Decodes `\uXXXX`, joining surrogate pairs; returns the character and
+    /// the bytes consumed.
+    fn unicode_escape(&self) -> Result<(char, usize), String> {
+        let hex = |at: usize| {
+            self.text
+                .get(at..at + 4)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                .ok_or_else(|| self.error("invalid \\u escape"))
+        };
+        let first = hex(self.pos + 2)?;
+        let pair_follows = self
+            .text
+            .get(self.pos + 6..)
+            .is_some_and(|rest| rest.starts_with(b"\\u"));
+        if (0xD800..0xDC00).contains(&first) && pair_follows {
+            let second = hex(self.pos + 8)?;
+            let code = 0x10000 + ((first - 0xD800) << 10) + (second.wrapping_sub(0xDC00) & 0x3FF);
+            return Ok((char::from_u32(code).unwrap_or('\u{FFFD}'), 12));
+        }
+        Ok((char::from_u32(first).unwrap_or('\u{FFFD}'), 6))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+
+    #[test]
+    fn round_trips_documents() {
+        let cases = [
+            "null",
+            "true",
+            "[]",
+            "{}",
+            "-12.5",
+            "[1,\"two\",[false,null],{\"a\":{}}]",
+            "{\"b\":1,\"a\":2,\"nested\":{\"list\":[0.25,-3]}}",
+        ];
+        for text in cases {
+            assert_eq!(Json::parse(text).unwrap().to_json(), text);
+        }
+        // Whitespace is dropped and number spellings normalised.
+        let parsed = Json::parse(" { \"n\" : 1.0 , \"e\" : 2E2 } ").unwrap();
+        assert_eq!(parsed.to_json(), "{\"n\":1,\"e\":200}");
+        assert_eq!(parsed.get("e").and_then(Json::as_f64), Some(200.0));
+        assert_eq!(parsed.get("missing"), None);
+    }
+
+    #[test]
+    fn decodes_and_escapes_strings() {
+        let cases = [
+            (r#""plain""#, "plain", r#""plain""#),
+            (
+                r#""quote \" slash \\ \/""#,
+                "quote \" slash \\ /",
+                r#""quote \" slash \\ /""#,
+            ),
+            (r#""a\nb\tc\rd""#, "a\nb\tc\rd", r#""a\nb\tc\rd""#),
+            (r#""\b\f""#, "\u{8}\u{c}", r#""\u0008\u000c""#),
+            (r#""\u00e9\u4e2d""#, "é中", "\"é中\""),
+            (r#""\ud83d\ude00""#, "😀", "\"😀\""),
+            ("\"raw é\"", "raw é", "\"raw é\""),
+        ];
+        for (text, decoded, encoded) in cases {
+            let value = Json::parse(text).unwrap();
+            assert_eq!(value.as_str(), Some(decoded), "{}", text);
+            assert_eq!(value.to_json(), encoded, "{}", text);
+            assert_eq!(Json::parse(encoded).unwrap(), value, "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        let cases = [
+            ("", "invalid JSON at byte 0: unexpected end"),
+            ("nul", "invalid JSON at byte 0: unexpected character"),
+            ("[1 2]", "invalid JSON at byte 3: expected , or ]"),
+            ("{\"a\" 1}", "invalid JSON at byte 5: expected :"),
+            ("{1:2}", "invalid JSON at byte 1: expected a key"),
+            ("{\"a\":1,}", "invalid JSON at byte 7: expected a key"),
+            ("\"open", "invalid JSON at byte 5: unterminated string"),
+            ("\"\\x\"", "invalid JSON at byte 1: invalid escape"),
+            ("\"\\u12\"", "invalid JSON at byte 1: invalid \\u escape"),
+            ("1.2.3", "invalid JSON at byte 5: invalid number"),
+            ("true false", "invalid JSON at byte 5: trailing characters"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(
+                Json::parse(text).err().as_deref(),
+                Some(expected),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn writes_non_finite_numbers_as_null() {
+        assert_eq!(Json::Number(f64::NAN).to_json(), "null");
+        assert_eq!(Json::Number(f64::INFINITY).to_json(), "null");
+    }
+}
@@ -0,0 +1,209 @@
+/// Files recognised by their whole name, checked before extensions.
+const FILE_NAMES: &[(&str, &str)] = &[
+    ("Makefile", "Makefile"),
+    ("GNUmakefile", "Makefile"),
+    ("Dockerfile", "Dockerfile"),
+    ("CMakeLists.txt", "CMake"),
+    ("Cargo.lock", "TOML"),
+    ("Gemfile", "Ruby"),
+    ("Rakefile", "Ruby"),
+    ("Jenkinsfile", "Groovy"),
+    ("BUILD", "Starlark"),
+    ("WORKSPACE", "Starlark"),
+    ("LICENSE", "Text"),
+    ("COPYING", "Text"),
+    (".gitignore", "Ignore List"),
+    (".gitattributes", "Git Attributes"),
+    (".gitmodules", "Git Config"),
+    (".mailmap", "Text"),
+];
+
+/// Lowercased extensions, without the dot.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "Rust"),
+    ("c", "C"),
+    ("h", "C"),
+    ("cc", "C++"),
+    ("cpp", "C++"),
+    ("cxx", "C++"),
+    ("hh", "C++"),
+    ("hpp", "C++"),
+    ("hxx", "C++"),
+    ("cs", "C#"),
+    ("go", "Go"),
+    ("java", "Java"),
+    ("kt", "Kotlin"),
+    ("kts", "Kotlin"),
+    ("scala", "Scala"),
+    ("groovy", "Groovy"),
+    ("gradle", "Groovy"),
+    ("swift", "Swift"),
+    ("m", "Objective-C"),
+    ("mm", "Objective-C++"),
+    ("py", "Python"),
+    ("pyi", "Python"),
+    ("rb", "Ruby"),
+    ("php", "PHP"),
+    ("pl", "Perl"),
+    ("pm", "Perl"),
+    ("lua", "Lua"),
+    ("r", "R"),
+    ("jl", "Julia"),
+    ("hs", "Haskell"),
+    ("ml", "OCaml"),
+    ("mli", "OCaml"),
+    ("ex", "Elixir"),
+    ("exs", "Elixir"),
+    ("erl", "Erlang"),
+    ("clj", "Clojure"),
+    ("dart", "Dart"),
+    ("zig", "Zig"),
+    ("nim", "Nim"),
+    ("js", "JavaScript"),
+    ("mjs", "JavaScript"),
+    ("cjs", "JavaScript"),
+    ("jsx", "JavaScript"),
+    ("ts", "TypeScript"),
+    ("tsx", "TypeScript"),
+    ("vue", "Vue"),
+    ("svelte", "Svelte"),
+    ("html", "HTML"),
+    ("htm", "HTML"),
+    ("css", "CSS"),
+    ("scss", "SCSS"),
+    ("sass", "Sass"),
+    ("less", "Less"),
+    ("sh", "Shell"),
+    ("bash", "Shell"),
+    ("zsh", "Shell"),
+    ("fish", "Shell"),
+    ("ps1", "PowerShell"),
+    ("bat", "Batchfile"),
+    ("cmd", "Batchfile"),
+    ("sql", "SQL"),
+    ("proto", "Protocol Buffers"),
+    ("graphql", "GraphQL"),
+    ("tf", "HCL"),
+    ("hcl", "HCL"),
+    ("nix", "Nix"),
+    ("cmake", "CMake"),
+    ("mk", "Makefile"),
+    ("bzl", "Starlark"),
+    ("json", "JSON"),
+    ("toml", "TOML"),
+    ("yaml", "YAML"),
+    ("yml", "YAML"),
+    ("xml", "XML"),
+    ("ini", "INI"),
+    ("cfg", "INI"),
+    ("csv", "CSV"),
+    ("md", "Markdown"),
+    ("markdown", "Markdown"),
+    ("rst", "reStructuredText"),
+    ("adoc", "AsciiDoc"),
+    ("tex", "TeX"),
+    ("txt", "Text"),
+    ("ipynb", "Jupyter Notebook"),
+    ("svg", "SVG"),
+    ("png", "Image"),
+    ("jpg", "Image"),
+    ("jpeg", "Image"),
+    ("gif", "Image"),
+    ("ico", "Image"),
+    ("webp", "Image"),
+];
+
+/// The language of a file judged by its name alone, like GitHub's linguist
+/// does for most files: a known file name first, then the extension. None
+/// when neither is recognised.
+pub fn language(path: &str) -> Option<&'static str> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    if let Some((_, language)) = FILE_NAMES.iter().find(|(file, _)| *file == name) {
+        return Some(language);
+    }
+    if name.starts_with("Dockerfile.") {
+        return Some("Dockerfile");
+    }
+    let (stem, extension) = name.rsplit_once('.')?;
+    if stem.is_empty() {
+        return None;
+    }
+    let extension = extension.to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, language)| *language)
+}
+
+/// Languages of data, documentation and assets rather than code.
+pub const NOT_CODE: &[&str] = &[
+    "JSON",
+    "TOML",
+    "YAML",
+    "XML",
+    "INI",
+    "CSV",
+    "Markdown",
+    "reStructuredText",
+    "AsciiDoc",
+    "TeX",
+    "Text",
+    "SVG",
+    "Image",
+    "Ignore List",
+    "Git Attributes",
+    "Git Config",
+];
+
+/// Whether `path` is a test by the usual conventions: under a `test`,
+/// `tests`, `spec` or `__tests__` directory, or named like `test_x.py`,
+/// `x_test.go`, `x.test.ts`, `x.spec.js`, `XTest.java` or `x_spec.rb`.
+pub fn is_test_path(path: &str) -> bool {
+    let mut parts: Vec<&str> = path.split('/').collect();
+    let name = parts.pop().unwrap_or(path);
+    if parts.iter().any(|dir| {
+        matches!(
+            *dir,
+            "test" | "tests" | "testing" | "spec" | "specs" | "__tests__"
+        )
+    }) {
+        return true;
+    }
+    if name.contains(".test.") || name.contains(".spec.") {
+        return true;
+    }
+    let stem = name.split('.').next().unwrap_or(name);
+    matches!(stem, "test" | "tests" | "conftest")
+        || stem.starts_with("test_")
+        || ["_test", "_tests", "_spec", "Test", "Tests"]
+            .iter()
+            .any(|suffix| stem.ends_with(suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_test_paths() {
+        let cases = [
+            ("tests/import.rs", true),
+            ("crates/core/tests/common/mod.rs", true),
+            ("web/__tests__/app.js", true),
+            ("spec/models/user_spec.rb", true),
+            ("pkg/server_test.go", true),
+            ("test_parser.py", true),
+            ("src/conftest.py", true),
+            ("src/app.test.ts", true),
+            ("src/app.spec.js", true),
+            ("src/main/java/FooTest.java", true),
+            ("src/main.rs", false),
+            ("src/latest.rs", false),
+            ("src/contest/rules.py", false),
+            ("docs/testing.md", false),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(is_test_path(path), expected, "{}", path);
+        }
+    }
+}
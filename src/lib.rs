@@ -0,0 +1,2954 @@
+//! Indexes a git repository's commits and refs into SQLite.
+//!
+//! [`ingest_commits`] and [`ingest_refs`] cover the common case; the CLI
+//! builds on the lower-level functions for its flags.
+
+extern crate git2;
+extern crate rusqlite;
+
+mod ansi;
+pub mod classify;
+pub mod cli;
+pub mod clone;
+pub mod config;
+pub mod divergence;
+pub mod embed;
+pub mod enrich;
+pub mod error;
+pub mod export;
+mod generated;
+mod glob;
+pub mod graph;
+mod http;
+pub mod json;
+pub mod language;
+pub mod loc;
+mod logfmt;
+pub mod logging;
+pub mod mcp;
+pub mod message;
+mod pattern;
+pub mod progress;
+pub mod prune;
+pub mod query;
+pub mod schema;
+pub mod serve;
+mod signature;
+
+use classify::{IssuePatterns, TypeRules};
+use error::IngestResult;
+use git2::{
+    BlameOptions, Commit, Delta, Diff, DiffDelta, DiffFindOptions, DiffFormat, DiffLine, Mailmap,
+    ObjectType, Oid, Patch, Reference, Repository, RepositoryState, Revwalk, Signature, Status,
+    StatusOptions, Time, Tree, TreeWalkMode, TreeWalkResult,
+};
+use progress::ProgressSink;
+use rusqlite::types::{ToSql, Type};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Walks every commit reachable from HEAD that is not stored yet into
+/// `commit_details` and `commit_relation`, using default options.
+pub fn ingest_commits(repo: &Repository, conn: &mut Connection) -> IngestResult<WalkCounts> {
+    let repo_id = register_repository(conn, &repository_name(repo), repo.path())?;
+    let tip = WalkTip::head(repo)?;
+    let options = ImportOptions::new(repo_id, tip);
+    let counts = get_commits_detail_array(conn, repo, &options, None, None)?;
+    link_reverted_commits(conn, repo_id)?;
+    rank_commit_impact(conn, repo_id, ImpactWeights::default(), counts.added)?;
+    Ok(counts)
+}
+
+/// Replaces `ref_details` with every ref currently in the repository.
+pub fn ingest_refs(repo: &Repository, conn: &mut Connection) -> IngestResult<WalkCounts> {
+    let repo_id = register_repository(conn, &repository_name(repo), repo.path())?;
+    refresh_ref_details(
+        conn,
+        repo,
+        repo_id,
+        &RefFilter::default(),
+        FailurePolicy::default(),
+        OnConflict::default(),
+        None,
+    )
+}
+
+/// Borrows objects from `--alternates` paths and `GIT_ALTERNATE_OBJECT_DIRECTORIES`,
+/// which libgit2 does not read on its own.
+pub fn add_alternates(repo: &Repository, alternates: &[String]) -> IngestResult<()> {
+    let mut paths: Vec<PathBuf> = alternates.iter().map(PathBuf::from).collect();
+    if let Some(value) = env::var_os("GIT_ALTERNATE_OBJECT_DIRECTORIES") {
+        paths.extend(env::split_paths(&value));
+    }
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let odb = repo.odb()?;
+    for path in paths {
+        if !path.is_dir() {
+            warn!(
+                "alternate object directory {} not found, skipping.",
+                path.display()
+            );
+            continue;
+        }
+        let added = path
+            .to_str()
+            .ok_or_else(|| git2::Error::from_str("path is not valid UTF-8"))
+            .and_then(|p| odb.add_disk_alternate(p));
+        if let Err(e) = added {
+            warn!("failed to add alternate {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// libgit2 reads `objects/info/commit-graph` on its own when `core.commitGraph`
+/// is enabled; git2 has no API for it, so this only reports whether it applies.
+pub fn report_commit_graph(repo: &Repository) {
+    let info = repo.path().join("objects").join("info");
+    let present = info.join("commit-graph").is_file()
+        || info
+            .join("commit-graphs")
+            .join("commit-graph-chain")
+            .is_file();
+    if !present {
+        return;
+    }
+
+    let enabled = repo
+        .config()
+        .and_then(|config| config.get_bool("core.commitGraph"))
+        .unwrap_or(true);
+    if enabled {
+        info!("Using commit-graph file to speed up the walk.");
+    } else {
+        info!("Commit-graph file found but core.commitGraph is disabled, ignoring it.");
+    }
+}
+
+#[derive(Default)]
+pub struct WalkCounts {
+    pub added: usize,
+    pub skipped: usize,
+    /// Commit walks only: everything up to the tip is stored and sync_state
+    /// records it. False after a bounded walk or one stopped by
+    /// `--time-budget-secs` or `--max-db-size-mb`, which the next run resumes.
+    pub complete: bool,
+}
+
+pub struct ImportRun {
+    pub repo_id: i64,
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub commits_added: usize,
+    pub refs_added: usize,
+    pub skipped: usize,
+    pub args: Vec<String>,
+    pub state: RepoState,
+    /// Nothing reachable from the walked tip was left unimported.
+    pub complete: bool,
+}
+
+/// What the repository looked like when an import started.
+pub struct RepoState {
+    /// The branch HEAD is on, None when detached or unborn.
+    pub head_ref: Option<String>,
+    pub head_oid: Option<String>,
+    pub head_detached: bool,
+    /// clean, or the operation in progress: merge, rebase, cherry-pick, ...
+    pub operation: &'static str,
+    /// Worktree counts, None for bare repositories.
+    pub staged_files: Option<usize>,
+    pub modified_files: Option<usize>,
+    pub untracked_files: Option<usize>,
+}
+
+/// The ref the commit walk starts from: `--branch`, or HEAD by default.
+pub struct WalkTip {
+    pub ref_name: String,
+    pub oid: Oid,
+}
+
+impl WalkTip {
+    pub fn head(repo: &Repository) -> std::result::Result<WalkTip, git2::Error> {
+        let head = repo.head()?;
+        Ok(WalkTip {
+            ref_name: head.name().unwrap_or("HEAD").to_string(),
+            oid: head.peel_to_commit()?.id(),
+        })
+    }
+}
+
+/// What to do with a commit or ref that fails to extract or insert.
+#[derive(Clone, Copy, Default)]
+pub struct FailurePolicy {
+    /// Counts failures instead of printing one line per failed item.
+    pub quiet: bool,
+    /// Records failures in `ingest_errors` and moves on instead of aborting.
+    pub keep_going: bool,
+}
+
+impl FailurePolicy {
+    /// Handles one failed item: reports it and, with `keep_going`, records it
+    /// so the run can continue. Without `keep_going` the caller aborts.
+    fn skip(
+        &self,
+        conn: &Connection,
+        kind: &str,
+        object_id: Option<&str>,
+        error: &dyn std::fmt::Display,
+    ) -> Result<()> {
+        if !self.quiet {
+            match object_id {
+                Some(id) => warn!("failed to process {} {}: {}", kind, id, error),
+                None => warn!("failed to process {}: {}", kind, error),
+            }
+        }
+        if self.keep_going {
+            conn.execute(
+                "INSERT INTO ingest_errors (kind, object_id, message, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![kind, object_id, error.to_string(), unix_now()],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// How inserts treat rows that are already stored.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum OnConflict {
+    /// Keeps the stored row, so re-running an import is a no-op.
+    #[default]
+    Ignore,
+    /// Overwrites the stored row, e.g. to re-classify commits after
+    /// `--type-keywords` changed.
+    Update,
+}
+
+impl OnConflict {
+    /// Builds the INSERT for `table`, resolving clashes on the `key` columns
+    /// according to the policy.
+    fn insert_sql(self, table: &str, columns: &[&str], key: &[&str]) -> String {
+        let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+        let updates: Vec<String> = columns
+            .iter()
+            .filter(|column| !key.contains(column))
+            .map(|column| format!("{} = excluded.{}", column, column))
+            .collect();
+        let action = if self == OnConflict::Update && !updates.is_empty() {
+            format!("DO UPDATE SET {}", updates.join(", "))
+        } else {
+            String::from("DO NOTHING")
+        };
+        format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({}) {}",
+            table,
+            columns.join(", "),
+            placeholders.join(", "),
+            key.join(", "),
+            action
+        )
+    }
+}
+
+/// Date and author restrictions applied to each commit during the walk.
+#[derive(Clone, Default)]
+pub struct CommitFilter {
+    /// Oldest commit time (UNIX seconds) to import, inclusive.
+    pub since: Option<i64>,
+    /// Newest commit time (UNIX seconds) to import, exclusive.
+    pub until: Option<i64>,
+    /// Case-insensitive substring of the author's name or email.
+    pub author: Option<String>,
+    /// Globs a commit must touch, like `git log -- <path>`. Also limits the
+    /// stored diffs to matching files.
+    pub paths: Vec<String>,
+}
+
+impl CommitFilter {
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none()
+            && self.until.is_none()
+            && self.author.is_none()
+            && self.paths.is_empty()
+    }
+
+    pub fn matches_path(&self, path: &str) -> bool {
+        self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|pattern| glob::glob_match(pattern, path))
+    }
+
+    /// Uses the commit time, like `commit_details.date` and `git log --since`.
+    /// A commit whose diffs can't be computed is kept so extracting it
+    /// reports the error.
+    pub fn matches(&self, repo: &Repository, commit: &Commit) -> bool {
+        let time = commit.time().seconds();
+        if self.since.is_some_and(|since| time < since)
+            || self.until.is_some_and(|until| time >= until)
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.author {
+            let author = commit.author();
+            let pattern = pattern.to_lowercase();
+            let found = [author.name_bytes(), author.email_bytes()]
+                .iter()
+                .any(|field| {
+                    String::from_utf8_lossy(field)
+                        .to_lowercase()
+                        .contains(&pattern)
+                });
+            if !found {
+                return false;
+            }
+        }
+        self.paths.is_empty() || self.touches_paths(repo, commit).unwrap_or(true)
+    }
+
+    /// Like git's history simplification, a merge only counts when it
+    /// differs from every parent in a matching path.
+    fn touches_paths(
+        &self,
+        repo: &Repository,
+        commit: &Commit,
+    ) -> std::result::Result<bool, git2::Error> {
+        let tree = commit.tree()?;
+        let parent_trees = if commit.parent_count() == 0 {
+            vec![None]
+        } else {
+            commit
+                .parents()
+                .map(|parent| parent.tree().map(Some))
+                .collect::<std::result::Result<Vec<_>, _>>()?
+        };
+        for parent_tree in parent_trees {
+            let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+            if !diff.deltas().any(|delta| self.matches_delta(&delta)) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// A rename matches on either side.
+    fn matches_delta(&self, delta: &DiffDelta) -> bool {
+        [delta.new_file().path(), delta.old_file().path()]
+            .iter()
+            .flatten()
+            .any(|path| self.matches_path(&path.to_string_lossy()))
+    }
+}
+
+pub struct ImportOptions {
+    /// `repositories` row every inserted row is scoped to.
+    pub repo_id: i64,
+    pub tip: WalkTip,
+    pub type_rules: TypeRules,
+    pub issue_patterns: IssuePatterns,
+    pub time_budget: Option<Duration>,
+    pub strip_ansi: bool,
+    pub max_db_size_mb: Option<u64>,
+    pub diffs_dir: Option<PathBuf>,
+    pub with_patches: bool,
+    /// Flags lockfiles, build output and other generated files by path.
+    pub detect_generated: bool,
+    /// Also flags files whose first line has an `@generated` marker, which
+    /// means reading every changed blob.
+    pub generated_markers: bool,
+    /// Commits reachable from here (the --since-tag commit) are not walked.
+    pub since_commit: Option<Oid>,
+    pub max_walk_depth: Option<usize>,
+    /// Commits outside the filter are passed over by the walk.
+    pub filter: CommitFilter,
+    pub failures: FailurePolicy,
+    pub on_conflict: OnConflict,
+    /// Walks at most this many new commits.
+    pub limit: Option<usize>,
+    /// Snapshots the tree of every Nth walked commit, starting with the tip.
+    pub tree_every: Option<usize>,
+    /// Threads extracting commits; inserts always happen on the caller's thread.
+    pub jobs: usize,
+    /// Alternate object directories the worker threads' repository handles
+    /// need, as passed to `add_alternates` for the main one.
+    pub alternates: Vec<String>,
+}
+
+impl ImportOptions {
+    /// Options for a plain walk from `tip` with every optional feature off.
+    pub fn new(repo_id: i64, tip: WalkTip) -> Self {
+        ImportOptions {
+            repo_id,
+            tip,
+            type_rules: TypeRules::default(),
+            issue_patterns: IssuePatterns::default(),
+            time_budget: None,
+            strip_ansi: false,
+            max_db_size_mb: None,
+            diffs_dir: None,
+            with_patches: false,
+            detect_generated: false,
+            generated_markers: false,
+            since_commit: None,
+            max_walk_depth: None,
+            filter: CommitFilter::default(),
+            failures: FailurePolicy::default(),
+            on_conflict: OnConflict::default(),
+            limit: None,
+            tree_every: None,
+            jobs: 1,
+            alternates: Vec::new(),
+        }
+    }
+}
+
+pub struct CommitDetails {
+    pub id: String,
+    pub author: String,
+    pub date: i64, // UNIX timestamp for simplicity, but can use a more detailed type if desired.
+    pub message: String,
+    pub author_is_committer: bool,
+    pub classified_type: String,
+    pub reverts_oid: Option<String>,
+    pub log_author: String,
+    pub log_date: String,
+    pub subject_slug: String,
+    pub author_email: String,
+    pub committer_name: String,
+    pub committer_email: String,
+    pub author_time: i64,
+    /// Minutes east of UTC, as git stores it.
+    pub author_tz_offset: i32,
+    pub commit_time: i64,
+    pub commit_tz_offset: i32,
+    pub parsed: message::ParsedMessage,
+    pub patch_path: Option<String>,
+    pub files: Vec<FileChange>,
+    /// Full tree listing, only filled for commits picked by `tree_every`.
+    pub tree: Vec<TreeEntry>,
+    pub parents: Vec<Oid>,
+    /// From the gpgsig header, None for unsigned commits.
+    pub signature: Option<signature::Signature>,
+}
+
+pub struct FileChange {
+    pub path: String,
+    /// Set only when the file moved, i.e. for renames and copies.
+    pub old_path: Option<String>,
+    pub change_type: String,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// Unified diff text, only collected with `--with-patches`.
+    pub patch: Option<String>,
+    /// Whether the file looks generated, only checked with `--detect-generated`.
+    pub is_generated: Option<bool>,
+    /// Whether git treats either side of the change as binary.
+    pub is_binary: bool,
+}
+pub struct TreeEntry {
+    pub path: String,
+    pub blob_oid: String,
+    pub size: i64,
+    /// Raw git file mode, e.g. 0o100755 for executables.
+    pub mode: i32,
+}
+
+pub struct RefDetails {
+    pub name: String,
+    pub id: String,
+    pub kind: String,
+    pub resolved_oid: Option<String>,
+    pub is_remote_head: bool,
+}
+
+pub struct TagDetails {
+    pub name: String,
+    pub target_oid: Option<String>,
+    pub tagger_name: Option<String>,
+    pub tagger_email: Option<String>,
+    pub tag_date: Option<i64>,
+    pub message: Option<String>,
+    pub signature: Option<signature::Signature>,
+}
+
+/// Creates every table in a new database, or upgrades one created by an older
+/// version; see `schema::migrate`.
+pub fn create_database(conn: &Connection) -> IngestResult<()> {
+    schema::migrate(conn)?;
+    Ok(())
+}
+
+pub fn get_commits_detail_array(
+    conn: &mut Connection,
+    repo: &Repository,
+    options: &ImportOptions,
+    progress: Option<&dyn ProgressSink>,
+    // Lets callers enrich or redact each commit before it is stored.
+    mut transform: Option<&mut dyn FnMut(&mut CommitDetails)>,
+) -> IngestResult<WalkCounts> {
+    let within = match options.max_walk_depth {
+        Some(max_depth) => Some(commits_within_depth(repo, options.tip.oid, max_depth)?),
+        None => None,
+    };
+    // A first pass only counts, so the progress total is exact while the
+    // second pass never holds more than one batch of OIDs.
+    let mut filtered = FilterResults::new();
+    let total = count_new_commits(conn, repo, options, within.as_ref(), &mut filtered)?;
+    debug!(
+        "{} new commits to import from {}",
+        total, options.tip.ref_name
+    );
+
+    let mut revwalk = new_revwalk(conn, repo, options)?;
+    // Bounded walks leave older history out, so they can't mark HEAD synced.
+    let mut complete = options.since_commit.is_none()
+        && options.max_walk_depth.is_none()
+        && options.filter.is_empty()
+        && options.limit.is_none();
+    let mut done = 0;
+    let mut counts = WalkCounts::default();
+    let started = Instant::now();
+    if let Some(dir) = &options.diffs_dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Each worker gets a batch of its own, so a batch grows with --jobs.
+    let batch_size = 50 * options.jobs.max(1);
+    loop {
+        let mut chunk = Vec::with_capacity(batch_size);
+        while chunk.len() < batch_size && done + chunk.len() < total {
+            match revwalk.next() {
+                Some(oid) => {
+                    // Walk errors are kept so they get reported below.
+                    if oid.as_ref().map_or(true, |oid| {
+                        is_new_commit(conn, repo, options, within.as_ref(), &mut filtered, *oid)
+                    }) {
+                        chunk.push(oid);
+                    }
+                }
+                None => break,
+            }
+        }
+        if chunk.is_empty() {
+            break;
+        }
+
+        if let Some(limit_mb) = options.max_db_size_mb {
+            let size = conn
+                .path()
+                .and_then(|path| fs::metadata(path).ok())
+                .map_or(0, |metadata| metadata.len());
+            if size >= limit_mb * 1024 * 1024 {
+                warn!(
+                    "database reached {} MB, stopped after {}/{} commits; the next run continues.",
+                    limit_mb, done, total
+                );
+                complete = false;
+                break;
+            }
+        }
+        let mut oids = Vec::new();
+        for (offset, oid) in chunk.iter().enumerate() {
+            match oid {
+                Ok(oid) => oids.push((done + offset, *oid)),
+                Err(e) => {
+                    // The walk itself yielded an error, there is no OID to retry.
+                    options.failures.skip(conn, "commit", None, e)?;
+                    counts.skipped += 1;
+                }
+            }
+        }
+
+        let mut chunk_commits = Vec::new();
+        for (oid, built) in build_commits(repo, &oids, options)? {
+            match built {
+                Ok(mut formatted_commit) => {
+                    if let Some(transform) = transform.as_mut() {
+                        transform(&mut formatted_commit);
+                    }
+                    chunk_commits.push(formatted_commit);
+                }
+                Err(e) if options.failures.keep_going => {
+                    options
+                        .failures
+                        .skip(conn, "commit", Some(&oid.to_string()), &e)?;
+                    counts.skipped += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        // One transaction per chunk; each commit gets a nested savepoint so a
+        // failed insert only rolls back its own rows. Savepoints also nest
+        // inside --single-transaction.
+        let mut chunk_tx = conn.savepoint()?;
+        for commit in &chunk_commits {
+            let commit_tx = chunk_tx.savepoint()?;
+            match insert_commit(&commit_tx, options.repo_id, commit, options.on_conflict) {
+                Ok(()) => {
+                    commit_tx.commit()?;
+                    counts.added += 1;
+                }
+                Err(e) if options.failures.keep_going => {
+                    drop(commit_tx);
+                    options
+                        .failures
+                        .skip(&chunk_tx, "commit", Some(&commit.id), &e)?;
+                    counts.skipped += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        chunk_tx.commit()?;
+
+        done += chunk.len();
+        if let Some(progress) = progress {
+            progress.on_commit(done, total);
+        }
+
+        // Checked after a chunk is stored so even a tiny budget makes progress.
+        if let Some(budget) = options.time_budget {
+            if started.elapsed() >= budget && done < total {
+                info!(
+                    "Time budget of {}s reached, stopped after {}/{} commits; the next run continues.",
+                    budget.as_secs(),
+                    done,
+                    total
+                );
+                complete = false;
+                break;
+            }
+        }
+    }
+
+    // A run cut short or bounded must walk the same range again next time.
+    if complete {
+        record_sync_state(conn, options.repo_id, &options.tip)?;
+    }
+    counts.complete = complete;
+
+    Ok(counts)
+}
+
+type BuiltCommit = (Oid, IngestResult<CommitDetails>);
+
+/// Builds the commits of one chunk, in order. `oids` pairs each OID with its
+/// position in the walk, which picks the `tree_every` snapshots. With
+/// `jobs` above 1 the chunk is split across threads, each with its own
+/// repository handle since `Repository` can't be shared between threads.
+fn build_commits(
+    repo: &Repository,
+    oids: &[(usize, Oid)],
+    options: &ImportOptions,
+) -> IngestResult<Vec<BuiltCommit>> {
+    let build = |repo: &Repository, oids: &[(usize, Oid)]| -> Vec<BuiltCommit> {
+        oids.iter()
+            .map(|&(index, oid)| {
+                let snapshot = options.tree_every.is_some_and(|every| index % every == 0);
+                (oid, build_commit(repo, oid, options, snapshot))
+            })
+            .collect()
+    };
+    if options.jobs <= 1 || oids.len() < 2 {
+        return Ok(build(repo, oids));
+    }
+
+    let path = repo.path();
+    let per_worker = oids.len().div_ceil(options.jobs);
+    thread::scope(|scope| {
+        let workers: Vec<_> = oids
+            .chunks(per_worker)
+            .map(|slice| {
+                scope.spawn(move || -> IngestResult<Vec<BuiltCommit>> {
+                    let worker_repo = Repository::open(path)?;
+                    add_alternates(&worker_repo, &options.alternates)?;
+                    Ok(build(&worker_repo, slice))
+                })
+            })
+            .collect();
+
+        let mut built = Vec::with_capacity(oids.len());
+        for worker in workers {
+            let results = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+            built.extend(results);
+        }
+        Ok(built)
+    })
+}
+
+/// Extracts one commit with its diffstat and, if requested, its patches
+/// and tree listing.
+fn build_commit(
+    repo: &Repository,
+    oid: Oid,
+    options: &ImportOptions,
+    snapshot: bool,
+) -> IngestResult<CommitDetails> {
+    let commit = repo.find_commit(oid)?;
+    let mut formatted_commit = extract_commit_details(&commit, options);
+
+    let diff = diff_against_first_parent(repo, &commit)?;
+    formatted_commit.files = file_changes(&diff, &options.filter)?;
+    if options.with_patches {
+        attach_file_patches(&diff, &mut formatted_commit.files)?;
+    }
+    if options.detect_generated {
+        flag_generated_files(
+            repo,
+            &diff,
+            &mut formatted_commit.files,
+            options.generated_markers,
+        );
+    }
+    if let Some(dir) = &options.diffs_dir {
+        formatted_commit.patch_path = Some(write_patch(&diff, oid, dir, &options.filter)?);
+    }
+    if snapshot {
+        formatted_commit.tree = tree_entries(repo, &commit.tree()?)?;
+    }
+
+    Ok(formatted_commit)
+}
+
+/// Whether each commit passed the commit filter, so the counting pass and
+/// the walk after it only diff a commit once for `--path`.
+type FilterResults = HashMap<Oid, bool>;
+
+/// How many commits the walk would extract, up to `--limit`.
+fn count_new_commits(
+    conn: &Connection,
+    repo: &Repository,
+    options: &ImportOptions,
+    within: Option<&HashSet<Oid>>,
+    filtered: &mut FilterResults,
+) -> IngestResult<usize> {
+    let mut total = 0;
+    for oid in new_revwalk(conn, repo, options)? {
+        if options.limit == Some(total) {
+            break;
+        }
+        if oid.map_or(true, |oid| {
+            is_new_commit(conn, repo, options, within, filtered, oid)
+        }) {
+            total += 1;
+        }
+    }
+    Ok(total)
+}
+
+/// Starts a walk from the tip, hiding `--since-tag` and, unless updating,
+/// everything behind the tips the last complete run recorded.
+fn new_revwalk<'repo>(
+    conn: &Connection,
+    repo: &'repo Repository,
+    options: &ImportOptions,
+) -> IngestResult<Revwalk<'repo>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(options.tip.oid)?;
+    if let Some(since) = options.since_commit {
+        revwalk.hide(since)?;
+    }
+    // Everything behind the tips stored by the last complete run is already
+    // imported. A tip that no longer exists (e.g. after a force-push and gc)
+    // just can't be hidden, and the known-commit check covers it.
+    // Updating walks everything again so stored commits get rewritten.
+    if options.on_conflict != OnConflict::Update {
+        for tip in synced_tips(conn, options.repo_id) {
+            revwalk.hide(tip).ok();
+        }
+    }
+    Ok(revwalk)
+}
+
+/// Whether the walk should extract `oid`: it must be within `--max-walk-depth`,
+/// pass the commit filter and, unless updating, not be stored yet. Commits from
+/// interrupted runs (or older databases without sync_state) are skipped rather
+/// than extracted again.
+fn is_new_commit(
+    conn: &Connection,
+    repo: &Repository,
+    options: &ImportOptions,
+    within: Option<&HashSet<Oid>>,
+    filtered: &mut FilterResults,
+    oid: Oid,
+) -> bool {
+    if within.is_some_and(|within| !within.contains(&oid)) {
+        return false;
+    }
+    // A commit that can't be read is kept so extracting it reports the error.
+    if !options.filter.is_empty()
+        && !*filtered.entry(oid).or_insert_with(|| {
+            repo.find_commit(oid)
+                .map_or(true, |commit| options.filter.matches(repo, &commit))
+        })
+    {
+        return false;
+    }
+    if options.on_conflict == OnConflict::Update {
+        return true;
+    }
+    !conn
+        .prepare_cached("SELECT 1 FROM commit_details WHERE repo_id = ?1 AND id = ?2")
+        .and_then(|mut known| known.exists(params![options.repo_id, oid.to_string()]))
+        .unwrap_or(false)
+}
+
+/// Tips recorded by the last complete walk; empty for databases created
+/// before sync_state existed.
+fn synced_tips(conn: &Connection, repo_id: i64) -> Vec<Oid> {
+    let tips: Result<Vec<String>> = conn
+        .prepare("SELECT oid FROM sync_state WHERE repo_id = ?1")
+        .and_then(|mut stmt| {
+            stmt.query_map(params![repo_id], |row| row.get(0))?
+                .collect()
+        });
+    tips.unwrap_or_default()
+        .iter()
+        .filter_map(|oid| Oid::from_str(oid).ok())
+        .collect()
+}
+
+/// Whether the last complete walk from `tip`'s ref ended at its current
+/// commit. Bounded and interrupted walks record nothing, so the run after
+/// one finds the tip unsynced and walks again.
+pub fn tip_synced(conn: &Connection, repo_id: i64, tip: &WalkTip) -> Result<bool> {
+    conn.prepare("SELECT 1 FROM sync_state WHERE repo_id = ?1 AND ref_name = ?2 AND oid = ?3")?
+        .exists(params![repo_id, tip.ref_name, tip.oid.to_string()])
+}
+
+/// Whether a walk from `tip` would reach a commit that isn't stored yet,
+/// stopping at the first one. Cheap after a complete run, whose recorded
+/// tips hide the history behind them.
+pub fn has_unimported_commits(
+    conn: &Connection,
+    repo: &Repository,
+    repo_id: i64,
+    tip: &WalkTip,
+) -> IngestResult<bool> {
+    let mut walk = repo.revwalk()?;
+    walk.push(tip.oid)?;
+    for synced in synced_tips(conn, repo_id) {
+        walk.hide(synced).ok();
+    }
+    let mut known = conn.prepare("SELECT 1 FROM commit_details WHERE repo_id = ?1 AND id = ?2")?;
+    for oid in walk {
+        if !known.exists(params![repo_id, oid?.to_string()])? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Remembers where the walked ref pointed so the next run only walks newer commits.
+fn record_sync_state(conn: &Connection, repo_id: i64, tip: &WalkTip) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_state (repo_id, ref_name, oid) VALUES (?1, ?2, ?3)",
+        params![repo_id, tip.ref_name, tip.oid.to_string()],
+    )?;
+    Ok(())
+}
+
+/// Breadth-first search over parent links collecting every commit whose
+/// shortest distance from `tip` is at most `max_depth` (the tip is depth 0).
+fn commits_within_depth(
+    repo: &Repository,
+    tip: Oid,
+    max_depth: usize,
+) -> IngestResult<HashSet<Oid>> {
+    let mut seen = HashSet::from([tip]);
+    let mut frontier = vec![tip];
+
+    for _ in 0..max_depth {
+        let mut next = Vec::new();
+        for oid in frontier {
+            let commit = repo.find_commit(oid)?;
+            for parent in commit.parent_ids() {
+                if seen.insert(parent) {
+                    next.push(parent);
+                }
+            }
+        }
+        frontier = next;
+    }
+
+    Ok(seen)
+}
+
+fn extract_commit_details(commit: &Commit, options: &ImportOptions) -> CommitDetails {
+    let id = commit.id().to_string();
+    let author = commit.author().name().unwrap_or("Unknown").to_string();
+    let date = commit.time().seconds();
+    let mut message = commit.message().unwrap_or("No message").to_string();
+    let mut summary = commit.summary().unwrap_or("").to_string();
+    if options.strip_ansi {
+        message = ansi::strip_ansi(&message);
+        summary = ansi::strip_ansi(&summary);
+    }
+    // Differs for rebased, cherry-picked or applied-by-someone-else commits.
+    let (author_sig, committer_sig) = (commit.author(), commit.committer());
+    let author_is_committer = author_sig.name_bytes() == committer_sig.name_bytes()
+        && author_sig.email_bytes() == committer_sig.email_bytes();
+    let classified_type = options.type_rules.classify(&summary).to_string();
+    let subject_slug = classify::subject_slug(&summary);
+    let reverts_oid = parse_reverted_oid(&message);
+    let parsed = message::parse_with(&message, &options.issue_patterns);
+    // Precomputed so exports can print the same strings as `git log`.
+    let log_author = logfmt::log_author(&author_sig);
+    let log_date = logfmt::log_date(&author_sig);
+    let author_email = String::from_utf8_lossy(author_sig.email_bytes()).into_owned();
+    let committer_name = String::from_utf8_lossy(committer_sig.name_bytes()).into_owned();
+    let committer_email = String::from_utf8_lossy(committer_sig.email_bytes()).into_owned();
+    let (author_when, commit_when) = (author_sig.when(), committer_sig.when());
+    //array of parents;
+    let parents = commit.parent_ids().collect::<Vec<_>>();
+    let signature = commit
+        .header_field_bytes("gpgsig")
+        .ok()
+        .and_then(|sig| sig.as_str().and_then(signature::parse));
+
+    CommitDetails {
+        id,
+        author,
+        date,
+        message,
+        author_is_committer,
+        classified_type,
+        reverts_oid,
+        log_author,
+        log_date,
+        subject_slug,
+        author_email,
+        committer_name,
+        committer_email,
+        author_time: author_when.seconds(),
+        author_tz_offset: author_when.offset_minutes(),
+        commit_time: commit_when.seconds(),
+        commit_tz_offset: commit_when.offset_minutes(),
+        parsed,
+        patch_path: None,
+        files: Vec::new(),
+        tree: Vec::new(),
+        parents,
+        signature,
+    }
+}
+
+/// Diffs the commit against its first parent, or against the empty tree for
+/// a root commit so every file shows up as added. Renames and copies are
+/// detected rather than reported as a deletion plus an addition.
+fn diff_against_first_parent<'r>(
+    repo: &'r Repository,
+    commit: &Commit,
+) -> std::result::Result<Diff<'r>, git2::Error> {
+    let tree = commit.tree()?;
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let mut diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true).copies(true)))?;
+    Ok(diff)
+}
+
+/// Collects the diffstat: one row per changed file (matching `--path`) with
+/// its line counts.
+fn file_changes(
+    diff: &Diff,
+    filter: &CommitFilter,
+) -> std::result::Result<Vec<FileChange>, git2::Error> {
+    let mut files = Vec::new();
+    for (idx, delta) in diff.deltas().enumerate() {
+        if !filter.matches_delta(&delta) {
+            continue;
+        }
+        let change_type = match delta.status() {
+            Delta::Added => "added",
+            Delta::Deleted => "deleted",
+            Delta::Renamed => "renamed",
+            Delta::Copied => "copied",
+            Delta::Typechange => "typechange",
+            _ => "modified",
+        };
+        let new_path = delta
+            .new_file()
+            .path()
+            .map(|p| p.to_string_lossy().into_owned());
+        let old_path = delta
+            .old_file()
+            .path()
+            .map(|p| p.to_string_lossy().into_owned());
+        // Binary files have no lines to count. Loading the patch is what
+        // tells whether the content is binary.
+        let (insertions, deletions, is_binary) = match Patch::from_diff(diff, idx)? {
+            Some(patch) => {
+                let (_, insertions, deletions) = patch.line_stats()?;
+                (insertions, deletions, patch.delta().flags().is_binary())
+            }
+            None => (0, 0, delta.flags().is_binary()),
+        };
+
+        files.push(FileChange {
+            path: new_path
+                .clone()
+                .or_else(|| old_path.clone())
+                .unwrap_or_default(),
+            old_path: old_path.filter(|old| Some(old) != new_path.as_ref()),
+            change_type: change_type.to_string(),
+            insertions,
+            deletions,
+            patch: None,
+            is_generated: None,
+            is_binary,
+        });
+    }
+    Ok(files)
+}
+
+/// Writes `diff` (files matching `--path` only) to `<dir>/<oid>.patch` and
+/// returns the file name.
+fn write_patch(diff: &Diff, oid: Oid, dir: &Path, filter: &CommitFilter) -> IngestResult<String> {
+    let mut patch = Vec::new();
+    diff.print(DiffFormat::Patch, |delta, _, line| {
+        if filter.matches_delta(&delta) {
+            append_patch_line(&mut patch, &line);
+        }
+        true
+    })?;
+
+    let file_name = format!("{}.patch", oid);
+    fs::write(dir.join(&file_name), patch)?;
+    Ok(file_name)
+}
+
+/// Attaches each file's unified diff text to its `FileChange`.
+fn attach_file_patches(
+    diff: &Diff,
+    files: &mut [FileChange],
+) -> std::result::Result<(), git2::Error> {
+    let mut patches: HashMap<String, Vec<u8>> = HashMap::new();
+    diff.print(DiffFormat::Patch, |delta, _, line| {
+        let path = delta
+            .new_file()
+            .path()
+            .or_else(|| delta.old_file().path())
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        append_patch_line(patches.entry(path).or_default(), &line);
+        true
+    })?;
+
+    for file in files {
+        if let Some(patch) = patches.remove(&file.path) {
+            file.patch = Some(String::from_utf8_lossy(&patch).into_owned());
+        }
+    }
+    Ok(())
+}
+
+/// Sets `is_generated` on each file from its path and, with `markers`, from
+/// the first line of its blob (the old one for deletions). Binary blobs and
+/// submodules only go by path.
+fn flag_generated_files(repo: &Repository, diff: &Diff, files: &mut [FileChange], markers: bool) {
+    let mut blobs = HashMap::new();
+    if markers {
+        for delta in diff.deltas() {
+            let side = if delta.new_file().id().is_zero() {
+                delta.old_file()
+            } else {
+                delta.new_file()
+            };
+            if let Some(path) = side.path() {
+                blobs.insert(path.to_string_lossy().into_owned(), side.id());
+            }
+        }
+    }
+    for file in files {
+        let generated = generated::is_generated_path(&file.path)
+            || blobs
+                .get(&file.path)
+                .and_then(|oid| repo.find_blob(*oid).ok())
+                .is_some_and(|blob| {
+                    !blob.is_binary() && generated::has_generated_marker(blob.content())
+                });
+        file.is_generated = Some(generated);
+    }
+}
+
+fn append_patch_line(patch: &mut Vec<u8>, line: &DiffLine) {
+    // Content lines carry their +/-/space marker separately from the text.
+    if matches!(line.origin(), '+' | '-' | ' ') {
+        patch.push(line.origin() as u8);
+    }
+    patch.extend_from_slice(line.content());
+}
+
+/// Finds the OID in the "This reverts commit <oid>." line written by `git revert`.
+fn parse_reverted_oid(message: &str) -> Option<String> {
+    message.lines().find_map(|line| {
+        let rest = line.trim().strip_prefix("This reverts commit ")?;
+        let oid: String = rest.chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+        (oid.len() == 40).then_some(oid)
+    })
+}
+
+/// Fills `reverted_by` from the `reverts_oid` of later commits once every
+/// commit of the run is in the table.
+pub fn link_reverted_commits(conn: &Connection, repo_id: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE commit_details
+         SET reverted_by = (
+             SELECT r.id FROM commit_details r
+             WHERE r.repo_id = commit_details.repo_id AND r.reverts_oid = commit_details.id
+             ORDER BY r.date ASC
+             LIMIT 1
+         )
+         WHERE repo_id = ?1
+           AND id IN (SELECT reverts_oid FROM commit_details
+                      WHERE repo_id = ?1 AND reverts_oid IS NOT NULL)",
+        params![repo_id],
+    )?;
+    Ok(())
+}
+
+/// How much each part of `impact_rank` counts; the rank is their weighted
+/// mean, so only the ratios between them matter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImpactWeights {
+    pub recency: f64,
+    pub churn: f64,
+    pub directories: f64,
+}
+
+impl Default for ImpactWeights {
+    fn default() -> Self {
+        ImpactWeights {
+            recency: 1.0,
+            churn: 1.0,
+            directories: 1.0,
+        }
+    }
+}
+
+/// Scores every commit of the repository from 0 to 1 into `impact_rank`,
+/// blending how recent it is among the repository's commits, its line
+/// churn on a log scale, and how many directories it touched, each scaled
+/// by the repository's largest. Rescored after every import that `added`
+/// commits, since a new commit moves the others' recency, or that changed
+/// the weights.
+pub fn rank_commit_impact(
+    conn: &mut Connection,
+    repo_id: i64,
+    weights: ImpactWeights,
+    added: usize,
+) -> Result<()> {
+    let fingerprint = format!(
+        "{},{},{}",
+        weights.recency, weights.churn, weights.directories
+    );
+    if added == 0
+        && get_repo_info(conn, repo_id, "impact_weights")?.as_deref() == Some(&fingerprint)
+    {
+        return Ok(());
+    }
+    struct Scored {
+        id: String,
+        time: i64,
+        churn: i64,
+        directories: HashSet<String>,
+    }
+    let mut commits: Vec<Scored> = Vec::new();
+    let mut stmt = conn.prepare(
+        "SELECT c.id, COALESCE(c.commit_time, c.date), f.path,
+                COALESCE(f.insertions, 0) + COALESCE(f.deletions, 0)
+         FROM commit_details c
+         LEFT JOIN commit_files f ON f.commit_id = c.id
+         WHERE c.repo_id = ?1
+         ORDER BY c.id",
+    )?;
+    let mut rows = stmt.query(params![repo_id])?;
+    while let Some(row) = rows.next()? {
+        let id: String = row.get(0)?;
+        if commits.last().is_none_or(|last| last.id != id) {
+            commits.push(Scored {
+                id,
+                time: row.get(1)?,
+                churn: 0,
+                directories: HashSet::new(),
+            });
+        }
+        let commit = commits.last_mut().expect("pushed above");
+        if let Some(path) = row.get::<_, Option<String>>(2)? {
+            let directory = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+            commit.directories.insert(directory.to_string());
+            commit.churn += row.get::<_, i64>(3)?;
+        }
+    }
+
+    let oldest = commits.iter().map(|c| c.time).min().unwrap_or(0);
+    let newest = commits.iter().map(|c| c.time).max().unwrap_or(0);
+    let most_churn = commits.iter().map(|c| c.churn).max().unwrap_or(0);
+    let most_directories = commits
+        .iter()
+        .map(|c| c.directories.len())
+        .max()
+        .unwrap_or(0);
+    drop(rows);
+    drop(stmt);
+
+    let total = weights.recency + weights.churn + weights.directories;
+    // One savepoint for the whole pass, like the chunk inserts; rows whose
+    // rank didn't change aren't rewritten.
+    let tx = conn.savepoint()?;
+    let mut update = tx.prepare(
+        "UPDATE commit_details SET impact_rank = ?3
+         WHERE repo_id = ?1 AND id = ?2 AND impact_rank IS NOT ?3",
+    )?;
+    for commit in &commits {
+        let recency = if newest > oldest {
+            (commit.time - oldest) as f64 / (newest - oldest) as f64
+        } else {
+            1.0
+        };
+        let churn = if most_churn > 0 {
+            (1.0 + commit.churn as f64).ln() / (1.0 + most_churn as f64).ln()
+        } else {
+            0.0
+        };
+        let directories = if most_directories > 0 {
+            commit.directories.len() as f64 / most_directories as f64
+        } else {
+            0.0
+        };
+        let rank =
+            (weights.recency * recency + weights.churn * churn + weights.directories * directories)
+                / total;
+        update.execute(params![repo_id, commit.id, rank])?;
+    }
+    drop(update);
+    set_repo_info(&tx, repo_id, "impact_weights", &fingerprint)?;
+    tx.commit()
+}
+
+/// Points commits at their canonical authors row, resolving each distinct
+/// name and email through the repository's .mailmap (and mailmap.file
+/// config). Only commits without an author_id are touched unless `remap`,
+/// which re-resolves all of them after the mailmap changed and drops
+/// authors no commit uses any more. Returns how many commits were updated.
+pub fn assign_author_ids(
+    conn: &Connection,
+    repo: &Repository,
+    repo_id: i64,
+    remap: bool,
+) -> IngestResult<usize> {
+    let mailmap = repo.mailmap().or_else(|_| Mailmap::new())?;
+    let identities: Vec<(String, Option<String>)> = conn
+        .prepare(
+            "SELECT DISTINCT author, author_email FROM commit_details
+             WHERE repo_id = ?1 AND (?2 OR author_id IS NULL)",
+        )?
+        .query_map(params![repo_id, remap], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .collect::<Result<_>>()?;
+
+    let mut upsert = conn.prepare(
+        "INSERT INTO authors (name, email) VALUES (?1, ?2)
+         ON CONFLICT (name, email) DO UPDATE SET name = excluded.name
+         RETURNING author_id",
+    )?;
+    let mut update = conn.prepare(
+        "UPDATE commit_details SET author_id = ?4
+         WHERE repo_id = ?1 AND author = ?2 AND author_email IS ?3
+           AND author_id IS NOT ?4",
+    )?;
+    let mut updated = 0;
+    for (name, email) in identities {
+        let raw_email = email.clone().unwrap_or_default();
+        // Names git itself would refuse can't be looked up; they stay as they are.
+        let (canonical_name, canonical_email) = Signature::new(&name, &raw_email, &Time::new(0, 0))
+            .and_then(|signature| mailmap.resolve_signature(&signature))
+            .map(|signature| {
+                (
+                    String::from_utf8_lossy(signature.name_bytes()).into_owned(),
+                    String::from_utf8_lossy(signature.email_bytes()).into_owned(),
+                )
+            })
+            .unwrap_or_else(|_| (name.clone(), raw_email));
+        let author_id: i64 =
+            upsert.query_row(params![canonical_name, canonical_email], |row| row.get(0))?;
+        updated += update.execute(params![repo_id, name, email, author_id])?;
+    }
+    if remap {
+        conn.execute(
+            "DELETE FROM authors WHERE author_id NOT IN
+             (SELECT author_id FROM commit_details WHERE author_id IS NOT NULL)",
+            [],
+        )?;
+    }
+    Ok(updated)
+}
+
+/// Adds a merges row for every merge commit of the repository that has none
+/// yet: its parents, the mainline (first) parent, the branches named in the
+/// message and, in merge_commits, the commits it brought in, i.e. those
+/// reachable from the other parents but not from the mainline. Returns how
+/// many merges were analysed.
+pub fn analyze_merges(conn: &Connection, repo: &Repository, repo_id: i64) -> IngestResult<usize> {
+    let pending: Vec<String> = conn
+        .prepare(
+            "SELECT child FROM commit_relation r
+             WHERE repo_id = ?1
+               AND NOT EXISTS (SELECT 1 FROM merges m WHERE m.repo_id = ?1 AND m.commit_id = r.child)
+             GROUP BY child
+             HAVING COUNT(*) > 1",
+        )?
+        .query_map(params![repo_id], |row| row.get(0))?
+        .collect::<Result<_>>()?;
+
+    let mut analysed = 0;
+    for id in pending {
+        // Pruned or otherwise gone from the repository.
+        let Ok(commit) = Oid::from_str(&id).and_then(|oid| repo.find_commit(oid)) else {
+            continue;
+        };
+        let parents: Vec<Oid> = commit.parent_ids().collect();
+        let mut walk = repo.revwalk()?;
+        for parent in &parents[1..] {
+            walk.push(*parent)?;
+        }
+        walk.hide(parents[0])?;
+        let introduced: Vec<Oid> = walk.collect::<std::result::Result<_, _>>()?;
+        let (merged_branch, target_branch) =
+            message::merge_branches(commit.summary().unwrap_or(""));
+
+        conn.execute(
+            "INSERT INTO merges
+             (repo_id, commit_id, parent_count, mainline_parent, merged_branch, target_branch,
+              introduced_commits)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                repo_id,
+                id,
+                parents.len() as i64,
+                parents[0].to_string(),
+                merged_branch,
+                target_branch,
+                introduced.len() as i64,
+            ],
+        )?;
+        let mut insert = conn.prepare_cached(
+            "INSERT OR IGNORE INTO merge_commits (repo_id, merge_id, commit_id) VALUES (?1, ?2, ?3)",
+        )?;
+        for oid in introduced {
+            insert.execute(params![repo_id, id, oid.to_string()])?;
+        }
+        analysed += 1;
+    }
+    Ok(analysed)
+}
+
+/// Cheap post-import assertion: the walk starts at the tip, so a missing
+/// tip row means something dropped commits silently.
+pub fn check_tip_imported(conn: &Connection, repo_id: i64, tip: &WalkTip) {
+    let found = conn
+        .query_row(
+            "SELECT 1 FROM commit_details WHERE repo_id = ?1 AND id = ?2",
+            params![repo_id, tip.oid.to_string()],
+            |_| Ok(()),
+        )
+        .optional();
+    match found {
+        Ok(Some(())) => {}
+        Ok(None) => warn!(
+            "{} commit {} is missing from commit_details!",
+            tip.ref_name, tip.oid
+        ),
+        Err(e) => warn!(
+            "failed to look up {} commit {}: {}",
+            tip.ref_name, tip.oid, e
+        ),
+    }
+}
+
+fn insert_commit(
+    conn: &Connection,
+    repo_id: i64,
+    commit: &CommitDetails,
+    on_conflict: OnConflict,
+) -> Result<()> {
+    trace!("storing commit {}", commit.id);
+    // reverted_by is left out so an update keeps the link_reverted_commits result.
+    let insert_sql = on_conflict.insert_sql(
+        "commit_details",
+        &[
+            "repo_id",
+            "id",
+            "author",
+            "date",
+            "message",
+            "author_is_committer",
+            "classified_type",
+            "reverts_oid",
+            "log_author",
+            "log_date",
+            "subject_slug",
+            "author_email",
+            "committer_name",
+            "committer_email",
+            "author_time",
+            "author_tz_offset",
+            "commit_time",
+            "commit_tz_offset",
+            "cc_type",
+            "cc_scope",
+            "is_breaking",
+            "subject",
+            "body",
+            "signature_status",
+            "signature_type",
+            "signing_key",
+            "touches_tests",
+            "binary_files_changed",
+        ],
+        &["repo_id", "id"],
+    );
+
+    // Statements are prepared once per connection instead of once per row.
+    let execute = |sql: &str, params: &[&dyn ToSql]| -> Result<usize> {
+        conn.prepare_cached(sql)?.execute(params)
+    };
+
+    execute(
+        &insert_sql,
+        params![
+            repo_id,
+            &commit.id,
+            &commit.author,
+            commit.date,
+            &commit.message,
+            commit.author_is_committer,
+            &commit.classified_type,
+            &commit.reverts_oid,
+            &commit.log_author,
+            &commit.log_date,
+            &commit.subject_slug,
+            &commit.author_email,
+            &commit.committer_name,
+            &commit.committer_email,
+            commit.author_time,
+            commit.author_tz_offset,
+            commit.commit_time,
+            commit.commit_tz_offset,
+            &commit.parsed.cc_type,
+            &commit.parsed.scope,
+            commit.parsed.breaking,
+            &commit.parsed.subject,
+            &commit.parsed.body,
+            signature_status(&commit.signature),
+            commit.signature.as_ref().map(|s| &s.kind),
+            commit.signature.as_ref().and_then(|s| s.key.as_ref()),
+            commit
+                .files
+                .iter()
+                .any(|file| language::is_test_path(&file.path)),
+            commit.files.iter().filter(|file| file.is_binary).count() as i64,
+        ],
+    )?;
+
+    // Like the diff rows below, parsed message rows depend on the OID alone.
+    for (position, (key, value)) in commit.parsed.footers.iter().enumerate() {
+        execute(
+            &on_conflict.insert_sql(
+                "commit_footers",
+                &["commit_id", "position", "key", "value"],
+                &["commit_id", "position"],
+            ),
+            params![commit.id, position as i64, key, value],
+        )?;
+    }
+    for (position, (name, email)) in commit.parsed.co_authors.iter().enumerate() {
+        execute(
+            &on_conflict.insert_sql(
+                "commit_co_authors",
+                &["commit_id", "position", "name", "email"],
+                &["commit_id", "position"],
+            ),
+            params![commit.id, position as i64, name, email],
+        )?;
+    }
+    for (issue_ref, action) in &commit.parsed.issue_refs {
+        execute(
+            &on_conflict.insert_sql(
+                "commit_issue_refs",
+                &["commit_id", "issue_ref", "action"],
+                &["commit_id", "issue_ref"],
+            ),
+            params![commit.id, issue_ref, action],
+        )?;
+    }
+
+    // Diff rows are keyed by OID alone: a commit has the same diff in every
+    // repository it appears in.
+    for file in &commit.files {
+        execute(
+            &on_conflict.insert_sql(
+                "commit_files",
+                &[
+                    "commit_id",
+                    "path",
+                    "old_path",
+                    "change_type",
+                    "insertions",
+                    "deletions",
+                    "language",
+                    "is_generated",
+                    "is_test",
+                    "is_binary",
+                ],
+                &["commit_id", "path"],
+            ),
+            params![
+                commit.id,
+                file.path,
+                file.old_path,
+                file.change_type,
+                file.insertions as i64,
+                file.deletions as i64,
+                language::language(&file.path),
+                file.is_generated,
+                language::is_test_path(&file.path),
+                file.is_binary
+            ],
+        )?;
+        if let Some(patch) = &file.patch {
+            execute(
+                &on_conflict.insert_sql(
+                    "commit_patches",
+                    &["commit_id", "path", "patch"],
+                    &["commit_id", "path"],
+                ),
+                params![commit.id, file.path, patch],
+            )?;
+        }
+        let renamed_from = file
+            .old_path
+            .as_deref()
+            .filter(|_| file.change_type == "renamed");
+        record_file_identity(conn, repo_id, &file.path, renamed_from)?;
+    }
+
+    for entry in &commit.tree {
+        execute(
+            &on_conflict.insert_sql(
+                "tree_entries",
+                &["commit_id", "path", "blob_oid", "size", "mode"],
+                &["commit_id", "path"],
+            ),
+            params![
+                commit.id,
+                entry.path,
+                entry.blob_oid,
+                entry.size,
+                entry.mode
+            ],
+        )?;
+    }
+
+    if let Some(patch_path) = &commit.patch_path {
+        execute(
+            &on_conflict.insert_sql("commit_diffs", &["commit_id", "patch_path"], &["commit_id"]),
+            params![commit.id, patch_path],
+        )?;
+    }
+
+    for parent in &commit.parents {
+        execute(
+            &on_conflict.insert_sql(
+                "commit_relation",
+                &["repo_id", "parent", "child"],
+                &["repo_id", "parent", "child"],
+            ),
+            params![repo_id, parent.to_string(), commit.id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Gives `path` a file_identity row. A rename joins the identities of both
+/// paths, so every name a file has had shares one file_id whichever order
+/// the commits arrive in. Copies start a file of their own.
+pub(crate) fn record_file_identity(
+    conn: &Connection,
+    repo_id: i64,
+    path: &str,
+    renamed_from: Option<&str>,
+) -> Result<()> {
+    let id_of = |path: &str| -> Result<Option<i64>> {
+        conn.prepare_cached("SELECT file_id FROM file_identity WHERE repo_id = ?1 AND path = ?2")?
+            .query_row(params![repo_id, path], |row| row.get(0))
+            .optional()
+    };
+    let old_id = renamed_from.map(id_of).transpose()?.flatten();
+    let new_id = id_of(path)?;
+    let file_id = match old_id.or(new_id) {
+        Some(id) => id,
+        None => conn
+            .prepare_cached("SELECT COALESCE(MAX(file_id), 0) + 1 FROM file_identity")?
+            .query_row([], |row| row.get(0))?,
+    };
+
+    let mut insert = conn.prepare_cached(
+        "INSERT OR IGNORE INTO file_identity (repo_id, path, file_id) VALUES (?1, ?2, ?3)",
+    )?;
+    insert.execute(params![repo_id, path, file_id])?;
+    if let Some(old_path) = renamed_from {
+        insert.execute(params![repo_id, old_path, file_id])?;
+    }
+    if let Some(merged) = new_id.filter(|&id| id != file_id) {
+        conn.prepare_cached(
+            "UPDATE file_identity SET file_id = ?2 WHERE repo_id = ?1 AND file_id = ?3",
+        )?
+        .execute(params![repo_id, file_id, merged])?;
+    }
+    Ok(())
+}
+
+/// Reads a stored commit and its parents back into a `CommitDetails`.
+pub fn load_commit(conn: &Connection, oid: &str) -> Result<Option<CommitDetails>> {
+    let commit = conn
+        .query_row(
+            "SELECT id, author, date, message, author_is_committer, classified_type, reverts_oid,
+                    log_author, log_date, subject_slug, author_email, committer_name,
+                    committer_email, author_time, author_tz_offset, commit_time,
+                    commit_tz_offset, d.patch_path, repo_id, signature_type, signing_key,
+                    cc_type, cc_scope, is_breaking, subject, body
+             FROM commit_details LEFT JOIN commit_diffs d ON d.commit_id = id
+             WHERE id = ?1
+             ORDER BY repo_id
+             LIMIT 1",
+            params![oid],
+            |row| {
+                let commit = CommitDetails {
+                    id: row.get(0)?,
+                    author: row.get(1)?,
+                    date: row.get(2)?,
+                    message: row.get(3)?,
+                    author_is_committer: row.get(4)?,
+                    classified_type: row.get(5)?,
+                    reverts_oid: row.get(6)?,
+                    log_author: row.get(7)?,
+                    log_date: row.get(8)?,
+                    subject_slug: row.get(9)?,
+                    author_email: row.get(10)?,
+                    committer_name: row.get(11)?,
+                    committer_email: row.get(12)?,
+                    author_time: row.get(13)?,
+                    author_tz_offset: row.get(14)?,
+                    commit_time: row.get(15)?,
+                    commit_tz_offset: row.get(16)?,
+                    parsed: message::ParsedMessage {
+                        cc_type: row.get(21)?,
+                        scope: row.get(22)?,
+                        breaking: row.get(23)?,
+                        subject: row.get(24)?,
+                        body: row.get(25)?,
+                        ..Default::default()
+                    },
+                    patch_path: row.get(17)?,
+                    files: Vec::new(),
+                    tree: Vec::new(),
+                    parents: Vec::new(),
+                    signature: row
+                        .get::<_, Option<String>>(19)?
+                        .map(|kind| signature::Signature {
+                            kind,
+                            key: row.get(20).ok().flatten(),
+                        }),
+                };
+                Ok((commit, row.get::<_, i64>(18)?))
+            },
+        )
+        .optional()?;
+    // A commit shared by several repositories reads the same from each.
+    let (mut commit, repo_id) = match commit {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    // Read back rather than reparsed: the import may have used its own
+    // --issue-pattern and trailer actions.
+    commit.parsed.footers = conn
+        .prepare("SELECT key, value FROM commit_footers WHERE commit_id = ?1 ORDER BY position")?
+        .query_map(params![oid], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    commit.parsed.co_authors = conn
+        .prepare(
+            "SELECT name, email FROM commit_co_authors WHERE commit_id = ?1 ORDER BY position",
+        )?
+        .query_map(params![oid], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    commit.parsed.issue_refs = conn
+        .prepare(
+            "SELECT issue_ref, action FROM commit_issue_refs WHERE commit_id = ?1 ORDER BY rowid",
+        )?
+        .query_map(params![oid], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT f.path, f.old_path, f.change_type, f.insertions, f.deletions, p.patch,
+                f.is_generated, COALESCE(f.is_binary, 0)
+         FROM commit_files f
+         LEFT JOIN commit_patches p ON p.commit_id = f.commit_id AND p.path = f.path
+         WHERE f.commit_id = ?1
+         ORDER BY f.rowid",
+    )?;
+    commit.files = stmt
+        .query_map(params![oid], |row| {
+            Ok(FileChange {
+                path: row.get(0)?,
+                old_path: row.get(1)?,
+                change_type: row.get(2)?,
+                insertions: row.get::<_, i64>(3)? as usize,
+                deletions: row.get::<_, i64>(4)? as usize,
+                patch: row.get(5)?,
+                is_generated: row.get(6)?,
+                is_binary: row.get(7)?,
+            })
+        })?
+        .collect::<Result<_>>()?;
+
+    // Relations are inserted in parent order, so rowid order restores it.
+    let mut stmt = conn.prepare(
+        "SELECT parent FROM commit_relation WHERE repo_id = ?1 AND child = ?2 ORDER BY rowid",
+    )?;
+    commit.parents = stmt
+        .query_map(params![repo_id, oid], |row| {
+            let parent: String = row.get(0)?;
+            Oid::from_str(&parent)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))
+        })?
+        .collect::<Result<_>>()?;
+
+    Ok(Some(commit))
+}
+
+pub fn get_ref_details(
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    refs: &RefFilter,
+    failures: FailurePolicy,
+    on_conflict: OnConflict,
+    progress: Option<&dyn ProgressSink>,
+) -> IngestResult<WalkCounts> {
+    let all_references: Vec<_> = repo.references()?.collect();
+    let mut done = 0;
+    let mut counts = WalkCounts::default();
+
+    for chunk in all_references.chunks(50) {
+        let mut chunk_refs = Vec::new();
+
+        for reference_result in chunk {
+            match reference_result {
+                Ok(reference) => {
+                    if !refs.matches_reference(reference) {
+                        continue;
+                    }
+                    let formatted_refs = extract_ref_details(reference);
+                    chunk_refs.push(formatted_refs);
+                }
+                Err(e) => {
+                    failures.skip(conn, "reference", None, e)?;
+                    counts.skipped += 1;
+                }
+            }
+        }
+        // Same transaction layout as the commit inserts.
+        let mut chunk_tx = conn.savepoint()?;
+        for reference in &chunk_refs {
+            let ref_tx = chunk_tx.savepoint()?;
+            match insert_ref(&ref_tx, repo_id, reference, on_conflict) {
+                Ok(()) => {
+                    ref_tx.commit()?;
+                    counts.added += 1;
+                }
+                Err(e) if failures.keep_going => {
+                    drop(ref_tx);
+                    failures.skip(&chunk_tx, "reference", Some(&reference.name), &e)?;
+                    counts.skipped += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        chunk_tx.commit()?;
+
+        done += chunk.len();
+        if let Some(progress) = progress {
+            progress.on_ref(done, all_references.len());
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Which refs are stored and followed. `include` and `exclude` are globs
+/// over full ref names (`refs/pull/*`, `refs/heads/dependabot/*`): a ref
+/// must match one include pattern, when there are any, and no exclude
+/// pattern. `tag_pattern` further narrows tags by their short name.
+#[derive(Clone, Default)]
+pub struct RefFilter {
+    pub tag_pattern: Option<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl RefFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        let matching = |patterns: &[String]| patterns.iter().any(|p| glob::glob_match(p, name));
+        if !self.include.is_empty() && !matching(&self.include) {
+            return false;
+        }
+        if matching(&self.exclude) {
+            return false;
+        }
+        match (&self.tag_pattern, name.strip_prefix("refs/tags/")) {
+            (Some(pattern), Some(tag_name)) => glob::glob_match(pattern, tag_name),
+            // Only tags are filtered, branches and remotes are always kept.
+            _ => true,
+        }
+    }
+
+    pub(crate) fn matches_reference(&self, reference: &Reference) -> bool {
+        self.matches(reference.name().unwrap_or(""))
+    }
+}
+
+fn extract_ref_details(reference: &Reference) -> RefDetails {
+    let name = reference.name().unwrap_or("").to_string();
+    let id = match reference.target() {
+        Some(target) => target.to_string(),
+        None => String::from("Unknown"),
+    };
+    let kind = match reference.kind() {
+        Some(git2::ReferenceType::Direct) => "Direct",
+        Some(git2::ReferenceType::Symbolic) => "Symbolic",
+        None => "Unknown",
+    }
+    .to_string();
+    // Follows symbolic refs such as refs/remotes/origin/HEAD to the tip they name.
+    let resolved_oid = reference
+        .resolve()
+        .ok()
+        .and_then(|resolved| resolved.target())
+        .map(|oid| oid.to_string());
+    let is_remote_head = reference.kind() == Some(git2::ReferenceType::Symbolic)
+        && name.starts_with("refs/remotes/")
+        && name.ends_with("/HEAD");
+
+    RefDetails {
+        id,
+        name,
+        kind,
+        resolved_oid,
+        is_remote_head,
+    }
+}
+
+fn insert_ref(
+    conn: &Connection,
+    repo_id: i64,
+    reference: &RefDetails,
+    on_conflict: OnConflict,
+) -> Result<()> {
+    let mut stmt = conn.prepare_cached(&on_conflict.insert_sql(
+        "ref_details",
+        &[
+            "repo_id",
+            "id",
+            "name",
+            "kind",
+            "resolved_oid",
+            "is_remote_head",
+        ],
+        &["repo_id", "name", "id"],
+    ))?;
+    stmt.execute(params![
+        repo_id,
+        &reference.id,
+        &reference.name,
+        reference.kind,
+        &reference.resolved_oid,
+        reference.is_remote_head,
+    ])?;
+    Ok(())
+}
+
+pub fn ref_targets_imported(
+    conn: &Connection,
+    repo: &Repository,
+    repo_id: i64,
+    refs: &RefFilter,
+) -> IngestResult<bool> {
+    let mut stmt = conn.prepare("SELECT 1 FROM commit_details WHERE repo_id = ?1 AND id = ?2")?;
+
+    for reference in repo.references()? {
+        let reference = reference?;
+        // Notes and stashes are read by refresh_notes and refresh_stashes,
+        // never walked.
+        if reference.is_note() || reference.name() == Some("refs/stash") {
+            continue;
+        }
+        if !refs.matches_reference(&reference) {
+            continue;
+        }
+        let commit = match reference.peel_to_commit() {
+            Ok(commit) => commit,
+            // Refs that don't resolve to a commit never add commits to the walk.
+            Err(_) => continue,
+        };
+        if !stmt.exists(params![repo_id, commit.id().to_string()])? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+pub fn refresh_ref_details(
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    refs: &RefFilter,
+    failures: FailurePolicy,
+    on_conflict: OnConflict,
+    progress: Option<&dyn ProgressSink>,
+) -> IngestResult<WalkCounts> {
+    // The delete and the inserts share one savepoint so a failed refresh
+    // keeps the previous refs instead of leaving none.
+    conn.execute_batch("SAVEPOINT refresh_refs")?;
+    match replace_ref_details(conn, repo, repo_id, refs, failures, on_conflict, progress) {
+        Ok(counts) => {
+            conn.execute_batch("RELEASE refresh_refs")?;
+            Ok(counts)
+        }
+        Err(e) => {
+            conn.execute_batch("ROLLBACK TO refresh_refs; RELEASE refresh_refs")?;
+            Err(e)
+        }
+    }
+}
+
+fn replace_ref_details(
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    refs: &RefFilter,
+    failures: FailurePolicy,
+    on_conflict: OnConflict,
+    progress: Option<&dyn ProgressSink>,
+) -> IngestResult<WalkCounts> {
+    // Drop refs that no longer exist along with the stale targets.
+    conn.execute(
+        "DELETE FROM ref_details WHERE repo_id = ?1",
+        params![repo_id],
+    )?;
+    let counts = get_ref_details(conn, repo, repo_id, refs, failures, on_conflict, progress)?;
+    refresh_tags(conn, repo, repo_id, refs)?;
+    Ok(counts)
+}
+
+/// Appends a ref_history row for every ref created, moved or deleted since
+/// the previous recorded run, comparing against the last row per ref rather
+/// than ref_details so partial imports don't lose changes. Returns the
+/// number of rows added.
+pub fn record_ref_history(
+    conn: &Connection,
+    repo: &Repository,
+    repo_id: i64,
+    recorded_at: i64,
+    refs: &RefFilter,
+) -> IngestResult<usize> {
+    let mut previous: HashMap<String, String> = conn
+        .prepare(
+            "SELECT name, target FROM ref_history
+             WHERE rowid IN (SELECT MAX(rowid) FROM ref_history WHERE repo_id = ?1 GROUP BY name)
+               AND change != 'deleted'",
+        )?
+        .query_map(params![repo_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    // Filtered-out refs weren't deleted, just not looked at.
+    previous.retain(|name, _| refs.matches(name));
+
+    let mut changes = Vec::new();
+    for reference in repo.references()?.flatten() {
+        if !refs.matches_reference(&reference) {
+            continue;
+        }
+        let details = extract_ref_details(&reference);
+        let target = details.resolved_oid.unwrap_or(details.id);
+        let change = match previous.remove(&details.name) {
+            None => Some(("created", None)),
+            Some(old) if old == target => None,
+            Some(old) => {
+                let fast_forward = match (Oid::from_str(&target), Oid::from_str(&old)) {
+                    (Ok(new), Ok(old)) => repo.graph_descendant_of(new, old).unwrap_or(false),
+                    _ => false,
+                };
+                Some((if fast_forward { "updated" } else { "forced" }, Some(old)))
+            }
+        };
+        if let Some((change, old)) = change {
+            changes.push((details.name, target, old, change));
+        }
+    }
+    let mut deleted: Vec<_> = previous.into_iter().collect();
+    deleted.sort();
+    for (name, old) in deleted {
+        changes.push((name, old, None, "deleted"));
+    }
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO ref_history (repo_id, recorded_at, name, target, previous_target, change)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for (name, target, old, change) in &changes {
+        stmt.execute(params![repo_id, recorded_at, name, target, old, change])?;
+    }
+    Ok(changes.len())
+}
+
+/// Replaces the `tags` table. Lightweight tags get a row too, with the
+/// tagger and annotation columns left NULL.
+pub fn refresh_tags(
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    refs: &RefFilter,
+) -> IngestResult<()> {
+    let mut tags = Vec::new();
+    for reference in repo.references_glob("refs/tags/*")?.flatten() {
+        if !refs.matches_reference(&reference) {
+            continue;
+        }
+        tags.push(extract_tag_details(repo, &reference));
+    }
+
+    let tx = conn.savepoint()?;
+    tx.execute("DELETE FROM tags WHERE repo_id = ?1", params![repo_id])?;
+    for tag in &tags {
+        tx.execute(
+            "INSERT INTO tags (repo_id, name, target_oid, tagger_name, tagger_email, tag_date, message,
+                               signature_status, signature_type, signing_key)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                repo_id,
+                tag.name,
+                tag.target_oid,
+                tag.tagger_name,
+                tag.tagger_email,
+                tag.tag_date,
+                tag.message,
+                signature_status(&tag.signature),
+                tag.signature.as_ref().map(|s| &s.kind),
+                tag.signature.as_ref().and_then(|s| s.key.as_ref()),
+            ],
+        )?;
+    }
+    Ok(tx.commit()?)
+}
+
+fn extract_tag_details(repo: &Repository, reference: &Reference) -> TagDetails {
+    let name = reference
+        .name()
+        .and_then(|n| n.strip_prefix("refs/tags/"))
+        .unwrap_or("")
+        .to_string();
+    // Tags of trees or blobs have no commit to point at.
+    let target_oid = reference
+        .peel_to_commit()
+        .ok()
+        .map(|commit| commit.id().to_string());
+    // Only annotated tags point at a tag object; lightweight ones point at the commit.
+    let annotation = reference.target().and_then(|oid| repo.find_tag(oid).ok());
+
+    match annotation {
+        Some(tag) => {
+            let tagger = tag.tagger();
+            TagDetails {
+                name,
+                target_oid,
+                tagger_name: tagger
+                    .as_ref()
+                    .map(|t| String::from_utf8_lossy(t.name_bytes()).into_owned()),
+                tagger_email: tagger
+                    .as_ref()
+                    .map(|t| String::from_utf8_lossy(t.email_bytes()).into_owned()),
+                tag_date: tagger.as_ref().map(|t| t.when().seconds()),
+                message: tag
+                    .message_bytes()
+                    .map(|m| String::from_utf8_lossy(m).into_owned()),
+                signature: tag
+                    .message_bytes()
+                    .and_then(|m| signature::from_tag_message(&String::from_utf8_lossy(m))),
+            }
+        }
+        None => TagDetails {
+            name,
+            target_oid,
+            tagger_name: None,
+            tagger_email: None,
+            tag_date: None,
+            message: None,
+            signature: None,
+        },
+    }
+}
+
+/// `signed` only says a signature is present; it isn't verified.
+fn signature_status(signature: &Option<signature::Signature>) -> &'static str {
+    match signature {
+        Some(_) => "signed",
+        None => "unsigned",
+    }
+}
+
+/// Snapshots every file of the HEAD tree. `mode` is the raw git file mode,
+/// so executables (0o100755) and symlinks (0o120000) can be told apart.
+pub fn get_tree_files(conn: &mut Connection, repo: &Repository, repo_id: i64) -> IngestResult<()> {
+    let files = tree_entries(repo, &repo.head()?.peel_to_tree()?)?;
+
+    // HEAD moves between runs, so the snapshot is replaced rather than merged.
+    let tx = conn.savepoint()?;
+    tx.execute(
+        "DELETE FROM tree_files WHERE repo_id = ?1",
+        params![repo_id],
+    )?;
+    for file in &files {
+        tx.execute(
+            "INSERT INTO tree_files (repo_id, path, blob_oid, size, mode) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![repo_id, file.path, file.blob_oid, file.size, file.mode],
+        )?;
+    }
+    Ok(tx.commit()?)
+}
+
+/// Which HEAD files `store_blobs` keeps the contents of.
+#[derive(Default)]
+pub struct BlobFilter {
+    /// Globs a path must match one of, all files when empty.
+    pub paths: Vec<String>,
+    /// Skips blobs larger than this many bytes.
+    pub max_size: Option<u64>,
+    pub exclude_binary: bool,
+}
+
+/// Stores the raw contents of the HEAD files passing `filter` in `blobs`,
+/// once per blob OID however many paths or repositories share it. Returns
+/// how many blobs were new.
+pub fn store_blobs(
+    conn: &mut Connection,
+    repo: &Repository,
+    filter: &BlobFilter,
+) -> IngestResult<usize> {
+    let entries = tree_entries(repo, &repo.head()?.peel_to_tree()?)?;
+
+    let tx = conn.savepoint()?;
+    let mut stored = 0;
+    let mut seen = HashSet::new();
+    for entry in &entries {
+        let wanted = filter.paths.is_empty()
+            || filter
+                .paths
+                .iter()
+                .any(|pattern| glob::glob_match(pattern, &entry.path));
+        // The size is known from the tree walk, so big blobs are never loaded.
+        let too_big = filter
+            .max_size
+            .is_some_and(|max_size| entry.size as u64 > max_size);
+        if !wanted || too_big || !seen.insert(entry.blob_oid.as_str()) {
+            continue;
+        }
+
+        let blob = repo.find_blob(Oid::from_str(&entry.blob_oid)?)?;
+        if filter.exclude_binary && blob.is_binary() {
+            continue;
+        }
+        stored += tx.execute(
+            &OnConflict::Ignore.insert_sql(
+                "blobs",
+                &["blob_oid", "size", "is_binary", "content"],
+                &["blob_oid"],
+            ),
+            params![
+                entry.blob_oid,
+                blob.size() as i64,
+                blob.is_binary(),
+                blob.content()
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(stored)
+}
+
+/// Bytes a commit takes up when the database has none to measure yet:
+/// the commit_details row, its commit_files rows, relations and indexes.
+const ESTIMATED_COMMIT_BYTES: u64 = 1024;
+/// Same for a ref_details row and a tree_files row.
+const ESTIMATED_REF_BYTES: u64 = 200;
+const ESTIMATED_TREE_FILE_BYTES: u64 = 150;
+
+/// What an import would add, see `dry_run`.
+#[derive(Default)]
+pub struct DryRun {
+    pub commits: usize,
+    pub refs: usize,
+    pub tags: usize,
+    /// HEAD files with `--tree` or `--with-blobs`.
+    pub tree_files: usize,
+    pub blobs: usize,
+    pub blob_bytes: u64,
+    /// Rough database growth: blob contents plus a per-row size, per commit
+    /// measured from the database itself when it already has commits.
+    pub estimated_bytes: u64,
+}
+
+/// Counts what importing with `options` would insert without writing
+/// anything: the same walk as an import, minus extracting the commits, and
+/// the refs, tags, HEAD files and blobs that aren't stored yet.
+/// `walk_commits` is false for ref-only refreshes; `tree_files` and `blobs`
+/// say whether those would be stored.
+pub fn dry_run(
+    conn: &Connection,
+    repo: &Repository,
+    options: &ImportOptions,
+    refs: &RefFilter,
+    walk_commits: bool,
+    tree_files: bool,
+    blobs: Option<&BlobFilter>,
+) -> IngestResult<DryRun> {
+    let mut report = DryRun::default();
+    if walk_commits {
+        let within = match options.max_walk_depth {
+            Some(max_depth) => Some(commits_within_depth(repo, options.tip.oid, max_depth)?),
+            None => None,
+        };
+        report.commits = count_new_commits(
+            conn,
+            repo,
+            options,
+            within.as_ref(),
+            &mut FilterResults::new(),
+        )?;
+    }
+
+    let mut known_ref =
+        conn.prepare("SELECT 1 FROM ref_details WHERE repo_id = ?1 AND name = ?2 AND id = ?3")?;
+    let mut known_tag = conn.prepare("SELECT 1 FROM tags WHERE repo_id = ?1 AND name = ?2")?;
+    for reference in repo.references()?.flatten() {
+        if !refs.matches_reference(&reference) {
+            continue;
+        }
+        let details = extract_ref_details(&reference);
+        if !known_ref.exists(params![options.repo_id, details.name, details.id])? {
+            report.refs += 1;
+        }
+        if let Some(tag) = details.name.strip_prefix("refs/tags/") {
+            if !known_tag.exists(params![options.repo_id, tag])? {
+                report.tags += 1;
+            }
+        }
+    }
+
+    if tree_files || blobs.is_some() {
+        let entries = tree_entries(repo, &repo.head()?.peel_to_tree()?)?;
+        report.tree_files = entries.len();
+        if let Some(filter) = blobs {
+            let mut known_blob = conn.prepare("SELECT 1 FROM blobs WHERE blob_oid = ?1")?;
+            let mut seen = HashSet::new();
+            for entry in &entries {
+                let wanted = filter.paths.is_empty()
+                    || filter
+                        .paths
+                        .iter()
+                        .any(|pattern| glob::glob_match(pattern, &entry.path));
+                let too_big = filter
+                    .max_size
+                    .is_some_and(|max_size| entry.size as u64 > max_size);
+                if !wanted
+                    || too_big
+                    || !seen.insert(entry.blob_oid.as_str())
+                    || known_blob.exists(params![entry.blob_oid])?
+                {
+                    continue;
+                }
+                if filter.exclude_binary
+                    && repo.find_blob(Oid::from_str(&entry.blob_oid)?)?.is_binary()
+                {
+                    continue;
+                }
+                report.blobs += 1;
+                report.blob_bytes += entry.size as u64;
+            }
+        }
+    }
+
+    // What the stored commits take up on average, leaving out blob contents
+    // and the empty schema, which would swamp the figure for small databases.
+    let size_sql = "SELECT page_count * page_size FROM pragma_page_count, pragma_page_size";
+    let empty = Connection::open_in_memory()?;
+    schema::migrate(&empty)?;
+    let empty_bytes: i64 = empty.query_row(size_sql, [], |row| row.get(0))?;
+    let measured: Option<u64> = conn.query_row(
+        &format!(
+            "SELECT ({}) - (SELECT COALESCE(SUM(size), 0) FROM blobs),
+                    (SELECT COUNT(*) FROM commit_details)",
+            size_sql
+        ),
+        [],
+        |row| {
+            let (bytes, commits): (i64, i64) = (row.get(0)?, row.get(1)?);
+            let bytes = bytes - empty_bytes;
+            Ok((commits > 0 && bytes > 0).then(|| bytes as u64 / commits as u64))
+        },
+    )?;
+    let per_commit = measured.unwrap_or(ESTIMATED_COMMIT_BYTES);
+    report.estimated_bytes = report.commits as u64 * per_commit
+        + report.refs as u64 * ESTIMATED_REF_BYTES
+        + report.tree_files as u64 * ESTIMATED_TREE_FILE_BYTES
+        + report.blob_bytes;
+    Ok(report)
+}
+
+/// Lists every blob under `tree` with its full path.
+fn tree_entries(repo: &Repository, tree: &Tree) -> IngestResult<Vec<TreeEntry>> {
+    let odb = repo.odb()?;
+    let mut entries = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        // Subtrees are descended into and submodule commits have no blob.
+        if entry.kind() == Some(ObjectType::Blob) {
+            let size = odb.read_header(entry.id()).map_or(0, |(size, _)| size);
+            entries.push(TreeEntry {
+                path: format!("{}{}", root, entry.name().unwrap_or("")),
+                blob_oid: entry.id().to_string(),
+                size: size as i64,
+                mode: entry.filemode(),
+            });
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(entries)
+}
+
+/// Blames every HEAD file matching one of `patterns` (all files when empty)
+/// and stores one `file_blame` row per hunk: the 1-based line range and the
+/// commit that last touched it. Counts blamed and failed files.
+pub fn get_file_blame(
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    patterns: &[String],
+    failures: FailurePolicy,
+) -> IngestResult<WalkCounts> {
+    let matches =
+        |path: &str| patterns.is_empty() || patterns.iter().any(|p| glob::glob_match(p, path));
+    let tree = repo.head()?.peel_to_tree()?;
+
+    let mut paths = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            let path = format!("{}{}", root, entry.name().unwrap_or(""));
+            if matches(&path) {
+                paths.push(path);
+            }
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    // Drop the previous blame of everything the patterns cover, including
+    // files deleted since, so other paths blamed earlier are kept.
+    let stored: Vec<String> = conn
+        .prepare("SELECT DISTINCT path FROM file_blame WHERE repo_id = ?1")?
+        .query_map(params![repo_id], |row| row.get(0))?
+        .collect::<Result<_>>()?;
+    for path in stored.iter().filter(|path| matches(path)) {
+        conn.execute(
+            "DELETE FROM file_blame WHERE repo_id = ?1 AND path = ?2",
+            params![repo_id, path],
+        )?;
+    }
+
+    let mut counts = WalkCounts::default();
+    for path in &paths {
+        match blame_file(conn, repo, repo_id, path) {
+            Ok(()) => counts.added += 1,
+            Err(e) if failures.keep_going => {
+                failures.skip(conn, "file", Some(path), &e)?;
+                counts.skipped += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(counts)
+}
+
+fn blame_file(
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    path: &str,
+) -> IngestResult<()> {
+    let blame = repo.blame_file(Path::new(path), Some(&mut BlameOptions::new()))?;
+
+    let tx = conn.savepoint()?;
+    for hunk in blame.iter() {
+        let signature = hunk.final_signature();
+        tx.execute(
+            "INSERT INTO file_blame (repo_id, path, start_line, line_count, commit_id, author, author_email)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                repo_id,
+                path,
+                hunk.final_start_line() as i64,
+                hunk.lines_in_hunk() as i64,
+                hunk.final_commit_id().to_string(),
+                String::from_utf8_lossy(signature.name_bytes()),
+                String::from_utf8_lossy(signature.email_bytes()),
+            ],
+        )?;
+    }
+    Ok(tx.commit()?)
+}
+
+/// Records for every imported commit whether it is an ancestor of any of
+/// the remote-tracking tips under `refs/remotes/<remote>/` that `refs` keeps.
+/// A remote whose tips haven't moved since the last run (per remote_tips)
+/// only gets rows for the commits imported since, and one that moved
+/// forward is walked back to its stored tips; any other move is recomputed.
+pub fn compute_remote_reachability(
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    refs: &RefFilter,
+) -> IngestResult<()> {
+    let remotes: Vec<String> = repo.remotes()?.iter().flatten().map(String::from).collect();
+    let tx = conn.savepoint()?;
+
+    // Remotes removed from the repository take their rows with them.
+    let stored: Vec<String> = tx
+        .prepare("SELECT remote_name FROM remote_tips WHERE repo_id = ?1")?
+        .query_map(params![repo_id], |row| row.get(0))?
+        .collect::<Result<_>>()?;
+    for remote_name in stored.iter().filter(|name| !remotes.contains(name)) {
+        for table in ["commit_remote_reachability", "remote_tips"] {
+            tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE repo_id = ?1 AND remote_name = ?2",
+                    table
+                ),
+                params![repo_id, remote_name],
+            )?;
+        }
+    }
+
+    for remote_name in &remotes {
+        let mut tips = Vec::new();
+        for reference in repo
+            .references_glob(&format!("refs/remotes/{}/*", remote_name))?
+            .flatten()
+        {
+            if !refs.matches_reference(&reference) {
+                continue;
+            }
+            if let Ok(commit) = reference.peel_to_commit() {
+                tips.push(commit.id());
+            }
+        }
+        tips.sort();
+        tips.dedup();
+        let fingerprint = tips
+            .iter()
+            .map(Oid::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let stored_tips: Option<Vec<Oid>> = tx
+            .query_row(
+                "SELECT tips FROM remote_tips WHERE repo_id = ?1 AND remote_name = ?2",
+                params![repo_id, remote_name],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+            .map(|stored| {
+                stored
+                    .split(',')
+                    .filter_map(|oid| Oid::from_str(oid).ok())
+                    .collect()
+            });
+        // A remote that only moved forward keeps every reachable commit, so
+        // the walk can stop at the stored tips. Anything else is recomputed.
+        let (known_tips, gained) = match stored_tips.clone() {
+            Some(stored) if stored == tips => (stored, HashSet::new()),
+            Some(stored)
+                if stored.iter().all(|old| {
+                    tips.iter().any(|new| {
+                        new == old || repo.graph_descendant_of(*new, *old).unwrap_or(false)
+                    })
+                }) =>
+            {
+                let mut revwalk = repo.revwalk()?;
+                for tip in &tips {
+                    revwalk.push(*tip)?;
+                }
+                for tip in &stored {
+                    revwalk.hide(*tip)?;
+                }
+                let gained: HashSet<String> = revwalk
+                    .filter_map(|oid| oid.ok())
+                    .map(|oid| oid.to_string())
+                    .collect();
+                (stored, gained)
+            }
+            _ => {
+                tx.execute(
+                    "DELETE FROM commit_remote_reachability WHERE repo_id = ?1 AND remote_name = ?2",
+                    params![repo_id, remote_name],
+                )?;
+                let mut revwalk = repo.revwalk()?;
+                for tip in &tips {
+                    revwalk.push(*tip)?;
+                }
+                let reachable: HashSet<String> = revwalk
+                    .filter_map(|oid| oid.ok())
+                    .map(|oid| oid.to_string())
+                    .collect();
+                (Vec::new(), reachable)
+            }
+        };
+        if stored_tips.as_ref() != Some(&tips) {
+            tx.execute(
+                "INSERT OR REPLACE INTO remote_tips (repo_id, remote_name, tips) VALUES (?1, ?2, ?3)",
+                params![repo_id, remote_name, fingerprint],
+            )?;
+        }
+
+        let mut mark = tx.prepare(
+            "UPDATE commit_remote_reachability SET reachable = 1
+             WHERE repo_id = ?1 AND remote_name = ?2 AND commit_id = ?3 AND reachable = 0",
+        )?;
+        for commit_id in &gained {
+            mark.execute(params![repo_id, remote_name, commit_id])?;
+        }
+
+        let missing: Vec<String> = tx
+            .prepare(
+                "SELECT c.id FROM commit_details c
+                 WHERE c.repo_id = ?1 AND NOT EXISTS (
+                     SELECT 1 FROM commit_remote_reachability r
+                     WHERE r.repo_id = c.repo_id AND r.commit_id = c.id AND r.remote_name = ?2
+                 )",
+            )?
+            .query_map(params![repo_id, remote_name], |row| row.get(0))?
+            .collect::<Result<_>>()?;
+        let mut insert = tx.prepare(
+            "INSERT INTO commit_remote_reachability (repo_id, commit_id, remote_name, reachable)
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for commit_id in &missing {
+            // Commits imported since are usually newer than the stored tips,
+            // which makes the ancestry check short.
+            let reachable = gained.contains(commit_id)
+                || Oid::from_str(commit_id).is_ok_and(|oid| {
+                    known_tips.iter().any(|tip| {
+                        *tip == oid || repo.graph_descendant_of(*tip, oid).unwrap_or(false)
+                    })
+                });
+            insert.execute(params![repo_id, commit_id, remote_name, reachable])?;
+        }
+    }
+
+    Ok(tx.commit()?)
+}
+
+/// Hashes the sorted `(name, target)` list of every ref `refs` keeps so a
+/// later run can tell whether anything moved without walking history.
+pub fn refs_checksum(repo: &Repository, refs: &RefFilter) -> IngestResult<String> {
+    let mut entries: Vec<String> = repo
+        .references()?
+        .filter_map(|reference| reference.ok())
+        .filter(|reference| refs.matches_reference(reference))
+        .map(|reference| {
+            let target = match reference.target() {
+                Some(oid) => oid.to_string(),
+                None => reference.symbolic_target().unwrap_or("").to_string(),
+            };
+            format!("{} {}", reference.name().unwrap_or(""), target)
+        })
+        .collect();
+    entries.sort();
+
+    let checksum = Oid::hash_object(ObjectType::Blob, entries.join("\n").as_bytes())?;
+    Ok(checksum.to_string())
+}
+
+pub fn record_import_run(conn: &Connection, run: &ImportRun) -> Result<()> {
+    // Stored as a JSON array so arguments containing spaces survive.
+    let args: Vec<String> = run.args.iter().map(|arg| query::json_string(arg)).collect();
+    let state = &run.state;
+    conn.execute(
+        "INSERT INTO import_runs
+         (repo_id, started_at, finished_at, commits_added, refs_added, skipped, args, head_ref,
+          head_oid, head_detached, operation, staged_files, modified_files, untracked_files,
+          complete)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            run.repo_id,
+            run.started_at,
+            run.finished_at,
+            run.commits_added as i64,
+            run.refs_added as i64,
+            run.skipped as i64,
+            format!("[{}]", args.join(",")),
+            state.head_ref,
+            state.head_oid,
+            state.head_detached,
+            state.operation,
+            state.staged_files.map(|n| n as i64),
+            state.modified_files.map(|n| n as i64),
+            state.untracked_files.map(|n| n as i64),
+            run.complete,
+        ],
+    )?;
+    Ok(())
+}
+
+/// HEAD, any merge/rebase/... in progress and how dirty the worktree is.
+pub fn repository_state(repo: &Repository) -> IngestResult<RepoState> {
+    let head = repo.head().ok();
+    let head_detached = repo.head_detached().unwrap_or(false);
+    let operation = match repo.state() {
+        RepositoryState::Clean => "clean",
+        RepositoryState::Merge => "merge",
+        RepositoryState::Revert | RepositoryState::RevertSequence => "revert",
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => "cherry-pick",
+        RepositoryState::Bisect => "bisect",
+        RepositoryState::Rebase
+        | RepositoryState::RebaseInteractive
+        | RepositoryState::RebaseMerge => "rebase",
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => "am",
+    };
+
+    let (mut staged, mut modified, mut untracked) = (None, None, None);
+    if !repo.is_bare() {
+        let mut options = StatusOptions::new();
+        options.include_untracked(true).exclude_submodules(true);
+        let (mut s, mut m, mut u) = (0, 0, 0);
+        for entry in repo.statuses(Some(&mut options))?.iter() {
+            let status = entry.status();
+            if status.is_wt_new() {
+                u += 1;
+                continue;
+            }
+            if status.intersects(
+                Status::INDEX_NEW
+                    | Status::INDEX_MODIFIED
+                    | Status::INDEX_DELETED
+                    | Status::INDEX_RENAMED
+                    | Status::INDEX_TYPECHANGE,
+            ) {
+                s += 1;
+            }
+            if status.intersects(
+                Status::WT_MODIFIED
+                    | Status::WT_DELETED
+                    | Status::WT_RENAMED
+                    | Status::WT_TYPECHANGE
+                    | Status::CONFLICTED,
+            ) {
+                m += 1;
+            }
+        }
+        (staged, modified, untracked) = (Some(s), Some(m), Some(u));
+    }
+
+    Ok(RepoState {
+        head_ref: head
+            .as_ref()
+            .filter(|_| !head_detached)
+            .and_then(|head| head.name().map(str::to_string)),
+        head_oid: head
+            .and_then(|head| head.target())
+            .map(|oid| oid.to_string()),
+        head_detached,
+        operation,
+        staged_files: staged,
+        modified_files: modified,
+        untracked_files: untracked,
+    })
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// Switches to WAL with `synchronous=NORMAL`: commits no longer wait for
+/// fsync, so a power loss can drop the last transactions but never corrupts
+/// the database. WAL mode sticks to the database file.
+pub fn enable_fast_writes(conn: &Connection) -> Result<()> {
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    conn.pragma_update(None, "synchronous", "NORMAL")
+}
+
+pub fn get_repo_info(conn: &Connection, repo_id: i64, key: &str) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT value FROM repo_info WHERE repo_id = ?1 AND key = ?2",
+        params![repo_id, key],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn set_repo_info(conn: &Connection, repo_id: i64, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO repo_info (repo_id, key, value) VALUES (?1, ?2, ?3)",
+        params![repo_id, key, value],
+    )?;
+    Ok(())
+}
+
+/// The id of the repository registered as `name`, without registering it.
+pub fn find_repository(conn: &Connection, name: &str) -> Result<Option<i64>> {
+    conn.query_row(
+        "SELECT repo_id FROM repositories WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Adds `name` to `repositories`, or points the existing entry at `path`,
+/// and returns its `repo_id`.
+pub fn register_repository(conn: &Connection, name: &str, path: &Path) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO repositories (name, path) VALUES (?1, ?2)
+         ON CONFLICT (name) DO UPDATE SET path = excluded.path",
+        params![name, path.to_string_lossy()],
+    )?;
+    conn.query_row(
+        "SELECT repo_id FROM repositories WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+    )
+}
+
+/// Records the repository's submodules, keeping the child repository of
+/// ones still present and dropping the rest. Bare repositories have no
+/// checkout to read .gitmodules from and are skipped. Returns how many were
+/// found.
+pub fn refresh_submodules(
+    conn: &Connection,
+    repo: &Repository,
+    repo_id: i64,
+) -> IngestResult<usize> {
+    if repo.is_bare() {
+        return Ok(0);
+    }
+    let submodules = repo.submodules()?;
+    let mut stmt = conn.prepare(
+        "INSERT INTO submodules (repo_id, path, name, url, head_oid) VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (repo_id, path) DO UPDATE
+         SET name = excluded.name, url = excluded.url, head_oid = excluded.head_oid",
+    )?;
+    let mut paths = Vec::new();
+    for submodule in &submodules {
+        let path = submodule.path().to_string_lossy().into_owned();
+        stmt.execute(params![
+            repo_id,
+            &path,
+            submodule.name().unwrap_or(&path),
+            submodule.url(),
+            submodule.head_id().map(|oid| oid.to_string()),
+        ])?;
+        paths.push(path);
+    }
+
+    let mut existing = conn.prepare("SELECT path FROM submodules WHERE repo_id = ?1")?;
+    let removed: Vec<String> = existing
+        .query_map(params![repo_id], |row| row.get(0))?
+        .collect::<Result<Vec<String>>>()?
+        .into_iter()
+        .filter(|path| !paths.contains(path))
+        .collect();
+    for path in removed {
+        conn.execute(
+            "DELETE FROM submodules WHERE repo_id = ?1 AND path = ?2",
+            params![repo_id, path],
+        )?;
+    }
+    Ok(submodules.len())
+}
+
+/// Replaces the repository's commit_notes with the notes currently under
+/// each `refs/notes/*` ref. Returns how many were stored.
+pub fn refresh_notes(conn: &Connection, repo: &Repository, repo_id: i64) -> IngestResult<usize> {
+    conn.execute(
+        "DELETE FROM commit_notes WHERE repo_id = ?1",
+        params![repo_id],
+    )?;
+    let mut stmt = conn.prepare(
+        "INSERT INTO commit_notes (repo_id, notes_ref, commit_id, note_oid, message)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    let notes_refs: Vec<String> = repo
+        .references_glob("refs/notes/*")?
+        .flatten()
+        .filter_map(|reference| reference.name().map(str::to_string))
+        .collect();
+    let mut stored = 0;
+    for notes_ref in &notes_refs {
+        for entry in repo.notes(Some(notes_ref))? {
+            let (note_oid, annotated) = entry?;
+            let note = repo.find_note(Some(notes_ref), annotated)?;
+            stmt.execute(params![
+                repo_id,
+                notes_ref,
+                annotated.to_string(),
+                note_oid.to_string(),
+                String::from_utf8_lossy(note.message_bytes()),
+            ])?;
+            stored += 1;
+        }
+    }
+    Ok(stored)
+}
+
+/// Adds the HEAD and branch reflog entries not stored yet. Entries stay
+/// after git expires them, so rebases and resets remain visible. Returns how
+/// many were added.
+pub fn store_reflogs(conn: &Connection, repo: &Repository, repo_id: i64) -> IngestResult<usize> {
+    let mut names = vec![String::from("HEAD")];
+    names.extend(
+        repo.references_glob("refs/heads/*")?
+            .flatten()
+            .filter_map(|reference| reference.name().map(str::to_string)),
+    );
+    let mut stmt = conn.prepare(
+        "INSERT OR IGNORE INTO reflog
+         (repo_id, ref_name, old_oid, new_oid, message, committer_name, committer_email,
+          time, tz_offset)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    let mut added = 0;
+    for name in &names {
+        let reflog = repo.reflog(name)?;
+        for entry in reflog.iter() {
+            let committer = entry.committer();
+            added += stmt.execute(params![
+                repo_id,
+                name,
+                entry.id_old().to_string(),
+                entry.id_new().to_string(),
+                entry.message(),
+                committer.name(),
+                committer.email(),
+                committer.when().seconds(),
+                committer.when().offset_minutes(),
+            ])?;
+        }
+    }
+    Ok(added)
+}
+
+/// Replaces the repository's stashes, newest (index 0) first. With `diffs`
+/// their changes against the commit they were made on go to stash_files,
+/// patch text included with `with_patches`. Returns how many were found.
+pub fn refresh_stashes(
+    conn: &Connection,
+    repo: &Repository,
+    repo_id: i64,
+    diffs: bool,
+    with_patches: bool,
+) -> IngestResult<usize> {
+    // stash_foreach needs a mutable handle.
+    let mut stash_repo = Repository::open(repo.path())?;
+    let mut stashes = Vec::new();
+    stash_repo.stash_foreach(|index, message, oid| {
+        stashes.push((index, message.to_string(), *oid));
+        true
+    })?;
+
+    for table in ["stashes", "stash_files"] {
+        conn.execute(
+            &format!("DELETE FROM {} WHERE repo_id = ?1", table),
+            params![repo_id],
+        )?;
+    }
+    for (index, message, oid) in &stashes {
+        conn.execute(
+            "INSERT INTO stashes (repo_id, stash_index, message, commit_id) VALUES (?1, ?2, ?3, ?4)",
+            params![repo_id, *index as i64, message, oid.to_string()],
+        )?;
+        if !diffs {
+            continue;
+        }
+        let diff = diff_against_first_parent(repo, &repo.find_commit(*oid)?)?;
+        let mut files = file_changes(&diff, &CommitFilter::default())?;
+        if with_patches {
+            attach_file_patches(&diff, &mut files)?;
+        }
+        for file in files {
+            conn.execute(
+                "INSERT INTO stash_files
+                 (repo_id, stash_index, path, old_path, change_type, insertions, deletions, patch)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    repo_id,
+                    *index as i64,
+                    file.path,
+                    file.old_path,
+                    file.change_type,
+                    file.insertions as i64,
+                    file.deletions as i64,
+                    file.patch
+                ],
+            )?;
+        }
+    }
+    Ok(stashes.len())
+}
+
+pub fn link_submodule(
+    conn: &Connection,
+    repo_id: i64,
+    path: &str,
+    child_repo_id: i64,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE submodules SET child_repo_id = ?3 WHERE repo_id = ?1 AND path = ?2",
+        params![repo_id, path, child_repo_id],
+    )?;
+    Ok(())
+}
+
+/// Default alias for a repository: its working directory name, or the
+/// bare repository's directory name without `.git`.
+pub fn repository_name(repo: &Repository) -> String {
+    let dir = repo.workdir().unwrap_or_else(|| repo.path());
+    dir.file_name()
+        .map(|name| name.to_string_lossy().trim_end_matches(".git").to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("default"))
+}
@@ -0,0 +1,161 @@
+use crate::error::IngestResult;
+use crate::language::language;
+use crate::RefFilter;
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+
+/// All lines, non-blank lines and bytes of one blob.
+type BlobLines = (usize, usize, usize);
+
+/// Totals for one language in one top-level directory.
+#[derive(Default)]
+struct Totals {
+    files: usize,
+    lines: usize,
+    code_lines: usize,
+    bytes: usize,
+}
+
+/// Stores a loc_snapshots breakdown for every tag passing `ref_filter` and
+/// every ref in `refs` (names or revisions like `main`). A ref whose commit
+/// already has a snapshot is skipped, so tags are only ever counted once and
+/// branches again when they move. Returns how many snapshots were taken.
+pub fn snapshot_refs(
+    conn: &Connection,
+    repo: &Repository,
+    repo_id: i64,
+    ref_filter: &RefFilter,
+    refs: &[String],
+    taken_at: i64,
+) -> IngestResult<usize> {
+    let mut targets: Vec<(String, Oid)> = Vec::new();
+    for reference in repo.references_glob("refs/tags/*")?.flatten() {
+        if !ref_filter.matches_reference(&reference) {
+            continue;
+        }
+        // Tags of trees or blobs have no commit to count.
+        if let (Some(name), Ok(commit)) = (reference.name(), reference.peel_to_commit()) {
+            targets.push((name.to_string(), commit.id()));
+        }
+    }
+    for name in refs {
+        let (object, reference) = repo.revparse_ext(name)?;
+        let name = reference
+            .as_ref()
+            .and_then(|reference| reference.name())
+            .unwrap_or(name)
+            .to_string();
+        targets.push((name, object.peel_to_commit()?.id()));
+    }
+
+    let mut cache: HashMap<Oid, Option<BlobLines>> = HashMap::new();
+    let mut taken = 0;
+    for (ref_name, oid) in targets {
+        let stored: Option<String> = conn
+            .query_row(
+                "SELECT commit_id FROM loc_snapshots WHERE repo_id = ?1 AND ref_name = ?2 LIMIT 1",
+                params![repo_id, ref_name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if stored.as_deref() == Some(oid.to_string().as_str()) {
+            continue;
+        }
+
+        let totals = count_tree(repo, oid, &mut cache)?;
+        let commit_time = repo.find_commit(oid)?.time().seconds();
+        conn.execute(
+            "DELETE FROM loc_snapshots WHERE repo_id = ?1 AND ref_name = ?2",
+            params![repo_id, ref_name],
+        )?;
+        let mut insert = conn.prepare_cached(
+            "INSERT INTO loc_snapshots
+             (repo_id, ref_name, commit_id, commit_time, language, directory, files, lines,
+              code_lines, bytes, taken_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        for ((language, directory), totals) in &totals {
+            insert.execute(params![
+                repo_id,
+                ref_name,
+                oid.to_string(),
+                commit_time,
+                language,
+                directory,
+                totals.files as i64,
+                totals.lines as i64,
+                totals.code_lines as i64,
+                totals.bytes as i64,
+                taken_at,
+            ])?;
+        }
+        taken += 1;
+    }
+    Ok(taken)
+}
+
+/// Sums the text files of the commit's tree by language and top-level
+/// directory ("." for files at the root). Binary files are left out.
+fn count_tree(
+    repo: &Repository,
+    oid: Oid,
+    cache: &mut HashMap<Oid, Option<BlobLines>>,
+) -> IngestResult<HashMap<(Option<&'static str>, String), Totals>> {
+    let tree = repo.find_commit(oid)?.tree()?;
+    let mut files = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            files.push((
+                format!("{}{}", root, entry.name().unwrap_or("")),
+                entry.id(),
+            ));
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    let mut totals: HashMap<(Option<&'static str>, String), Totals> = HashMap::new();
+    for (path, blob_oid) in files {
+        let counted = match cache.get(&blob_oid) {
+            Some(counted) => *counted,
+            None => {
+                let blob = repo.find_blob(blob_oid)?;
+                let counted = (!blob.is_binary()).then(|| count_lines(blob.content()));
+                cache.insert(blob_oid, counted);
+                counted
+            }
+        };
+        let Some((lines, code_lines, bytes)) = counted else {
+            continue;
+        };
+        let directory = match path.split_once('/') {
+            Some((directory, _)) => directory.to_string(),
+            None => String::from("."),
+        };
+        let entry = totals.entry((language(&path), directory)).or_default();
+        entry.files += 1;
+        entry.lines += lines;
+        entry.code_lines += code_lines;
+        entry.bytes += bytes;
+    }
+    Ok(totals)
+}
+
+fn count_lines(content: &[u8]) -> BlobLines {
+    if content.is_empty() {
+        return (0, 0, 0);
+    }
+    let mut lines = 0;
+    let mut code_lines = 0;
+    for line in content.split(|&b| b == b'\n') {
+        lines += 1;
+        if line.iter().any(|b| !b.is_ascii_whitespace()) {
+            code_lines += 1;
+        }
+    }
+    // A trailing newline ends the last line rather than starting another.
+    if content.last() == Some(&b'\n') {
+        lines -= 1;
+    }
+    (lines, code_lines, content.len())
+}
@@ -0,0 +1,174 @@
+use git2::Signature;
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats a signature the way `git log --format=%an <%ae>` prints it.
+pub fn log_author(signature: &Signature) -> String {
+    format!(
+        "{} <{}>",
+        String::from_utf8_lossy(signature.name_bytes()),
+        String::from_utf8_lossy(signature.email_bytes())
+    )
+}
+
+/// Formats a signature's time in git's default date format, e.g.
+/// `Thu Oct 15 18:27:45 2026 +0000`, in the signature's own timezone.
+pub fn log_date(signature: &Signature) -> String {
+    let when = signature.when();
+    let offset = when.offset_minutes();
+    let local = when.seconds() + i64::from(offset) * 60;
+
+    let days = local.div_euclid(86_400);
+    let secs_of_day = local.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}{:02}{:02}",
+        weekday,
+        MONTHS[(month - 1) as usize],
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        year,
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian `(year, month, day)`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Parses `YYYY-MM-DD` (midnight UTC) or plain UNIX seconds.
+pub fn parse_date(value: &str) -> Option<i64> {
+    if let Ok(seconds) = value.parse() {
+        return Some(seconds);
+    }
+    let mut parts = value.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86_400)
+}
+
+/// Parses an RFC 3339 timestamp as APIs send them, e.g.
+/// `2024-05-01T12:30:00Z` or `2024-05-01T14:30:00.000+02:00`, into UNIX
+/// seconds.
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    let (date, time) = value.split_once('T')?;
+    let days = parse_date(date)? / 86_400;
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => (time, ""),
+    };
+    let mut parts = clock.split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    // Fractions of a second are dropped.
+    let seconds: i64 = parts
+        .next()
+        .unwrap_or("0")
+        .split('.')
+        .next()?
+        .parse()
+        .ok()?;
+    let offset = match offset.chars().next() {
+        Some(sign @ ('+' | '-')) => {
+            let (h, m) = offset[1..].split_once(':').unwrap_or((&offset[1..], "0"));
+            let minutes = h.parse::<i64>().ok()? * 60 + m.parse::<i64>().ok()?;
+            if sign == '-' {
+                -minutes
+            } else {
+                minutes
+            }
+        }
+        _ => 0,
+    };
+    Some(days * 86_400 + hours * 3600 + minutes * 60 + seconds - offset * 60)
+}
+
+/// Inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Time;
+
+    #[test]
+    fn formats_like_git_log() {
+        let cases = [
+            (0, 0, "Thu Jan 1 00:00:00 1970 +0000"),
+            (1_700_000_000, 0, "Tue Nov 14 22:13:20 2023 +0000"),
+            (1_700_000_000, 120, "Wed Nov 15 00:13:20 2023 +0200"),
+            (1_700_000_000, -330, "Tue Nov 14 16:43:20 2023 -0530"),
+            // Leap day.
+            (951_782_400, 0, "Tue Feb 29 00:00:00 2000 +0000"),
+        ];
+        for (seconds, offset, expected) in cases {
+            let signature =
+                Signature::new("Jane", "jane@example.com", &Time::new(seconds, offset)).unwrap();
+            assert_eq!(log_date(&signature), expected);
+            assert_eq!(log_author(&signature), "Jane <jane@example.com>");
+        }
+    }
+
+    #[test]
+    fn parses_dates() {
+        let cases = [
+            ("1970-01-01", Some(0)),
+            ("2000-02-29", Some(951_782_400)),
+            ("1700000000", Some(1_700_000_000)),
+            ("2024-13-01", None),
+            ("2024-01", None),
+            ("yesterday", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_date(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn parses_rfc3339_timestamps() {
+        let cases = [
+            ("2023-11-14T22:13:20Z", Some(1_700_000_000)),
+            ("2023-11-14T22:13:20.123Z", Some(1_700_000_000)),
+            ("2023-11-15T00:13:20+02:00", Some(1_700_000_000)),
+            ("2023-11-14T16:43:20-05:30", Some(1_700_000_000)),
+            ("2023-11-14T22:13:20", Some(1_700_000_000)),
+            ("2023-11-14", None),
+            ("2023-11-14Tnoon", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_timestamp(value), expected, "{}", value);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::json::Json;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// How much gets logged, most important first. Info is the default, `-v`
+/// adds Debug and `-vv` Trace.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn from_verbosity(verbosity: u8) -> Level {
+        match verbosity {
+            0 => Level::Info,
+            1 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Plain lines as a person would read them.
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Sets the most verbose level written and the line format. Until called,
+/// Info and above are logged as text.
+pub fn init(level: Level, format: LogFormat) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Writes one event to stderr, keeping stdout for command output. Use the
+/// `error!` ... `trace!` macros, which skip formatting for disabled levels.
+pub fn write(level: Level, message: fmt::Arguments, span: Option<&str>, fields: &[(&str, u64)]) {
+    let line = if is_json() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let mut members = vec![
+            (String::from("ts"), Json::Number(timestamp as f64)),
+            (
+                String::from("level"),
+                Json::String(level.name().to_string()),
+            ),
+            (String::from("message"), Json::String(message.to_string())),
+        ];
+        if let Some(span) = span {
+            members.push((String::from("span"), Json::String(span.to_string())));
+        }
+        for (key, value) in fields {
+            members.push((key.to_string(), Json::Number(*value as f64)));
+        }
+        Json::Object(members).to_json()
+    } else {
+        let prefix = match level {
+            Level::Error => "Error: ",
+            Level::Warn => "Warning: ",
+            Level::Info => "",
+            Level::Debug => "debug: ",
+            Level::Trace => "trace: ",
+        };
+        let mut line = format!("{}{}", prefix, message);
+        for (key, value) in fields {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+        line
+    };
+    let _ = writeln!(io::stderr(), "{}", line);
+}
+
+/// A timed phase of an import. Logs `message` when it starts and, once
+/// finished, how long it took along with the recorded counts.
+pub struct Span {
+    name: &'static str,
+    started: Instant,
+    fields: Vec<(&'static str, u64)>,
+}
+
+pub fn span(name: &'static str, message: fmt::Arguments) -> Span {
+    if enabled(Level::Info) {
+        write(Level::Info, message, Some(name), &[]);
+    }
+    Span {
+        name,
+        started: Instant::now(),
+        fields: Vec::new(),
+    }
+}
+
+impl Span {
+    pub fn record(&mut self, key: &'static str, value: usize) {
+        self.fields.push((key, value as u64));
+    }
+
+    /// Not done on drop: a phase that failed with `?` didn't finish.
+    pub fn finish(mut self) {
+        if enabled(Level::Info) {
+            let elapsed = self.started.elapsed();
+            // Text lines already say how long it took.
+            if is_json() {
+                self.fields.push(("elapsed_ms", elapsed.as_millis() as u64));
+            }
+            write(
+                Level::Info,
+                format_args!("Done in {:.2}s.", elapsed.as_secs_f64()),
+                Some(self.name),
+                &self.fields,
+            );
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::logging::enabled($level) {
+            $crate::logging::write($level, format_args!($($arg)+), None, &[]);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log_at!($crate::logging::Level::Trace, $($arg)+) };
+}
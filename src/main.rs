@@ -1,11 +1,16 @@
-extern crate git2;
-extern crate rusqlite;
-
-use git2::{Commit, Oid, Reference, Repository};
-use rusqlite::{params, Connection, Result};
+use git2::{Oid, Repository};
+use git_info_llama::cli::{Cli, Command, OutputFormat, StatsReport};
+use git_info_llama::error::IngestResult;
+use git_info_llama::progress::{ConsoleProgress, ProgressSink};
+use git_info_llama::*;
+use rusqlite::Connection;
+use std::collections::HashMap;
 use std::env;
+use std::error::Error;
+use std::fmt::Display;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::thread;
 
 fn to_absolute_path<P: AsRef<Path>>(path: P) -> std::io::Result<std::path::PathBuf> {
     let path = path.as_ref();
@@ -19,203 +24,835 @@ fn to_absolute_path<P: AsRef<Path>>(path: P) -> std::io::Result<std::path::PathB
     }
 }
 
+/// Opens the repository argument. A URL is cloned into `clone_dir` (or the
+/// default cache directory) first, or fetched when that clone already exists.
+fn open_repository(location: &str, clone_dir: Option<&str>) -> IngestResult<(Repository, PathBuf)> {
+    if clone::is_remote_url(location) {
+        let dir = match clone_dir {
+            Some(dir) => to_absolute_path(dir)?,
+            None => clone::default_clone_dir(location),
+        };
+        let repo = clone::clone_or_fetch(location, &dir)?;
+        return Ok((repo, dir));
+    }
+    let path = to_absolute_path(location)?;
+    Ok((Repository::open(&path)?, path))
+}
+
+/// Unwraps `result` or prints the error and exits, instead of panicking.
+fn or_exit<T, E: Display>(result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let clis = match Cli::parse() {
+        Ok(clis) => clis,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    logging::init(
+        logging::Level::from_verbosity(clis[0].verbosity),
+        clis[0].log_format,
+    );
+
+    // Several when a config file lists repositories; watch handles them all
+    // in one loop and never returns.
+    for cli in &clis {
+        run(cli, &clis);
+    }
+}
+
+fn run(cli: &Cli, clis: &[Cli]) {
+    if !matches!(
+        cli.command,
+        Command::Import
+            | Command::CheckDrift
+            | Command::Blame
+            | Command::Watch(_)
+            | Command::Migrate
+            | Command::Embed
+            | Command::Enrich(_)
+            | Command::Diverge(..)
+            | Command::Prune
+            | Command::RemapAuthors
+    ) {
+        // Read-only commands must never create an empty database by accident.
+        if !Path::new(&cli.db_path).is_file() {
+            error!(
+                "database {} does not exist, run an import first",
+                cli.db_path
+            );
+            std::process::exit(1);
+        }
+        if let Command::Serve = cli.command {
+            or_exit(serve::serve(&cli.db_path, &cli.listen));
+            return;
+        }
+        if let Command::Mcp = cli.command {
+            or_exit(mcp::serve_stdio(&cli.db_path));
+            return;
+        }
+        let conn = or_exit(query::open_read_only(&cli.db_path));
+        if let Command::Export(out) = &cli.command {
+            if let Err(e) = export::export(&conn, &cli.format, out.as_deref(), &cli.select_columns)
+            {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        if let Command::ExportOwnership(author, out) = &cli.command {
+            if let Err(e) = export::ownership(
+                &conn,
+                author,
+                &cli.format,
+                out.as_deref(),
+                &cli.select_columns,
+            ) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        if let Command::ExportGraph(out) = &cli.command {
+            let options = graph::GraphOptions {
+                merges_only: cli.merges_only,
+                ref_name: cli.graph_ref.clone(),
+            };
+            if let Err(e) = graph::export_graph(&conn, &cli.format, out.as_deref(), &options) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        if let Command::Stats(report) = &cli.command {
+            if let Err(e) = query::check_schema(&conn, report) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        if let Command::Search(_) = &cli.command {
+            or_exit(query::check_search_index(&conn));
+        }
+        if let Command::Similar(text) = &cli.command {
+            let embedder = or_exit(embed::embedder(&cli.embedder, cli.embed_model.as_deref()));
+            let result = or_exit(embed::similar(
+                &conn,
+                embedder.as_ref(),
+                text,
+                &cli.embed_targets,
+                cli.limit.unwrap_or(10),
+            ));
+            query::print_result(&result, &cli.format);
+            return;
+        }
+        let result = match &cli.command {
+            Command::Query(sql) => query::run_query(&conn, sql),
+            Command::AuthorTimeline(author) => query::author_timeline(&conn, author),
+            Command::History(path) => query::file_history(&conn, path, None),
+            Command::Stats(report) => query::stats(&conn, report, &stats_options(cli)),
+            Command::Search(search) => query::search(&conn, search, cli.search_patches),
+            Command::Import
+            | Command::CheckDrift
+            | Command::Blame
+            | Command::Watch(_)
+            | Command::Migrate
+            | Command::Embed
+            | Command::Enrich(_)
+            | Command::Similar(_)
+            | Command::Serve
+            | Command::Mcp
+            | Command::Diverge(..)
+            | Command::Prune
+            | Command::RemapAuthors
+            | Command::Export(_)
+            | Command::ExportOwnership(..)
+            | Command::ExportGraph(_) => unreachable!(),
+        };
+        match result {
+            Ok(mut result) => {
+                if let Some(limit) = cli.limit {
+                    result.rows.truncate(limit);
+                }
+                match (&cli.command, &cli.format) {
+                    (
+                        Command::Stats(StatsReport::AuthorNetwork),
+                        OutputFormat::Json | OutputFormat::Dot,
+                    ) => graph::print_author_network(&result, &cli.format),
+                    _ => query::print_result(&result, &cli.format),
+                }
+            }
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let repository_path = args.get(1).map_or(".", |s| s.as_str());
-    let db_path = args.get(2).map_or("git_info_llama.db", |s| s.as_str());
+    let repository_path = cli.repository_path.as_str();
+    let db_path = cli.db_path.as_str();
+
+    if let Command::CheckDrift = cli.command {
+        let (repo, _) = or_exit(open_repository(repository_path, cli.clone_dir.as_deref()));
+        let name = repo_name(cli, repository_path, &repo);
+        let stored = query::open_read_only(db_path).ok().and_then(|conn| {
+            let repo_id = find_repository(&conn, &name).ok().flatten()?;
+            get_repo_info(&conn, repo_id, "refs_checksum")
+                .ok()
+                .flatten()
+        });
+
+        if stored.as_deref() == Some(or_exit(refs_checksum(&repo, &ref_filter(cli))).as_str()) {
+            println!("Refs unchanged since last import.");
+            return;
+        }
+        println!("Refs changed since last import.");
+        std::process::exit(1);
+    }
 
     let db_exists = fs::metadata(db_path).is_ok();
-    let mut conn = Connection::open(db_path).expect("Failed to open database");
-
-    // Check if the database file exists
-    if !db_exists {
-        // Call the create_database function to initialize your database tables.
-        match create_database(&conn) {
-            Ok(_) => println!("Database and tables created successfully!"),
-            Err(e) => eprintln!("Error: {}", e),
+    if cli.dry_run {
+        if let Command::Import = cli.command {
+            or_exit(report_dry_run(cli, repository_path, db_exists));
+            return;
         }
     }
+    if matches!(
+        cli.command,
+        Command::Migrate | Command::Embed | Command::Enrich(_)
+    ) && !db_exists
+    {
+        error!("database {} does not exist", db_path);
+        std::process::exit(1);
+    }
+    let mut conn = or_exit(open_for_import(db_path, cli.fast, db_exists));
+
+    if let Command::Migrate = cli.command {
+        println!(
+            "Database schema is at version {}.",
+            or_exit(schema::schema_version(&conn))
+        );
+        return;
+    }
 
-    let path = to_absolute_path(repository_path).expect("Failed to get absolute path.");
-    let repo = Repository::open(&path).expect("Failed to open the repository.");
+    if let Command::Embed = cli.command {
+        let embedder = or_exit(embed::embedder(&cli.embedder, cli.embed_model.as_deref()));
+        let added = or_exit(embed::embed_missing(
+            &mut conn,
+            embedder.as_ref(),
+            &cli.embed_targets,
+        ));
+        info!("Embedded {} items with {}.", added, embedder.model());
+        return;
+    }
 
-    println!("Getting Commit Details...");
-    get_commits_detail_array(&mut conn, &repo);
-    println!("Done!");
+    if let Command::Enrich(remote) = &cli.command {
+        let project = or_exit(enrich::parse_remote(remote));
+        let token = cli.token.clone().or_else(|| {
+            let variable = match project.forge {
+                enrich::Forge::GitHub => "GITHUB_TOKEN",
+                enrich::Forge::GitLab => "GITLAB_TOKEN",
+            };
+            env::var(variable).ok()
+        });
+        let stored = or_exit(enrich::enrich(
+            &conn,
+            &project,
+            cli.api_url.as_deref(),
+            token.as_deref(),
+            cli.limit,
+            unix_now(),
+        ));
+        let linked: i64 = or_exit(conn.query_row(
+            "SELECT COUNT(*) FROM pull_requests p
+             WHERE project = ?1
+               AND EXISTS (SELECT 1 FROM commit_details c WHERE c.id = p.merge_commit_id)",
+            [&project.path],
+            |row| row.get(0),
+        ));
+        info!(
+            "Stored {} pull requests, {} linked to imported commits.",
+            stored, linked
+        );
+        return;
+    }
 
-    println!("Getting Ref Details...");
-    get_ref_details(&mut conn, &repo);
-    println!("Done!");
-}
+    if let Command::Watch(_) = cli.command {
+        watch(clis, &mut conn);
+    }
 
-struct CommitDetails {
-    id: String,
-    author: String,
-    date: i64, // UNIX timestamp for simplicity, but can use a more detailed type if desired.
-    message: String,
-    parents: Vec<Oid>,
-}
-struct RefDetails {
-    name: String,
-    id: String,
-    kind: String,
-}
+    let (repo, path) = or_exit(open_repository(repository_path, cli.clone_dir.as_deref()));
+    debug!("opened repository at {}", path.display());
+    let repo_id = or_exit(register(cli, &conn, repository_path, &repo, &path));
+
+    if let Command::Blame = cli.command {
+        let mut span = logging::span("blame", format_args!("Blaming Files..."));
+        let counts = or_exit(get_file_blame(
+            &mut conn,
+            &repo,
+            repo_id,
+            &cli.paths,
+            failure_policy(cli),
+        ));
+        span.record("blamed", counts.added);
+        span.record("failed", counts.skipped);
+        span.finish();
+        return;
+    }
 
-fn create_database(conn: &Connection) -> rusqlite::Result<()> {
-    conn.execute(
-        "CREATE TABLE commit_details (
-            id TEXT PRIMARY KEY,
-            author TEXT NOT NULL,
-            date INTEGER NOT NULL,
-            message TEXT NOT NULL
-        )",
-        {},
-    )?;
+    if let Command::Prune = cli.command {
+        // Recorded first, so ref_history keeps the deletions.
+        or_exit(record_ref_history(
+            &conn,
+            &repo,
+            repo_id,
+            unix_now(),
+            &ref_filter(cli),
+        ));
+        let mut counts = prune::PruneCounts::default();
+        or_exit(prune::prune_refs(&conn, &repo, repo_id, &mut counts));
+        if cli.prune_unreachable {
+            let archived_at = cli.archive.then(unix_now);
+            or_exit(prune::prune_unreachable(
+                &mut conn,
+                &repo,
+                repo_id,
+                &ref_filter(cli),
+                archived_at,
+                &mut counts,
+            ));
+        }
+        info!(
+            "Pruned {} refs and {} tags; {} {} unreachable commits.",
+            counts.refs,
+            counts.tags,
+            if cli.archive { "archived" } else { "removed" },
+            counts.commits
+        );
+        return;
+    }
 
-    conn.execute(
-        "CREATE TABLE commit_relation (
-            parent TEXT NOT NULL,
-            child TEXT NOT NULL,
-            PRIMARY KEY (parent, child)
-        )",
-        {},
-    )?;
+    if let Command::RemapAuthors = cli.command {
+        let updated = or_exit(assign_author_ids(&conn, &repo, repo_id, true));
+        info!("Remapped the author of {} commits.", updated);
+        return;
+    }
 
-    conn.execute(
-        "CREATE TABLE ref_details (
-            name TEXT NOT NULL,
-            id TEXT NOT NULL,
-            kind TEXT NOT NULL,
-            PRIMARY KEY (name, id)
-        )",
-        {},
-    )?;
+    if let Command::Diverge(left, right) = &cli.command {
+        let divergence = or_exit(divergence::compute(&repo, left, right));
+        or_exit(divergence::store(&conn, repo_id, &divergence, unix_now()));
+        let commits = or_exit(divergence::commits_result(&repo, &divergence));
+        // The summary line would make the JSON unparseable.
+        if let OutputFormat::Table = cli.format {
+            let merge_base = divergence
+                .merge_base
+                .map_or_else(|| String::from("none"), |oid| oid.to_string());
+            println!(
+                "Merge base {}: {} is {} ahead and {} behind {}.",
+                merge_base,
+                left,
+                divergence.ahead(),
+                divergence.behind(),
+                right
+            );
+        }
+        query::print_result(&commits, &cli.format);
+        return;
+    }
 
-    Ok(())
+    or_exit(import(cli, &mut conn, &repo, repo_id, db_exists));
 }
 
-fn get_commits_detail_array(conn: &mut Connection, repo: &Repository) {
-    let mut revwalk = repo.revwalk().expect("Failed to get revwalk.");
-    revwalk.push_head().expect("Failed to push head.");
+fn open_for_import(db_path: &str, fast: bool, db_exists: bool) -> IngestResult<Connection> {
+    let conn = Connection::open(db_path)?;
+    if fast {
+        enable_fast_writes(&conn)?;
+    }
 
-    let all_commits: Vec<_> = revwalk.collect();
+    // New databases get every table; older ones are upgraded in place.
+    schema::migrate(&conn)?;
+    if !db_exists {
+        info!("Database and tables created successfully!");
+    }
+    Ok(conn)
+}
+
+/// Adds alternates and registers the repository under `--name`, or a name
+/// derived from its URL or directory.
+fn register(
+    cli: &Cli,
+    conn: &Connection,
+    location: &str,
+    repo: &Repository,
+    path: &Path,
+) -> IngestResult<i64> {
+    add_alternates(repo, &cli.alternates)?;
+    Ok(register_repository(
+        conn,
+        &repo_name(cli, location, repo),
+        path,
+    )?)
+}
+
+/// `--name`, or a name derived from the URL or directory.
+fn repo_name(cli: &Cli, location: &str, repo: &Repository) -> String {
+    cli.repo_name.clone().unwrap_or_else(|| {
+        if clone::is_remote_url(location) {
+            clone::name_from_url(location)
+        } else {
+            repository_name(repo)
+        }
+    })
+}
 
-    for chunk in all_commits.chunks(50) {
-        let mut chunk_commits = Vec::new();
+fn stats_options(cli: &Cli) -> query::StatsOptions {
+    query::StatsOptions {
+        exclude_generated: cli.exclude_generated,
+        resolve_ambiguous_refs: cli.resolve_ambiguous_refs,
+        paths: cli.paths.clone(),
+        limit: cli.limit,
+    }
+}
 
-        for oid in chunk {
-            match oid {
-                Ok(oid) => {
-                    let commit = repo.find_commit(*oid).expect("Failed to find commit.");
-                    let formatted_commit = extract_commit_details(&commit);
+fn failure_policy(cli: &Cli) -> FailurePolicy {
+    FailurePolicy {
+        quiet: cli.quiet_skipped,
+        keep_going: cli.keep_going,
+    }
+}
 
-                    chunk_commits.push(formatted_commit);
+/// Re-imports every location whose refs changed, then sleeps for the
+/// interval, until killed. URLs are fetched each round, local repositories
+/// are only compared against their refs at the previous round. A failing
+/// round is reported and retried next time instead of stopping the loop.
+/// Each config file repository comes with its own options.
+fn watch(clis: &[Cli], conn: &mut Connection) -> ! {
+    let mut last_checksums: HashMap<&str, String> = HashMap::new();
+    loop {
+        for cli in clis {
+            let Command::Watch(locations) = &cli.command else {
+                continue;
+            };
+            for location in locations {
+                let last = last_checksums.get(location.as_str()).map(String::as_str);
+                match watch_once(cli, conn, location, last) {
+                    Ok(checksum) => {
+                        last_checksums.insert(location, checksum);
+                    }
+                    Err(e) => error!("importing {} failed: {}", location, e),
                 }
-                Err(e) => println!("Failed to process commit: {}", e),
             }
         }
-        batch_insert_commits(conn, &chunk_commits).expect("Failed to insert commits.");
+        thread::sleep(clis[0].watch_interval);
+    }
+}
+
+/// One watch round for one location; returns the refs checksum it saw.
+fn watch_once(
+    cli: &Cli,
+    conn: &mut Connection,
+    location: &str,
+    last_checksum: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let (repo, path) = open_repository(location, cli.clone_dir.as_deref())?;
+    let checksum = refs_checksum(&repo, &ref_filter(cli))?;
+    if last_checksum != Some(checksum.as_str()) {
+        info!("Importing {}...", location);
+        let repo_id = register(cli, conn, location, &repo, &path)?;
+        import(cli, conn, &repo, repo_id, true)?;
     }
+    Ok(checksum)
 }
 
-fn extract_commit_details(commit: &Commit) -> CommitDetails {
-    let id = commit.id().to_string();
-    let author = commit.author().name().unwrap_or("Unknown").to_string();
-    let date = commit.time().seconds();
-    let message = commit.message().unwrap_or("No message").to_string();
-    //array of parents;
-    let parents = commit.parent_ids().collect::<Vec<_>>();
+/// Imports commits and refs of one repository and records the run.
+fn import(
+    cli: &Cli,
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    db_exists: bool,
+) -> Result<(), Box<dyn Error>> {
+    let started_at = unix_now();
+    let failures = failure_policy(cli);
+    report_commit_graph(repo);
+    let state = repository_state(repo)?;
+    if state.operation != "clean" {
+        warn!("a {} is in progress in this repository.", state.operation);
+    }
+
+    let since_commit = since_commit(cli, repo)?;
+    let branch_tip = branch_tip(cli, repo)?;
+
+    if cli.single_transaction {
+        warn!("--single-transaction commits only at the end, a crash loses the whole import.");
+        conn.execute_batch("BEGIN")?;
+    }
 
-    return CommitDetails {
-        id,
-        author,
-        date,
-        message,
-        parents,
+    let console = ConsoleProgress::new();
+    // Redrawn bars would break up JSON log lines.
+    let progress: Option<&dyn ProgressSink> = if cli.quiet || logging::is_json() {
+        None
+    } else {
+        Some(&console)
     };
-}
 
-fn batch_insert_commits(conn: &mut Connection, commits: &Vec<CommitDetails>) -> Result<()> {
-    let insert_sql =
-        "INSERT INTO commit_details (id, author, date, message) VALUES (?1, ?2, ?3, ?4)";
+    let mut commit_counts = WalkCounts::default();
+    let ref_counts;
+    let tip = match branch_tip {
+        Some(tip) => Ok(tip),
+        None => WalkTip::head(repo),
+    };
+    // Only the refs moved when the last walk completed at the current tip
+    // and every other ref points at an imported commit. A bounded or
+    // interrupted walk recorded no tip, so the next run walks again.
+    let up_to_date = db_exists
+        && cli.on_conflict == OnConflict::Ignore
+        && match &tip {
+            Ok(tip) => tip_synced(conn, repo_id, tip)?,
+            Err(_) => false,
+        }
+        && ref_targets_imported(conn, repo, repo_id, &ref_filter(cli))?;
+    // --refresh-refs-only still finishes a walk that left commits behind.
+    let pending = cli.refresh_refs_only
+        && !up_to_date
+        && match &tip {
+            Ok(tip) => has_unimported_commits(conn, repo, repo_id, tip)?,
+            Err(_) => false,
+        };
+    if pending {
+        info!("Some commits aren't imported yet, walking them first.");
+    }
+    if (cli.refresh_refs_only && !pending) || up_to_date {
+        let mut span = logging::span(
+            "refs",
+            format_args!("No new commits, refreshing Ref Details..."),
+        );
+        // Either way nothing reachable from the tip is missing.
+        commit_counts.complete = true;
+        ref_counts = refresh_ref_details(
+            conn,
+            repo,
+            repo_id,
+            &ref_filter(cli),
+            failures,
+            cli.on_conflict,
+            progress,
+        )?;
+        compute_remote_reachability(conn, repo, repo_id, &ref_filter(cli))?;
+        span.record("added", ref_counts.added);
+        span.record("skipped", ref_counts.skipped);
+        span.finish();
+    } else {
+        let mut span = logging::span("commits", format_args!("Getting Commit Details..."));
+        let tip = tip?;
+        let options = import_options(cli, repo_id, tip, since_commit);
+        commit_counts = get_commits_detail_array(conn, repo, &options, progress, None)?;
+        link_reverted_commits(conn, repo_id)?;
+        // A filtered walk may legitimately pass over the tip.
+        if options.filter.is_empty() {
+            check_tip_imported(conn, repo_id, &options.tip);
+        }
+        span.record("added", commit_counts.added);
+        span.record("skipped", commit_counts.skipped);
+        span.finish();
+
+        let mut span = logging::span("refs", format_args!("Getting Ref Details..."));
+        ref_counts = refresh_ref_details(
+            conn,
+            repo,
+            repo_id,
+            &ref_filter(cli),
+            failures,
+            cli.on_conflict,
+            progress,
+        )?;
+        span.record("added", ref_counts.added);
+        span.record("skipped", ref_counts.skipped);
+        span.finish();
+
+        let span = logging::span(
+            "reachability",
+            format_args!("Computing Remote Reachability..."),
+        );
+        compute_remote_reachability(conn, repo, repo_id, &ref_filter(cli))?;
+        span.finish();
+    }
 
-    for commit in commits {
-        let tx = conn.transaction()?; // Begin a new transaction
+    let ref_changes = record_ref_history(conn, repo, repo_id, started_at, &ref_filter(cli))?;
+    if ref_changes > 0 {
+        info!("Recorded {} ref changes in ref_history.", ref_changes);
+    }
+    assign_author_ids(conn, repo, repo_id, false)?;
+    rank_commit_impact(conn, repo_id, cli.impact_weights, commit_counts.added)?;
+    let merges = analyze_merges(conn, repo, repo_id)?;
+    debug!("analysed {} merges", merges);
+    let notes = refresh_notes(conn, repo, repo_id)?;
+    debug!("stored {} notes", notes);
+    let stashes = refresh_stashes(conn, repo, repo_id, cli.stash_diffs, cli.with_patches)?;
+    debug!("stored {} stashes", stashes);
+    if cli.with_reflog {
+        let added = store_reflogs(conn, repo, repo_id)?;
+        info!("Stored {} new reflog entries.", added);
+    }
 
-        tx.execute(
-            insert_sql,
-            params![&commit.id, &commit.author, commit.date, &commit.message],
+    if cli.keep_going && commit_counts.skipped + ref_counts.skipped > 0 {
+        info!("Failures were recorded in the ingest_errors table.");
+    }
+    if cli.quiet_skipped && commit_counts.skipped + ref_counts.skipped > 0 {
+        warn!(
+            "skipped {} commits and {} refs that failed to process.",
+            commit_counts.skipped, ref_counts.skipped
+        );
+    }
+
+    // Blobs are keyed by OID only, tree_files maps HEAD paths onto them.
+    if cli.tree || cli.with_blobs {
+        let span = logging::span("tree_files", format_args!("Getting Tree Files..."));
+        get_tree_files(conn, repo, repo_id)?;
+        span.finish();
+    }
+
+    if cli.loc_snapshots {
+        let mut span = logging::span("loc", format_args!("Counting Lines per Tag..."));
+        let taken = loc::snapshot_refs(
+            conn,
+            repo,
+            repo_id,
+            &ref_filter(cli),
+            &cli.loc_refs,
+            unix_now(),
         )?;
+        span.record("snapshots", taken);
+        span.finish();
+    }
 
-        for parent in &commit.parents {
-            tx.execute(
-                "INSERT INTO commit_relation (parent, child) VALUES (?1, ?2)",
-                params![parent.to_string(), commit.id],
-            )
-            .expect("Failed to insert commit relation.");
-        }
-        tx.commit()?; // Commit the transaction
+    if cli.with_blobs {
+        let mut span = logging::span("blobs", format_args!("Getting Blob Contents..."));
+        let stored = store_blobs(conn, repo, &blob_filter(cli))?;
+        span.record("stored", stored);
+        span.finish();
     }
 
-    Ok(())
-}
+    if let Err(e) = set_repo_info(
+        conn,
+        repo_id,
+        "refs_checksum",
+        &refs_checksum(repo, &ref_filter(cli))?,
+    ) {
+        warn!("failed to store refs checksum: {}", e);
+    }
+    let finished_at = unix_now();
+    if let Err(e) = set_repo_info(conn, repo_id, "imported_at", &finished_at.to_string()) {
+        warn!("failed to store import time: {}", e);
+    }
 
-fn get_ref_details(conn: &mut Connection, repo: &Repository) {
-    let all_references: Vec<_> = repo
-        .references()
-        .expect("Failed to get references.")
-        .collect();
+    let run = ImportRun {
+        repo_id,
+        started_at,
+        finished_at,
+        commits_added: commit_counts.added,
+        refs_added: ref_counts.added,
+        skipped: commit_counts.skipped + ref_counts.skipped,
+        args: env::args().skip(1).collect(),
+        state,
+        complete: commit_counts.complete,
+    };
+    if let Err(e) = record_import_run(conn, &run) {
+        warn!("failed to record import run: {}", e);
+    }
 
-    for chunk in all_references.chunks(50) {
-        let mut chunk_refs = Vec::new();
+    let submodules = refresh_submodules(conn, repo, repo_id)?;
+    if cli.recurse_submodules && submodules > 0 {
+        import_submodules(cli, conn, repo, repo_id, db_exists)?;
+    }
 
-        for reference_result in chunk {
-            match reference_result {
-                Ok(reference) => {
-                    let formatted_refs = extract_ref_details(&reference);
-                    chunk_refs.push(formatted_refs);
-                }
-                Err(e) => println!("Failed to process reference: {}", e),
-            }
-        }
-        batch_insert_refs(conn, &chunk_refs).expect("Failed to insert references.");
+    if cli.single_transaction {
+        conn.execute_batch("COMMIT")?;
+    }
+    Ok(())
+}
+
+/// The `--since-tag` commit, whose history the walk leaves out.
+fn since_commit(cli: &Cli, repo: &Repository) -> Result<Option<Oid>, Box<dyn Error>> {
+    match cli.since_tag.as_deref() {
+        Some(tag) => Ok(Some(
+            repo.revparse_single(&format!("refs/tags/{}", tag))
+                .and_then(|object| object.peel_to_commit())
+                .map_err(|e| format!("tag {} does not resolve to a commit: {}", tag, e))?
+                .id(),
+        )),
+        None => Ok(None),
     }
 }
 
-fn extract_ref_details(reference: &Reference) -> RefDetails {
-    let name = reference.name().unwrap_or("").to_string();
-    let id = match reference.target() {
-        Some(target) => target.to_string(),
-        None => String::from("Unknown"),
+/// The `--branch` tip; None walks from HEAD.
+fn branch_tip(cli: &Cli, repo: &Repository) -> Result<Option<WalkTip>, Box<dyn Error>> {
+    let Some(branch) = cli.branch.as_deref() else {
+        return Ok(None);
     };
-    let kind = match reference.kind() {
-        Some(git2::ReferenceType::Direct) => "Direct",
-        Some(git2::ReferenceType::Symbolic) => "Symbolic",
-        None => "Unknown",
+    let ref_name = format!("refs/heads/{}", branch);
+    let commit = repo
+        .find_reference(&ref_name)
+        .and_then(|r| r.peel_to_commit())
+        .map_err(|e| format!("branch {} does not resolve to a commit: {}", branch, e))?;
+    Ok(Some(WalkTip {
+        ref_name,
+        oid: commit.id(),
+    }))
+}
+
+fn import_options(
+    cli: &Cli,
+    repo_id: i64,
+    tip: WalkTip,
+    since_commit: Option<Oid>,
+) -> ImportOptions {
+    ImportOptions {
+        repo_id,
+        tip,
+        type_rules: cli.type_rules.clone(),
+        issue_patterns: cli.issue_patterns.clone(),
+        time_budget: cli.time_budget,
+        strip_ansi: cli.strip_ansi,
+        max_db_size_mb: cli.max_db_size_mb,
+        diffs_dir: cli.diffs_dir.as_ref().map(PathBuf::from),
+        with_patches: cli.with_patches,
+        detect_generated: cli.detect_generated,
+        generated_markers: cli.generated_markers,
+        since_commit,
+        max_walk_depth: cli.max_walk_depth,
+        filter: CommitFilter {
+            paths: cli.paths.clone(),
+            ..cli.filter.clone()
+        },
+        failures: failure_policy(cli),
+        on_conflict: cli.on_conflict,
+        limit: cli.limit,
+        tree_every: cli.tree_every,
+        jobs: cli.jobs,
+        alternates: cli.alternates.clone(),
     }
-    .to_string();
+}
 
-    return RefDetails { id, name, kind };
+fn ref_filter(cli: &Cli) -> RefFilter {
+    RefFilter {
+        tag_pattern: cli.tag_pattern.clone(),
+        include: cli.ref_include.clone(),
+        exclude: cli.ref_exclude.clone(),
+    }
 }
 
-fn batch_insert_refs(conn: &mut Connection, refs: &Vec<RefDetails>) -> Result<()> {
-    let chunk_size = 50;
+fn blob_filter(cli: &Cli) -> BlobFilter {
+    BlobFilter {
+        paths: cli.paths.clone(),
+        max_size: cli.max_blob_size,
+        exclude_binary: cli.exclude_binary,
+    }
+}
 
-    let insert_sql = "INSERT INTO ref_details (id, name, kind) VALUES (?1, ?2, ?3)";
+/// `--dry-run`: reports what the import would add. The database is only
+/// read, and a missing one is stood in for by an empty in-memory one.
+fn report_dry_run(cli: &Cli, location: &str, db_exists: bool) -> Result<(), Box<dyn Error>> {
+    let (repo, _) = open_repository(location, cli.clone_dir.as_deref())?;
+    add_alternates(&repo, &cli.alternates)?;
+    let conn = if db_exists {
+        query::open_read_only(&cli.db_path)?
+    } else {
+        let conn = Connection::open_in_memory()?;
+        schema::migrate(&conn)?;
+        conn
+    };
+    // An unregistered repository has nothing stored, and no row has id 0.
+    let repo_id = find_repository(&conn, &repo_name(cli, location, &repo))?.unwrap_or(0);
 
-    for chunk in refs.chunks(chunk_size) {
-        let tx = conn.transaction()?; // Begin a new transaction
+    let since_commit = since_commit(cli, &repo)?;
+    let tip = match branch_tip(cli, &repo)? {
+        Some(tip) => tip,
+        None => WalkTip::head(&repo)?,
+    };
+    let options = import_options(cli, repo_id, tip, since_commit);
+    let blobs = blob_filter(cli);
+    let report = dry_run(
+        &conn,
+        &repo,
+        &options,
+        &ref_filter(cli),
+        !cli.refresh_refs_only,
+        cli.tree,
+        cli.with_blobs.then_some(&blobs),
+    )?;
 
-        for reference in chunk {
-            tx.execute(
-                insert_sql,
-                params![&reference.id, &reference.name, reference.kind,],
-            )?;
-        }
+    let result = query::QueryResult {
+        columns: [
+            "commits",
+            "refs",
+            "tags",
+            "tree_files",
+            "blobs",
+            "blob_bytes",
+            "estimated_bytes",
+        ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect(),
+        rows: vec![[
+            report.commits as u64,
+            report.refs as u64,
+            report.tags as u64,
+            report.tree_files as u64,
+            report.blobs as u64,
+            report.blob_bytes,
+            report.estimated_bytes,
+        ]
+        .iter()
+        .map(|&n| rusqlite::types::Value::Integer(n as i64))
+        .collect()],
+    };
+    query::print_result(&result, &cli.format);
+    Ok(())
+}
 
-        tx.commit()?; // Commit the transaction
+/// Imports each initialized submodule as a repository named
+/// `<superproject>/<path>`, recursing into nested ones the same way.
+fn import_submodules(
+    cli: &Cli,
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    db_exists: bool,
+) -> Result<(), Box<dyn Error>> {
+    let parent: String = conn.query_row(
+        "SELECT name FROM repositories WHERE repo_id = ?1",
+        [repo_id],
+        |row| row.get(0),
+    )?;
+    let submodule_cli = cli.for_submodule();
+    for submodule in repo.submodules()? {
+        let path = submodule.path().to_string_lossy().into_owned();
+        let submodule_repo = match submodule.open() {
+            Ok(submodule_repo) => submodule_repo,
+            Err(_) => {
+                info!("Skipping submodule {}, it isn't initialized.", path);
+                continue;
+            }
+        };
+        info!("Importing submodule {}...", path);
+        let workdir = submodule_repo
+            .workdir()
+            .unwrap_or_else(|| submodule_repo.path())
+            .to_path_buf();
+        let child_id = register_repository(conn, &format!("{}/{}", parent, path), &workdir)?;
+        link_submodule(conn, repo_id, &path, child_id)?;
+        import(&submodule_cli, conn, &submodule_repo, child_id, db_exists)?;
     }
-
     Ok(())
 }
@@ -0,0 +1,554 @@
+use crate::json::Json;
+use crate::query;
+use crate::serve;
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+/// Used when the client doesn't say which protocol revision it speaks.
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Runs a Model Context Protocol server on stdin/stdout: newline-delimited
+/// JSON-RPC 2.0, with tools that read the database. Returns when the client
+/// closes stdin.
+pub fn serve_stdio(db_path: &str) -> Result<(), Box<dyn Error>> {
+    let conn = query::open_read_only(db_path)?;
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match Json::parse(&line) {
+            Ok(message) => handle(&conn, &message),
+            Err(e) => Some(error_response(Json::Null, -32700, &e)),
+        };
+        if let Some(response) = response {
+            writeln!(stdout, "{}", response.to_json())?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// The response to one message; notifications (no id) get none.
+fn handle(conn: &Connection, message: &Json) -> Option<Json> {
+    let id = message.get("id")?.clone();
+    let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+    let params = message.get("params");
+    let result = match method {
+        "initialize" => Ok(object(vec![
+            (
+                "protocolVersion",
+                params
+                    .and_then(|p| p.get("protocolVersion"))
+                    .cloned()
+                    .unwrap_or_else(|| Json::String(PROTOCOL_VERSION.to_string())),
+            ),
+            ("capabilities", object(vec![("tools", object(vec![]))])),
+            (
+                "serverInfo",
+                object(vec![
+                    ("name", Json::String(String::from("git_info_llama"))),
+                    (
+                        "version",
+                        Json::String(env!("CARGO_PKG_VERSION").to_string()),
+                    ),
+                ]),
+            ),
+        ])),
+        "ping" => Ok(object(vec![])),
+        "tools/list" => Ok(object(vec![("tools", tools())])),
+        "tools/call" => {
+            let name = params
+                .and_then(|p| p.get("name"))
+                .and_then(Json::as_str)
+                .unwrap_or("");
+            let empty = object(vec![]);
+            let arguments = params.and_then(|p| p.get("arguments")).unwrap_or(&empty);
+            match call_tool(conn, name, arguments) {
+                Some(outcome) => Ok(tool_result(outcome)),
+                None => Err((-32602, format!("unknown tool: {}", name))),
+            }
+        }
+        _ => Err((-32601, format!("method not found: {}", method))),
+    };
+    Some(match result {
+        Ok(result) => object(vec![
+            ("jsonrpc", Json::String(String::from("2.0"))),
+            ("id", id),
+            ("result", result),
+        ]),
+        Err((code, message)) => error_response(id, code, &message),
+    })
+}
+
+/// Tool failures are results with isError set, so the model gets to read
+/// the message; only unknown tools are protocol errors.
+fn tool_result(outcome: Result<Json, String>) -> Json {
+    let (text, is_error) = match outcome {
+        Ok(json) => (json.to_json(), false),
+        Err(message) => (message, true),
+    };
+    object(vec![
+        (
+            "content",
+            Json::Array(vec![object(vec![
+                ("type", Json::String(String::from("text"))),
+                ("text", Json::String(text)),
+            ])]),
+        ),
+        ("isError", Json::Bool(is_error)),
+    ])
+}
+
+fn call_tool(conn: &Connection, name: &str, arguments: &Json) -> Option<Result<Json, String>> {
+    let arguments = Arguments(arguments);
+    Some(match name {
+        "get_commit" => arguments
+            .required("oid")
+            .and_then(|oid| serve::commit(conn, oid).map_err(|(_, e)| e)),
+        "search_commits" => search_commits(conn, &arguments),
+        "file_history" => file_history(conn, &arguments),
+        "blame_range" => blame_range(conn, &arguments),
+        _ => return None,
+    })
+}
+
+/// A tool call's arguments. A missing or null argument takes its default;
+/// one of the wrong type is an error rather than quietly defaulted.
+struct Arguments<'a>(&'a Json);
+
+impl<'a> Arguments<'a> {
+    fn text(&self, key: &str) -> Result<Option<&'a str>, String> {
+        match self.0.get(key) {
+            None | Some(Json::Null) => Ok(None),
+            Some(Json::String(s)) => Ok(Some(s)),
+            Some(_) => Err(format!("{} must be a string", key)),
+        }
+    }
+
+    fn required(&self, key: &str) -> Result<&'a str, String> {
+        self.text(key)?
+            .ok_or_else(|| format!("{} is required", key))
+    }
+
+    fn integer(&self, key: &str) -> Result<Option<i64>, String> {
+        match self.0.get(key) {
+            None | Some(Json::Null) => Ok(None),
+            Some(Json::Number(n)) if n.fract() == 0.0 => Ok(Some(*n as i64)),
+            Some(_) => Err(format!("{} must be an integer", key)),
+        }
+    }
+
+    fn flag(&self, key: &str) -> Result<bool, String> {
+        match self.0.get(key) {
+            None | Some(Json::Null) => Ok(false),
+            Some(Json::Bool(b)) => Ok(*b),
+            Some(_) => Err(format!("{} must be a boolean", key)),
+        }
+    }
+
+    /// `limit`, 50 by default and at most 1000.
+    fn limit(&self) -> Result<usize, String> {
+        Ok(self.integer("limit")?.unwrap_or(50).clamp(1, 1000) as usize)
+    }
+}
+
+fn search_commits(conn: &Connection, arguments: &Arguments) -> Result<Json, String> {
+    let search = arguments.required("query")?;
+    let patches = arguments.flag("patches")?;
+    let limit = arguments.limit()?;
+    query::check_search_index(conn)?;
+    let mut result = query::search(conn, search, patches).map_err(|e| e.to_string())?;
+    result.rows.truncate(limit);
+    Ok(serve::rows(&result))
+}
+
+fn file_history(conn: &Connection, arguments: &Arguments) -> Result<Json, String> {
+    let path = arguments.required("path")?;
+    let repo = arguments.text("repo")?;
+    let limit = arguments.limit()?;
+    let mut result = query::file_history(conn, path, repo).map_err(|e| e.to_string())?;
+    result.rows.truncate(limit);
+    Ok(serve::rows(&result))
+}
+
+fn blame_range(conn: &Connection, arguments: &Arguments) -> Result<Json, String> {
+    let path = arguments.required("path")?;
+    let repo = arguments.text("repo")?;
+    let start = arguments.integer("start_line")?.unwrap_or(1);
+    let end = arguments.integer("end_line")?.unwrap_or(i64::MAX);
+    let result = query::run_query_with(
+        conn,
+        "SELECT r.name AS repo, b.start_line, b.line_count, b.commit_id, b.author,
+                        b.author_email,
+                        (SELECT MIN(subject) FROM commit_details c WHERE c.id = b.commit_id)
+                            AS subject
+                 FROM file_blame b JOIN repositories r USING (repo_id)
+                 WHERE b.path = ?1 AND (?2 IS NULL OR r.name = ?2)
+                   AND b.start_line <= ?4 AND b.start_line + b.line_count > ?3
+                 ORDER BY r.name, b.start_line",
+        params![path, repo, start, end],
+    )
+    .map_err(|e| e.to_string())?;
+    if result.rows.is_empty() {
+        return Err(format!(
+            "no blame stored for {} in that range; run blame --path {} first",
+            path, path
+        ));
+    }
+    Ok(serve::rows(&result))
+}
+
+fn tools() -> Json {
+    let tool = |name: &str,
+                description: &str,
+                properties: Vec<(&str, &str, &str)>,
+                required: &[&str]| {
+        object(vec![
+            ("name", Json::String(name.to_string())),
+            ("description", Json::String(description.to_string())),
+            (
+                "inputSchema",
+                object(vec![
+                    ("type", Json::String(String::from("object"))),
+                    (
+                        "properties",
+                        object(
+                            properties
+                                .into_iter()
+                                .map(|(key, kind, description)| {
+                                    (
+                                        key,
+                                        object(vec![
+                                            ("type", Json::String(kind.to_string())),
+                                            ("description", Json::String(description.to_string())),
+                                        ]),
+                                    )
+                                })
+                                .collect(),
+                        ),
+                    ),
+                    (
+                        "required",
+                        Json::Array(
+                            required
+                                .iter()
+                                .map(|r| Json::String(r.to_string()))
+                                .collect(),
+                        ),
+                    ),
+                ]),
+            ),
+        ])
+    };
+    Json::Array(vec![
+        tool(
+            "get_commit",
+            "A commit's author, dates, message, parents, changed files and repositories.",
+            vec![(
+                "oid",
+                "string",
+                "Full or abbreviated (at least 4 hex digits) commit id",
+            )],
+            &["oid"],
+        ),
+        tool(
+            "search_commits",
+            "Full-text search over commit messages, or over patch text with patches=true.",
+            vec![
+                (
+                    "query",
+                    "string",
+                    "SQLite FTS5 query, e.g. 'login AND retry' or '\"exact phrase\"'",
+                ),
+                ("patches", "boolean", "Search diffs instead of messages"),
+                ("limit", "integer", "Maximum hits, 50 by default"),
+            ],
+            &["query"],
+        ),
+        tool(
+            "file_history",
+            "Commits that changed a file under any of its names, newest first, with change type and line counts.",
+            vec![
+                ("path", "string", "Repository-relative path"),
+                ("repo", "string", "Only this imported repository"),
+                ("limit", "integer", "Maximum commits, 50 by default"),
+            ],
+            &["path"],
+        ),
+        tool(
+            "blame_range",
+            "Which commits and authors last touched a line range of a file, from stored blame.",
+            vec![
+                ("path", "string", "Repository-relative path"),
+                ("start_line", "integer", "First line, 1-based"),
+                ("end_line", "integer", "Last line, inclusive"),
+                ("repo", "string", "Only this imported repository"),
+            ],
+            &["path"],
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: i64, message: &str) -> Json {
+    object(vec![
+        ("jsonrpc", Json::String(String::from("2.0"))),
+        ("id", id),
+        (
+            "error",
+            object(vec![
+                ("code", Json::Number(code as f64)),
+                ("message", Json::String(message.to_string())),
+            ]),
+        ),
+    ])
+}
+
+fn object(members: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        members
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+
+    const FIRST: &str = "1111111111111111111111111111111111111111";
+    const SECOND: &str = "2222222222222222222222222222222222222222";
+
+    /// Two commits to src/lib.rs in `app`, with blame splitting its twenty
+    /// lines between them.
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        schema::migrate(&conn).unwrap();
+        conn.execute_batch(&format!(
+            "INSERT INTO repositories (name, path) VALUES ('app', '/app');
+             INSERT INTO commit_details (
+                 repo_id, id, author, date, message, author_is_committer, classified_type,
+                 log_author, log_date, subject_slug, author_email, committer_name,
+                 committer_email, author_time, author_tz_offset, commit_time,
+                 commit_tz_offset, is_breaking, subject, body)
+             VALUES
+                 (1, '{first}', 'Jane Doe', 1700000000, 'Add login', 1, 'feature', '', '',
+                  '', 'jane@example.com', 'Jane Doe', 'jane@example.com', 1700000000, 0,
+                  1700000000, 0, 0, 'Add login', ''),
+                 (1, '{second}', 'Bob Roe', 1700086400, 'Retry login on timeout', 1, 'fix',
+                  '', '', '', 'bob@example.com', 'Bob Roe', 'bob@example.com', 1700086400,
+                  0, 1700086400, 0, 0, 'Retry login on timeout', '');
+             INSERT INTO commit_files (commit_id, path, change_type, insertions, deletions)
+             VALUES ('{first}', 'src/lib.rs', 'added', 20, 0),
+                    ('{second}', 'src/lib.rs', 'modified', 10, 10);
+             INSERT INTO file_blame
+                 (repo_id, path, start_line, line_count, commit_id, author, author_email)
+             VALUES (1, 'src/lib.rs', 1, 10, '{first}', 'Jane Doe', 'jane@example.com'),
+                    (1, 'src/lib.rs', 11, 10, '{second}', 'Bob Roe', 'bob@example.com');",
+            first = FIRST,
+            second = SECOND
+        ))
+        .unwrap();
+        conn
+    }
+
+    fn request(conn: &Connection, text: &str) -> Json {
+        handle(conn, &Json::parse(text).unwrap()).unwrap()
+    }
+
+    /// Calls `tool` and returns its text and whether it is an error.
+    fn call(conn: &Connection, tool: &str, arguments: &str) -> (String, bool) {
+        let response = request(
+            conn,
+            &format!(
+                "{{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"tools/call\",\
+                 \"params\":{{\"name\":\"{}\",\"arguments\":{}}}}}",
+                tool, arguments
+            ),
+        );
+        let result = response.get("result").expect("a result");
+        let content = &result.get("content").and_then(Json::as_array).unwrap()[0];
+        (
+            content
+                .get("text")
+                .and_then(Json::as_str)
+                .unwrap()
+                .to_string(),
+            result.get("isError") == Some(&Json::Bool(true)),
+        )
+    }
+
+    #[test]
+    fn lists_the_tools() {
+        let conn = database();
+        let response = request(
+            &conn,
+            "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"tools/list\"}",
+        );
+        let tools = response
+            .get("result")
+            .and_then(|r| r.get("tools"))
+            .and_then(Json::as_array)
+            .unwrap();
+        let listed: Vec<(&str, String)> = tools
+            .iter()
+            .map(|tool| {
+                let schema = tool.get("inputSchema").unwrap();
+                assert_eq!(schema.get("type").and_then(Json::as_str), Some("object"));
+                (
+                    tool.get("name").and_then(Json::as_str).unwrap(),
+                    schema.get("required").unwrap().to_json(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("get_commit", String::from("[\"oid\"]")),
+                ("search_commits", String::from("[\"query\"]")),
+                ("file_history", String::from("[\"path\"]")),
+                ("blame_range", String::from("[\"path\"]")),
+            ]
+        );
+    }
+
+    #[test]
+    fn answers_protocol_messages() {
+        let conn = database();
+        let response = request(
+            &conn,
+            "{\"jsonrpc\":\"2.0\",\"id\":\"a\",\"method\":\"initialize\",\
+             \"params\":{\"protocolVersion\":\"2025-01-01\"}}",
+        );
+        let result = response.get("result").unwrap();
+        assert_eq!(response.get("id").and_then(Json::as_str), Some("a"));
+        assert_eq!(
+            result.get("protocolVersion").and_then(Json::as_str),
+            Some("2025-01-01")
+        );
+
+        let notification = Json::parse("{\"jsonrpc\":\"2.0\",\"method\":\"initialized\"}").unwrap();
+        assert_eq!(handle(&conn, &notification), None);
+
+        let cases = [
+            (
+                "{\"id\":2,\"method\":\"nope\"}",
+                -32601.0,
+                "method not found: nope",
+            ),
+            (
+                "{\"id\":3,\"method\":\"tools/call\",\"params\":{\"name\":\"nope\"}}",
+                -32602.0,
+                "unknown tool: nope",
+            ),
+        ];
+        for (text, code, message) in cases {
+            let error = request(&conn, text).get("error").cloned().unwrap();
+            assert_eq!(error.get("code").and_then(Json::as_f64), Some(code));
+            assert_eq!(error.get("message").and_then(Json::as_str), Some(message));
+        }
+    }
+
+    #[test]
+    fn calls_each_tool() {
+        let conn = database();
+
+        let (text, is_error) = call(&conn, "get_commit", "{\"oid\":\"2222\"}");
+        assert!(!is_error, "{}", text);
+        let commit = Json::parse(&text).unwrap();
+        assert_eq!(commit.get("id").and_then(Json::as_str), Some(SECOND));
+        assert_eq!(commit.get("repositories").unwrap().to_json(), "[\"app\"]");
+
+        let (text, is_error) = call(&conn, "search_commits", "{\"query\":\"login\"}");
+        assert!(!is_error, "{}", text);
+        assert_eq!(Json::parse(&text).unwrap().as_array().unwrap().len(), 2);
+        let (text, _) = call(&conn, "search_commits", "{\"query\":\"login\",\"limit\":1}");
+        assert_eq!(Json::parse(&text).unwrap().as_array().unwrap().len(), 1);
+
+        let (text, is_error) = call(&conn, "file_history", "{\"path\":\"src/lib.rs\"}");
+        assert!(!is_error, "{}", text);
+        let history = Json::parse(&text).unwrap();
+        let ids: Vec<&str> = history
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row.get("id").and_then(Json::as_str).unwrap())
+            .collect();
+        assert_eq!(ids, [SECOND, FIRST]);
+
+        let blamed = |arguments: &str| {
+            let (text, is_error) = call(&conn, "blame_range", arguments);
+            assert!(!is_error, "{}", text);
+            Json::parse(&text)
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|row| {
+                    row.get("commit_id")
+                        .and_then(Json::as_str)
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(blamed("{\"path\":\"src/lib.rs\"}"), [FIRST, SECOND]);
+        assert_eq!(
+            blamed("{\"path\":\"src/lib.rs\",\"start_line\":12,\"end_line\":null}"),
+            [SECOND]
+        );
+        assert_eq!(
+            blamed("{\"path\":\"src/lib.rs\",\"start_line\":1,\"end_line\":10}"),
+            [FIRST]
+        );
+    }
+
+    #[test]
+    fn reports_bad_arguments_as_tool_errors() {
+        let conn = database();
+        let cases = [
+            ("get_commit", "{}", "oid is required"),
+            ("get_commit", "{\"oid\":2222}", "oid must be a string"),
+            ("get_commit", "{\"oid\":\"3333\"}", "no commit 3333"),
+            (
+                "search_commits",
+                "{\"query\":\"login\",\"patches\":\"yes\"}",
+                "patches must be a boolean",
+            ),
+            (
+                "file_history",
+                "{\"path\":\"src/lib.rs\",\"limit\":\"5\"}",
+                "limit must be an integer",
+            ),
+            (
+                "blame_range",
+                "{\"path\":\"src/lib.rs\",\"end_line\":\"20\"}",
+                "end_line must be an integer",
+            ),
+            (
+                "blame_range",
+                "{\"path\":\"src/lib.rs\",\"start_line\":1.5}",
+                "start_line must be an integer",
+            ),
+            (
+                "blame_range",
+                "{\"path\":\"README.md\"}",
+                "no blame stored for README.md in that range; run blame --path README.md first",
+            ),
+        ];
+        for (tool, arguments, message) in cases {
+            assert_eq!(
+                call(&conn, tool, arguments),
+                (message.to_string(), true),
+                "{} {}",
+                tool,
+                arguments
+            );
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use crate::classify::IssuePatterns;
+
+/// A commit message split into conventional-commit parts.
+///
+/// `fix(parser)!: handle empty input` has type `fix`, scope `parser`, is
+/// breaking and has subject `handle empty input`. Messages that don't follow
+/// the convention keep their whole first line as the subject.
+#[derive(Default)]
+pub struct ParsedMessage {
+    pub cc_type: Option<String>,
+    pub scope: Option<String>,
+    /// Set by `!` before the colon or a `BREAKING CHANGE` footer.
+    pub breaking: bool,
+    pub subject: String,
+    /// Everything between the subject and the footers.
+    pub body: String,
+    /// `Key: value` trailers from the last paragraph, in order.
+    pub footers: Vec<(String, String)>,
+    /// Name and email of every `Co-authored-by` trailer.
+    pub co_authors: Vec<(String, String)>,
+    /// Ticket references (`#123`, `ABC-123`) anywhere in the message, once
+    /// each, with the lowercased key of the trailer they were found in
+    /// (`fixes` for `Fixes: #12`). Trailers are searched first.
+    pub issue_refs: Vec<(String, Option<String>)>,
+}
+
+pub fn parse(message: &str) -> ParsedMessage {
+    parse_with(message, &IssuePatterns::default())
+}
+
+/// Like `parse`, finding issue references with `issue_patterns`.
+pub fn parse_with(message: &str, issue_patterns: &IssuePatterns) -> ParsedMessage {
+    let mut lines = message.lines();
+    let first_line = lines.next().unwrap_or("").trim();
+    let rest: Vec<&str> = lines.collect();
+
+    let mut parsed = ParsedMessage::default();
+    match parse_header(first_line) {
+        Some((cc_type, scope, breaking, subject)) => {
+            parsed.cc_type = Some(cc_type);
+            parsed.scope = scope;
+            parsed.breaking = breaking;
+            parsed.subject = subject;
+        }
+        None => parsed.subject = first_line.to_string(),
+    }
+
+    let mut paragraphs: Vec<Vec<&str>> = Vec::new();
+    let mut current = Vec::new();
+    for line in rest {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(line);
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+
+    if let Some(footers) = paragraphs.last().and_then(|last| parse_footers(last)) {
+        paragraphs.pop();
+        parsed.footers = footers;
+    }
+    parsed.body = paragraphs
+        .iter()
+        .map(|paragraph| paragraph.join("\n"))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    for (key, value) in &parsed.footers {
+        if key == "BREAKING CHANGE" || key == "BREAKING-CHANGE" {
+            parsed.breaking = true;
+        }
+        if key.eq_ignore_ascii_case("co-authored-by") {
+            parsed.co_authors.push(parse_identity(value));
+        }
+    }
+
+    let footer_refs = parsed.footers.iter().flat_map(|(key, value)| {
+        issue_patterns
+            .find(value)
+            .into_iter()
+            .map(|reference| (reference, Some(key.to_lowercase())))
+    });
+    let mentions = issue_patterns
+        .find(message)
+        .into_iter()
+        .map(|reference| (reference, None));
+    for (reference, action) in footer_refs.chain(mentions) {
+        if !parsed.issue_refs.iter().any(|(r, _)| *r == reference) {
+            parsed.issue_refs.push((reference, action));
+        }
+    }
+
+    parsed
+}
+
+/// Splits `type(scope)!: subject`. The type must be a single word of
+/// letters, so merge subjects and other prose with a later colon don't match.
+fn parse_header(line: &str) -> Option<(String, Option<String>, bool, String)> {
+    let (prefix, subject) = line.split_once(": ")?;
+    let (prefix, breaking) = match prefix.strip_suffix('!') {
+        Some(prefix) => (prefix, true),
+        None => (prefix, false),
+    };
+    let (cc_type, scope) = match prefix.split_once('(') {
+        Some((cc_type, scope)) => (cc_type, Some(scope.strip_suffix(')')?)),
+        None => (prefix, None),
+    };
+    if cc_type.is_empty() || !cc_type.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    if scope.is_some_and(|scope| scope.is_empty() || scope.contains(['(', ')'])) {
+        return None;
+    }
+    Some((
+        cc_type.to_lowercase(),
+        scope.map(str::to_string),
+        breaking,
+        subject.trim().to_string(),
+    ))
+}
+
+/// Reads a paragraph as git trailers, or `None` if any line isn't one.
+/// Indented lines continue the previous trailer's value.
+fn parse_footers(paragraph: &[&str]) -> Option<Vec<(String, String)>> {
+    let mut footers: Vec<(String, String)> = Vec::new();
+    for line in paragraph {
+        if line.starts_with([' ', '\t']) {
+            let (_, value) = footers.last_mut()?;
+            value.push(' ');
+            value.push_str(line.trim());
+            continue;
+        }
+        // `Fixes #12` is a trailer too; the `#` stays part of the value.
+        let (key, value) = match line.split_once(": ") {
+            Some(pair) => pair,
+            None => {
+                let (key, _) = line.split_once(" #")?;
+                (key, &line[key.len() + 1..])
+            }
+        };
+        let is_token = key == "BREAKING CHANGE"
+            || (!key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+        if !is_token {
+            return None;
+        }
+        footers.push((key.to_string(), value.trim().to_string()));
+    }
+    Some(footers)
+}
+
+/// Splits `Name <email>`; a value without angle brackets is all name.
+fn parse_identity(value: &str) -> (String, String) {
+    match value.split_once('<') {
+        Some((name, email)) => (
+            name.trim().to_string(),
+            email.trim_end().trim_end_matches('>').trim().to_string(),
+        ),
+        None => (value.trim().to_string(), String::new()),
+    }
+}
+
+/// The merged and target branch named by a merge commit's summary, as
+/// written by git (`Merge branch 'topic' into main`, `Merge remote-tracking
+/// branch 'origin/topic'`, `Merge tag 'v1.0'`), GitHub (`Merge pull request
+/// #12 from owner/topic`), GitLab (`Merge branch 'topic' into 'main'`) and
+/// Bitbucket (`Merged in topic (pull request #12)`). Either is None when the
+/// summary doesn't say.
+pub fn merge_branches(summary: &str) -> (Option<String>, Option<String>) {
+    let summary = summary.trim();
+    if let Some(rest) = summary.strip_prefix("Merge pull request ") {
+        let source = rest
+            .split_once(" from ")
+            .and_then(|(_, source)| source.split_whitespace().next())
+            .map(str::to_string);
+        return (source, None);
+    }
+    if let Some(rest) = summary.strip_prefix("Merged in ") {
+        let source = rest.split_whitespace().next().map(str::to_string);
+        return (source, None);
+    }
+    let rest = [
+        "Merge branch ",
+        "Merge remote-tracking branch ",
+        "Merge tag ",
+    ]
+    .iter()
+    .find_map(|prefix| summary.strip_prefix(prefix));
+    let Some(rest) = rest else {
+        return (None, None);
+    };
+    let Some((source, rest)) = quoted(rest) else {
+        return (None, None);
+    };
+    let target = rest.split_once("into ").map(|(_, target)| {
+        let target = target.trim();
+        match quoted(target) {
+            Some((target, _)) => target,
+            None => target.to_string(),
+        }
+    });
+    (Some(source), target.filter(|target| !target.is_empty()))
+}
+
+/// Splits `'text' rest` into the quoted text and what follows it.
+fn quoted(text: &str) -> Option<(String, &str)> {
+    let (inner, rest) = text.strip_prefix('\'')?.split_once('\'')?;
+    Some((inner.to_string(), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn footers(parsed: &ParsedMessage) -> Vec<(&str, &str)> {
+        parsed
+            .footers
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_conventional_headers() {
+        let cases = [
+            (
+                "fix(parser)!: handle empty input",
+                Some("fix"),
+                Some("parser"),
+                true,
+                "handle empty input",
+            ),
+            ("Feat: add thing", Some("feat"), None, false, "add thing"),
+            (
+                "docs!: drop v1 notes",
+                Some("docs"),
+                None,
+                true,
+                "drop v1 notes",
+            ),
+            (
+                "Merge branch 'a': b",
+                None,
+                None,
+                false,
+                "Merge branch 'a': b",
+            ),
+            (
+                "fix(): empty scope",
+                None,
+                None,
+                false,
+                "fix(): empty scope",
+            ),
+            ("v2: not a type", None, None, false, "v2: not a type"),
+            ("Plain subject", None, None, false, "Plain subject"),
+        ];
+        for (message, cc_type, scope, breaking, subject) in cases {
+            let parsed = parse(message);
+            assert_eq!(parsed.cc_type.as_deref(), cc_type, "{}", message);
+            assert_eq!(parsed.scope.as_deref(), scope, "{}", message);
+            assert_eq!(parsed.breaking, breaking, "{}", message);
+            assert_eq!(parsed.subject, subject, "{}", message);
+        }
+    }
+
+    #[test]
+    fn splits_body_and_trailers() {
+        let parsed = parse(
+            "feat: thing\n\nFirst paragraph.\n\nSecond\nparagraph.\n\n\
+             Reviewed-by: Ann <ann@example.com>\n\
+             Co-authored-by: Bob Smith <bob@example.com>\n\
+             Note: a long value\n  that continues\n\
+             Fixes #12",
+        );
+        assert_eq!(parsed.body, "First paragraph.\n\nSecond\nparagraph.");
+        assert_eq!(
+            footers(&parsed),
+            [
+                ("Reviewed-by", "Ann <ann@example.com>"),
+                ("Co-authored-by", "Bob Smith <bob@example.com>"),
+                ("Note", "a long value that continues"),
+                ("Fixes", "#12"),
+            ]
+        );
+        assert_eq!(
+            parsed.co_authors,
+            [(String::from("Bob Smith"), String::from("bob@example.com"))]
+        );
+    }
+
+    #[test]
+    fn prose_last_paragraph_is_body() {
+        let parsed = parse("fix: thing\n\nThis explains: why it broke.\nAnd more.");
+        assert!(parsed.footers.is_empty());
+        assert_eq!(parsed.body, "This explains: why it broke.\nAnd more.");
+    }
+
+    #[test]
+    fn breaking_change_footer_marks_breaking() {
+        for footer in [
+            "BREAKING CHANGE: config moved",
+            "BREAKING-CHANGE: config moved",
+        ] {
+            let parsed = parse(&format!("feat: thing\n\n{}", footer));
+            assert!(parsed.breaking, "{}", footer);
+        }
+    }
+
+    #[test]
+    fn co_author_without_email() {
+        let parsed = parse("x\n\nCo-authored-by: Someone");
+        assert_eq!(
+            parsed.co_authors,
+            [(String::from("Someone"), String::new())]
+        );
+    }
+
+    #[test]
+    fn reads_merge_branches() {
+        let some = |s: &str| Some(String::from(s));
+        let cases = [
+            ("Merge branch 'topic'", (some("topic"), None)),
+            (
+                "Merge branch 'topic' into main",
+                (some("topic"), some("main")),
+            ),
+            (
+                "Merge branch 'topic' into 'main'",
+                (some("topic"), some("main")),
+            ),
+            (
+                "Merge remote-tracking branch 'origin/x'",
+                (some("origin/x"), None),
+            ),
+            ("Merge tag 'v1.0'", (some("v1.0"), None)),
+            ("Merge pull request #7 from bob/t3", (some("bob/t3"), None)),
+            ("Merged in topic (pull request #12)", (some("topic"), None)),
+            ("Merge stuff", (None, None)),
+        ];
+        for (summary, expected) in cases {
+            assert_eq!(merge_branches(summary), expected, "{}", summary);
+        }
+    }
+
+    #[test]
+    fn trailer_references_carry_their_action() {
+        let parsed = parse("fix: thing for #3\n\nFixes: #7\nRefs: ABC-9 #3");
+        assert_eq!(
+            parsed.issue_refs,
+            [
+                (String::from("#7"), Some(String::from("fixes"))),
+                (String::from("ABC-9"), Some(String::from("refs"))),
+                (String::from("#3"), Some(String::from("refs"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_issue_patterns_replace_the_default() {
+        let mut patterns = IssuePatterns::default();
+        patterns.add("GH-\\d+").unwrap();
+        let parsed = parse_with("fix: GH-4 and #5\n\nCloses: GH-6", &patterns);
+        assert_eq!(
+            parsed.issue_refs,
+            [
+                (String::from("GH-6"), Some(String::from("closes"))),
+                (String::from("GH-4"), None),
+            ]
+        );
+    }
+}
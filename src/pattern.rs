@@ -0,0 +1,247 @@
+/// A regular expression subset for user-supplied patterns: literal
+/// characters, `.`, `\d` `\w` `\s` (and `\D` `\W` `\S`), classes like
+/// `[A-Z_]` or `[^0-9]`, `\b` word boundaries and the quantifiers `?`, `*`,
+/// `+`, `{n}`, `{n,}` and `{n,m}`. Any other escaped character is literal.
+/// Groups, alternation and anchors are rejected rather than misread.
+#[derive(Clone)]
+pub struct Pattern {
+    /// Each atom with the least and most times it repeats.
+    atoms: Vec<(Atom, usize, usize)>,
+}
+
+#[derive(Clone)]
+enum Atom {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    WordBoundary,
+}
+
+const DIGITS: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Char(expected) => c == *expected,
+            Atom::Any => c != '\n',
+            Atom::Class { ranges, negated } => {
+                ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+            }
+            Atom::WordBoundary => false,
+        }
+    }
+}
+
+/// `\d`, `\w` and `\s` and their negations, usable inside classes too.
+fn shorthand(c: char) -> Option<(&'static [(char, char)], bool)> {
+    match c {
+        'd' => Some((DIGITS, false)),
+        'w' => Some((WORD, false)),
+        's' => Some((SPACE, false)),
+        'D' => Some((DIGITS, true)),
+        'W' => Some((WORD, true)),
+        'S' => Some((SPACE, true)),
+        _ => None,
+    }
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern, String> {
+        let mut chars = pattern.chars().peekable();
+        let mut atoms = Vec::new();
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '.' => Atom::Any,
+                '\\' => match chars.next() {
+                    Some('b') => Atom::WordBoundary,
+                    Some(c) => match shorthand(c) {
+                        Some((ranges, negated)) => Atom::Class {
+                            ranges: ranges.to_vec(),
+                            negated,
+                        },
+                        None => Atom::Char(c),
+                    },
+                    None => return Err(format!("Pattern ends in a backslash: {}", pattern)),
+                },
+                '[' => parse_class(&mut chars).map_err(|e| format!("{}: {}", e, pattern))?,
+                '?' | '*' | '+' | '{' => {
+                    return Err(format!("Nothing to repeat before {} in: {}", c, pattern))
+                }
+                '(' | ')' | '|' | '^' | '$' => {
+                    return Err(format!(
+                    "Groups, alternation and anchors aren't supported (escape {} to match it): {}",
+                    c, pattern
+                ))
+                }
+                c => Atom::Char(c),
+            };
+            // A quantifier after a quantifier (`+?`, `a**`) fails as having
+            // nothing to repeat on the next turn.
+            let (min, max) = match chars.next_if(|c| matches!(c, '?' | '*' | '+' | '{')) {
+                Some('?') => (0, 1),
+                Some('*') => (0, usize::MAX),
+                Some('+') => (1, usize::MAX),
+                Some(_) => {
+                    let bounds: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    parse_bounds(&bounds)
+                        .ok_or_else(|| format!("Invalid {{{}}} in pattern: {}", bounds, pattern))?
+                }
+                None => (1, 1),
+            };
+            if matches!(atom, Atom::WordBoundary) && (min, max) != (1, 1) {
+                return Err(format!("\\b can't be repeated: {}", pattern));
+            }
+            atoms.push((atom, min, max));
+        }
+        if atoms.is_empty() {
+            return Err(String::from("Empty pattern"));
+        }
+        Ok(Pattern { atoms })
+    }
+
+    /// Every leftmost, longest non-overlapping match in `text`, in order.
+    /// Empty matches are skipped.
+    pub fn find_all(&self, text: &str) -> Vec<String> {
+        let text: Vec<char> = text.chars().collect();
+        let mut found = Vec::new();
+        let mut start = 0;
+        while start < text.len() {
+            match self.match_at(&text, 0, start) {
+                Some(end) if end > start => {
+                    found.push(text[start..end].iter().collect());
+                    start = end;
+                }
+                _ => start += 1,
+            }
+        }
+        found
+    }
+
+    /// Where a match of the atoms from `index` on, starting at `pos`, ends.
+    /// Quantifiers are greedy and give back one character at a time.
+    fn match_at(&self, text: &[char], index: usize, pos: usize) -> Option<usize> {
+        let Some((atom, min, max)) = self.atoms.get(index) else {
+            return Some(pos);
+        };
+        if let Atom::WordBoundary = atom {
+            let is_word = |i: usize| {
+                text.get(i)
+                    .is_some_and(|&c| c.is_alphanumeric() || c == '_')
+            };
+            let at_boundary = is_word(pos) != (pos > 0 && is_word(pos - 1));
+            return at_boundary.then(|| self.match_at(text, index + 1, pos))?;
+        }
+        let mut count = 0;
+        while count < *max && text.get(pos + count).is_some_and(|&c| atom.matches(c)) {
+            count += 1;
+        }
+        while count >= *min {
+            if let Some(end) = self.match_at(text, index + 1, pos + count) {
+                return Some(end);
+            }
+            if count == 0 {
+                break;
+            }
+            count -= 1;
+        }
+        None
+    }
+}
+
+/// Reads a class after its `[`, through the closing `]`. A `]` first or a
+/// `-` last is literal.
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Atom, &'static str> {
+    let negated = chars.next_if_eq(&'^').is_some();
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = chars.next().ok_or("Unclosed [ in pattern")?;
+        match c {
+            ']' if !first => return Ok(Atom::Class { ranges, negated }),
+            '\\' => {
+                let escaped = chars.next().ok_or("Unclosed [ in pattern")?;
+                match shorthand(escaped) {
+                    // These would need set subtraction.
+                    Some((set, false)) => ranges.extend_from_slice(set),
+                    Some((_, true)) => return Err("\\D, \\W and \\S can't be used in a class"),
+                    None => ranges.push((escaped, escaped)),
+                }
+            }
+            c => {
+                let mut ahead = chars.clone();
+                match (ahead.next(), ahead.next()) {
+                    (Some('-'), Some(high)) if high != ']' => {
+                        *chars = ahead;
+                        ranges.push((c, high));
+                    }
+                    _ => ranges.push((c, c)),
+                }
+            }
+        }
+        first = false;
+    }
+}
+
+/// `n`, `n,` or `n,m` from a `{...}` quantifier.
+fn parse_bounds(bounds: &str) -> Option<(usize, usize)> {
+    match bounds.split_once(',') {
+        None => {
+            let n = bounds.trim().parse().ok()?;
+            Some((n, n))
+        }
+        Some((min, "")) => Some((min.trim().parse().ok()?, usize::MAX)),
+        Some((min, max)) => {
+            let (min, max) = (min.trim().parse().ok()?, max.trim().parse().ok()?);
+            (min <= max).then_some((min, max))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+
+    #[test]
+    fn finds_matches() {
+        let cases: &[(&str, &str, &[&str])] = &[
+            (
+                "JIRA-\\d+",
+                "JIRA-12 and JIRA-345, not JIRA-",
+                &["JIRA-12", "JIRA-345"],
+            ),
+            ("#\\d{2,3}", "#1 #12 #1234", &["#12", "#123"]),
+            ("#\\d{2}", "#123", &["#12"]),
+            ("#\\d{2,}", "#1 #12345", &["#12345"]),
+            ("[A-Z]+-\\d+", "ab-1 AB-2", &["AB-2"]),
+            ("[^ ]+@x", "a@x b@x", &["a@x", "b@x"]),
+            ("\\bgh-\\d+\\b", "gh-1 xgh-2 gh-3x", &["gh-1"]),
+            ("colou?r", "color colour", &["color", "colour"]),
+            ("a.c", "abc a\nc", &["abc"]),
+            ("\\w+\\s\\w+", "one two", &["one two"]),
+            ("[\\d_]+", "a1_2b", &["1_2"]),
+            ("\\.", "a.b", &["."]),
+            ("x*", "abc", &[]),
+            ("[]a]", "]a", &["]", "a"]),
+            ("[a-]", "-", &["-"]),
+        ];
+        for (pattern, text, expected) in cases {
+            let found = Pattern::parse(pattern).unwrap().find_all(text);
+            assert_eq!(found, *expected, "{} in {:?}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_patterns() {
+        for pattern in [
+            "", "\\", "*a", "a**", "a+?", "(a)", "a|b", "^a", "a$", "[a", "[\\D]", "a{x}",
+            "a{3,1}", "\\b+",
+        ] {
+            assert!(Pattern::parse(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
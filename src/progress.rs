@@ -0,0 +1,82 @@
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::time::Instant;
+
+/// Receives progress updates while commits and refs are being extracted.
+pub trait ProgressSink {
+    fn on_commit(&self, done: usize, total: usize);
+    fn on_ref(&self, done: usize, total: usize);
+}
+
+const BAR_WIDTH: usize = 30;
+
+/// Progress reporting for the CLI: a bar with rate and ETA, redrawn in place
+/// on stderr.
+pub struct ConsoleProgress {
+    commits_started: Cell<Option<Instant>>,
+    refs_started: Cell<Option<Instant>>,
+    last_update: Cell<Instant>,
+}
+
+impl ConsoleProgress {
+    pub fn new() -> Self {
+        ConsoleProgress {
+            commits_started: Cell::new(None),
+            refs_started: Cell::new(None),
+            last_update: Cell::new(Instant::now()),
+        }
+    }
+
+    fn draw(&self, label: &str, started: &Cell<Option<Instant>>, done: usize, total: usize) {
+        // Updates only come after a batch is done, so a phase is timed from
+        // the previous update (or construction) rather than its first update.
+        let start = started.get().unwrap_or_else(|| self.last_update.get());
+        started.set(Some(start));
+        self.last_update.set(Instant::now());
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let filled = (BAR_WIDTH * done.min(total))
+            .checked_div(total)
+            .unwrap_or(BAR_WIDTH);
+        let mut line = format!(
+            "\r{}: [{}{}] {}/{}",
+            label,
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            done,
+            total
+        );
+        if elapsed > 0.0 && done > 0 {
+            let rate = done as f64 / elapsed;
+            line.push_str(&format!(" {:.0}/s", rate));
+            if done < total {
+                let eta = ((total - done) as f64 / rate).round() as u64;
+                line.push_str(&format!(" ETA {}:{:02}", eta / 60, eta % 60));
+            }
+        }
+
+        let mut stderr = io::stderr();
+        // Trailing spaces wipe what's left of a longer previous line.
+        let _ = write!(stderr, "{}   ", line);
+        if done >= total {
+            let _ = writeln!(stderr);
+        }
+        let _ = stderr.flush();
+    }
+}
+
+impl Default for ConsoleProgress {
+    fn default() -> Self {
+        ConsoleProgress::new()
+    }
+}
+
+impl ProgressSink for ConsoleProgress {
+    fn on_commit(&self, done: usize, total: usize) {
+        self.draw("Commits", &self.commits_started, done, total);
+    }
+
+    fn on_ref(&self, done: usize, total: usize) {
+        self.draw("Refs", &self.refs_started, done, total);
+    }
+}
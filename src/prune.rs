@@ -0,0 +1,161 @@
+use crate::error::IngestResult;
+use crate::RefFilter;
+use git2::Repository;
+use rusqlite::{params, Connection};
+use std::collections::HashSet;
+
+/// Tables keyed by commit id alone, shared by every repository that has
+/// the commit.
+const COMMIT_TABLES: &[&str] = &[
+    "commit_footers",
+    "commit_co_authors",
+    "commit_issue_refs",
+    "commit_files",
+    "commit_patches",
+    "commit_diffs",
+    "tree_entries",
+];
+
+#[derive(Default)]
+pub struct PruneCounts {
+    pub refs: usize,
+    pub tags: usize,
+    pub commits: usize,
+}
+
+/// Drops the stored refs, tags and walk tips of refs that no longer exist
+/// in `repo`, counting the refs and tags removed.
+pub fn prune_refs(
+    conn: &Connection,
+    repo: &Repository,
+    repo_id: i64,
+    counts: &mut PruneCounts,
+) -> IngestResult<()> {
+    let present: HashSet<String> = repo
+        .references()?
+        .flatten()
+        .filter_map(|reference| reference.name().map(str::to_string))
+        .collect();
+    let gone = |sql: &str, prefix: &str| -> rusqlite::Result<Vec<String>> {
+        let names: Vec<String> = conn
+            .prepare(sql)?
+            .query_map(params![repo_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(names
+            .into_iter()
+            .filter(|name| !present.contains(&format!("{}{}", prefix, name)))
+            .collect())
+    };
+
+    for name in gone(
+        "SELECT DISTINCT name FROM ref_details WHERE repo_id = ?1",
+        "",
+    )? {
+        counts.refs += conn.execute(
+            "DELETE FROM ref_details WHERE repo_id = ?1 AND name = ?2",
+            params![repo_id, name],
+        )?;
+    }
+    for name in gone("SELECT name FROM tags WHERE repo_id = ?1", "refs/tags/")? {
+        counts.tags += conn.execute(
+            "DELETE FROM tags WHERE repo_id = ?1 AND name = ?2",
+            params![repo_id, name],
+        )?;
+    }
+    for name in gone("SELECT ref_name FROM sync_state WHERE repo_id = ?1", "")? {
+        conn.execute(
+            "DELETE FROM sync_state WHERE repo_id = ?1 AND ref_name = ?2",
+            params![repo_id, name],
+        )?;
+    }
+    Ok(())
+}
+
+/// Deletes, or with `archived_at` marks, the repository's commits that no
+/// ref passing `refs` (or HEAD) reaches any more. Rows keyed by commit id alone are kept
+/// while another repository still has the commit. Archived commits that
+/// became reachable again are unmarked.
+pub fn prune_unreachable(
+    conn: &mut Connection,
+    repo: &Repository,
+    repo_id: i64,
+    refs: &RefFilter,
+    archived_at: Option<i64>,
+    counts: &mut PruneCounts,
+) -> IngestResult<()> {
+    let mut walk = repo.revwalk()?;
+    let tips = repo
+        .references()?
+        .flatten()
+        .filter(|reference| refs.matches_reference(reference))
+        .filter_map(|reference| reference.peel_to_commit().ok())
+        .chain(repo.head().ok().and_then(|head| head.peel_to_commit().ok()));
+    for tip in tips {
+        walk.push(tip.id())?;
+    }
+    let reachable: HashSet<String> = walk.flatten().map(|oid| oid.to_string()).collect();
+
+    let stored: Vec<(String, bool)> = conn
+        .prepare("SELECT id, archived_at IS NOT NULL FROM commit_details WHERE repo_id = ?1")?
+        .query_map(params![repo_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let tx = conn.transaction()?;
+    for (id, archived) in stored {
+        let is_reachable = reachable.contains(&id);
+        match archived_at {
+            Some(_) if is_reachable && archived => {
+                tx.execute(
+                    "UPDATE commit_details SET archived_at = NULL WHERE repo_id = ?1 AND id = ?2",
+                    params![repo_id, id],
+                )?;
+            }
+            Some(at) if !is_reachable && !archived => {
+                counts.commits += tx.execute(
+                    "UPDATE commit_details SET archived_at = ?3 WHERE repo_id = ?1 AND id = ?2",
+                    params![repo_id, id, at],
+                )?;
+            }
+            None if !is_reachable => {
+                counts.commits += tx.execute(
+                    "DELETE FROM commit_details WHERE repo_id = ?1 AND id = ?2",
+                    params![repo_id, id],
+                )?;
+                tx.execute(
+                    "DELETE FROM commit_relation WHERE repo_id = ?1 AND child = ?2",
+                    params![repo_id, id],
+                )?;
+                tx.execute(
+                    "DELETE FROM commit_remote_reachability WHERE repo_id = ?1 AND commit_id = ?2",
+                    params![repo_id, id],
+                )?;
+                tx.execute(
+                    "DELETE FROM merges WHERE repo_id = ?1 AND commit_id = ?2",
+                    params![repo_id, id],
+                )?;
+                tx.execute(
+                    "DELETE FROM merge_commits WHERE repo_id = ?1 AND merge_id = ?2",
+                    params![repo_id, id],
+                )?;
+                let shared = tx
+                    .prepare_cached("SELECT 1 FROM commit_details WHERE id = ?1")?
+                    .exists(params![id])?;
+                if !shared {
+                    for table in COMMIT_TABLES {
+                        tx.execute(
+                            &format!("DELETE FROM {} WHERE commit_id = ?1", table),
+                            params![id],
+                        )?;
+                    }
+                    tx.execute(
+                        "DELETE FROM embeddings WHERE kind = 'commit' AND object_id = ?1",
+                        params![id],
+                    )?;
+                }
+            }
+            _ => {}
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
@@ -0,0 +1,679 @@
+use crate::cli::{OutputFormat, StatsReport};
+use crate::language::NOT_CODE;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, Params, Result};
+
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Flags that shape the built-in reports.
+#[derive(Default)]
+pub struct StatsOptions {
+    /// Leaves files flagged by `--detect-generated` out of file reports.
+    pub exclude_generated: bool,
+    /// `--path` globs naming the source files, for reports that tell code
+    /// from everything else.
+    pub paths: Vec<String>,
+    /// `--limit`, for reports that list a sample inside one row.
+    pub limit: Option<usize>,
+    /// Lists refs sharing a target once, in ref reports.
+    pub resolve_ambiguous_refs: bool,
+}
+
+pub fn open_read_only(db_path: &str) -> Result<Connection> {
+    Connection::open_with_flags(
+        db_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+}
+
+pub fn run_query(conn: &Connection, sql: &str) -> Result<QueryResult> {
+    run_query_with(conn, sql, [])
+}
+
+pub fn run_query_with<P: Params>(conn: &Connection, sql: &str, params: P) -> Result<QueryResult> {
+    let mut stmt = conn.prepare(sql)?;
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+    let column_count = columns.len();
+
+    let rows = stmt
+        .query_map(params, |row| {
+            (0..column_count)
+                .map(|i| row.get::<_, Value>(i))
+                .collect::<Result<Vec<_>>>()
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(QueryResult { columns, rows })
+}
+
+/// Tables and columns a report reads, checked up front so databases imported
+/// by an older version fail with a readable message instead of "no such table".
+fn required_schema(report: &StatsReport) -> &'static [(&'static str, &'static [&'static str])] {
+    match report {
+        StatsReport::DuplicateMessages => &[("commit_details", &["id", "message"])],
+        StatsReport::Freshness => &[
+            ("commit_details", &["repo_id", "id", "date"]),
+            (
+                "ref_details",
+                &["repo_id", "name", "id", "resolved_oid", "is_remote_head"],
+            ),
+            ("repo_info", &["repo_id", "key", "value"]),
+            ("repositories", &["repo_id", "name"]),
+        ],
+        StatsReport::DuplicateRefTargets => &[
+            ("ref_details", &["repo_id", "name", "id", "kind"]),
+            ("repositories", &["repo_id", "name"]),
+        ],
+        StatsReport::TopAuthors => &[("commit_details", &["id", "author", "date"])],
+        StatsReport::CommitsPerMonth => &[("commit_details", &["id", "date"])],
+        StatsReport::LargestCommits => &[
+            ("commit_details", &["id", "author", "date", "message"]),
+            ("commit_files", &["commit_id", "insertions", "deletions"]),
+        ],
+        StatsReport::MergeRatio => &[
+            ("commit_details", &["repo_id", "id"]),
+            ("commit_relation", &["repo_id", "child"]),
+            ("repositories", &["repo_id", "name"]),
+        ],
+        StatsReport::OrphanedRefs => &[
+            ("commit_details", &["repo_id", "id"]),
+            ("ref_details", &["repo_id", "name", "id", "resolved_oid"]),
+            ("tags", &["repo_id", "name", "target_oid"]),
+            ("repositories", &["repo_id", "name"]),
+        ],
+        StatsReport::Hotspots => &[
+            ("commit_details", &["id", "author"]),
+            (
+                "commit_files",
+                &[
+                    "commit_id",
+                    "path",
+                    "insertions",
+                    "deletions",
+                    "is_generated",
+                ],
+            ),
+        ],
+        StatsReport::TopImpact => &[
+            (
+                "commit_details",
+                &["repo_id", "id", "author", "date", "message", "impact_rank"],
+            ),
+            ("repositories", &["repo_id", "name"]),
+        ],
+        StatsReport::PushActivity => &[(
+            "reflog",
+            &["repo_id", "ref_name", "new_oid", "time", "tz_offset"],
+        )],
+        StatsReport::AuthorNetwork => &[
+            ("commit_details", &["repo_id", "id", "author"]),
+            ("commit_files", &["commit_id", "path", "is_generated"]),
+        ],
+        StatsReport::UntestedChanges => &[
+            ("commit_details", &["id", "date", "touches_tests"]),
+            (
+                "commit_files",
+                &["commit_id", "path", "language", "is_test", "is_generated"],
+            ),
+        ],
+    }
+}
+
+pub fn check_schema(conn: &Connection, report: &StatsReport) -> Result<(), String> {
+    for (table, columns) in required_schema(report) {
+        let existing: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")
+            .and_then(|mut stmt| {
+                stmt.query_map(params![table], |row| row.get(0))?
+                    .collect::<Result<_>>()
+            })
+            .map_err(|e| e.to_string())?;
+
+        let missing = if existing.is_empty() {
+            Some(format!("table {}", table))
+        } else {
+            columns
+                .iter()
+                .find(|column| !existing.iter().any(|c| c == *column))
+                .map(|column| format!("column {}.{}", table, column))
+        };
+        if let Some(missing) = missing {
+            return Err(format!(
+                "this database was imported before the {} report existed (missing {}); \
+                 run the migrate command or an import to upgrade it",
+                report.name(),
+                missing
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Commits whose message matches the FTS5 `query`, or with `patches` whose
+/// stored patch text does (one row per matching file), best match first.
+/// Commits shared by several repositories are listed once. The matches are
+/// materialized first because snippet() can't run inside the grouping.
+pub fn search(conn: &Connection, query: &str, patches: bool) -> Result<QueryResult> {
+    let sql = if patches {
+        "WITH hits AS MATERIALIZED (
+             SELECT p.commit_id AS id, c.author, datetime(c.date, 'unixepoch') AS date, p.path,
+                    snippet(patch_search, 0, '[', ']', '...', 12) AS snippet, s.rank
+             FROM patch_search s
+             JOIN commit_patches p ON p.rowid = s.rowid
+             LEFT JOIN commit_details c ON c.id = p.commit_id
+             WHERE patch_search MATCH ?1
+         )
+         SELECT id, author, date, path, snippet FROM hits
+         GROUP BY id, path
+         ORDER BY MIN(rank)"
+    } else {
+        "WITH hits AS MATERIALIZED (
+             SELECT c.id, c.author, datetime(c.date, 'unixepoch') AS date,
+                    substr(c.message, 1, instr(c.message || char(10), char(10)) - 1) AS summary,
+                    snippet(commit_search, 0, '[', ']', '...', 12) AS snippet, s.rank
+             FROM commit_search s
+             JOIN commit_details c ON c.rowid = s.rowid
+             WHERE commit_search MATCH ?1
+         )
+         SELECT id, author, date, summary, snippet FROM hits
+         GROUP BY id
+         ORDER BY MIN(rank)"
+    };
+    run_query_with(conn, sql, params![query])
+}
+
+/// Search needs the FTS5 tables, which an older read-only database lacks.
+pub fn check_search_index(conn: &Connection) -> Result<(), String> {
+    let exists = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE name = 'commit_search'")
+        .and_then(|mut stmt| stmt.exists([]))
+        .map_err(|e| e.to_string())?;
+    if !exists {
+        return Err(String::from(
+            "this database has no search index; run the migrate command or an import to upgrade it",
+        ));
+    }
+    Ok(())
+}
+
+/// The commits of the author named or mailed `author`, oldest first, with
+/// the files, insertions and deletions of each. Through the authors table
+/// a canonical (mailmap) name or email also finds the commits made under
+/// the aliases it maps.
+pub fn author_timeline(conn: &Connection, author: &str) -> Result<QueryResult> {
+    // Databases from before the authors table only match the raw identity.
+    let canonical = if conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE name = 'authors'")?
+        .exists([])?
+    {
+        "OR author_id IN (SELECT author_id FROM authors
+                          WHERE name = ?1 COLLATE NOCASE OR email = ?1 COLLATE NOCASE)"
+    } else {
+        ""
+    };
+    // DISTINCT folds commits shared by several imported repositories before
+    // commit_files, keyed by commit alone, is joined.
+    run_query_with(
+        conn,
+        &format!(
+            "SELECT c.id,
+                    datetime(c.date, 'unixepoch') AS date,
+                    c.summary,
+                    COUNT(f.path) AS files,
+                    COALESCE(SUM(f.insertions), 0) AS insertions,
+                    COALESCE(SUM(f.deletions), 0) AS deletions
+             FROM (SELECT DISTINCT id, date,
+                          substr(message, 1, instr(message || char(10), char(10)) - 1) AS summary
+                   FROM commit_details
+                   WHERE author = ?1 COLLATE NOCASE OR author_email = ?1 COLLATE NOCASE
+                         {}) c
+             LEFT JOIN commit_files f ON f.commit_id = c.id
+             GROUP BY c.id
+             ORDER BY c.date ASC, c.id ASC",
+            canonical
+        ),
+        params![author],
+    )
+}
+
+/// Every commit that touched `path` or, via file_identity, any other name
+/// the file had in that repository, newest first with its diffstat.
+pub fn file_history(conn: &Connection, path: &str, repo: Option<&str>) -> Result<QueryResult> {
+    // Databases from before file_identity only match the path itself.
+    let paths = if conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE name = 'file_identity'")?
+        .exists([])?
+    {
+        "SELECT repo_id, path FROM file_identity
+         WHERE file_id IN (SELECT file_id FROM file_identity WHERE path = ?1)
+         UNION SELECT repo_id, ?1 FROM repositories"
+    } else {
+        "SELECT repo_id, ?1 AS path FROM repositories"
+    };
+    run_query_with(
+        conn,
+        &format!(
+            "WITH paths AS ({})
+             SELECT c.id, r.name AS repo, c.author,
+                    datetime(c.date, 'unixepoch') AS date, c.subject,
+                    f.path, f.old_path, f.change_type, f.insertions, f.deletions
+             FROM commit_files f
+             JOIN commit_details c ON c.id = f.commit_id
+             JOIN paths p ON p.repo_id = c.repo_id AND p.path = f.path
+             JOIN repositories r ON r.repo_id = c.repo_id
+             WHERE ?2 IS NULL OR r.name = ?2
+             ORDER BY c.date DESC, c.id",
+            paths
+        ),
+        params![path, repo],
+    )
+}
+
+pub fn stats(
+    conn: &Connection,
+    report: &StatsReport,
+    options: &StatsOptions,
+) -> Result<QueryResult> {
+    match report {
+        StatsReport::DuplicateMessages => duplicate_messages(conn),
+        StatsReport::Freshness => freshness(conn, options.resolve_ambiguous_refs),
+        StatsReport::DuplicateRefTargets => duplicate_ref_targets(conn),
+        StatsReport::TopAuthors => top_authors(conn),
+        StatsReport::CommitsPerMonth => commits_per_month(conn),
+        StatsReport::LargestCommits => largest_commits(conn),
+        StatsReport::MergeRatio => merge_ratio(conn),
+        StatsReport::OrphanedRefs => orphaned_refs(conn, options.resolve_ambiguous_refs),
+        StatsReport::Hotspots => hotspots(conn, options.exclude_generated),
+        StatsReport::UntestedChanges => untested_changes(conn, &options.paths, options.limit),
+        StatsReport::AuthorNetwork => author_network(conn, options.exclude_generated),
+        StatsReport::PushActivity => push_activity(conn),
+        StatsReport::TopImpact => top_impact(conn, options.limit),
+    }
+}
+
+fn duplicate_messages(conn: &Connection) -> Result<QueryResult> {
+    run_query(
+        conn,
+        "SELECT COUNT(DISTINCT id) AS count,
+                substr(message, 1, instr(message || char(10), char(10)) - 1) AS summary,
+                group_concat(DISTINCT id) AS ids
+         FROM commit_details
+         GROUP BY message
+         HAVING COUNT(DISTINCT id) > 1
+         ORDER BY count DESC, summary ASC",
+    )
+}
+
+fn freshness(conn: &Connection, resolve_ambiguous_refs: bool) -> Result<QueryResult> {
+    // Measured against each repository's last import rather than now, so
+    // the answer matches the data; repositories without imported_at fall
+    // back to now. Branch names are unique within a repository, so grouping
+    // by them keeps every row unless refs sharing a target are resolved.
+    run_query_with(
+        conn,
+        "SELECT repository, imported_at, group_concat(branch, ',') AS branch, last_commit, age_days
+         FROM (SELECT p.name AS repository,
+                      datetime(CAST(i.value AS INTEGER), 'unixepoch') AS imported_at,
+                      r.name AS branch,
+                      c.id AS target,
+                      datetime(c.date, 'unixepoch') AS last_commit,
+                      CAST((COALESCE(CAST(i.value AS INTEGER), strftime('%s', 'now')) - c.date)
+                           / 86400 AS INTEGER) AS age_days
+               FROM ref_details r
+               JOIN commit_details c
+                 ON c.repo_id = r.repo_id AND c.id = COALESCE(r.resolved_oid, r.id)
+               JOIN repositories p ON p.repo_id = r.repo_id
+               LEFT JOIN repo_info i ON i.repo_id = r.repo_id AND i.key = 'imported_at'
+               WHERE (r.name LIKE 'refs/heads/%' OR r.name LIKE 'refs/remotes/%')
+                 AND r.is_remote_head = 0
+               ORDER BY branch)
+         GROUP BY repository, CASE WHEN ?1 THEN target ELSE branch END
+         ORDER BY repository ASC, age_days ASC, branch ASC",
+        params![resolve_ambiguous_refs],
+    )
+}
+
+fn duplicate_ref_targets(conn: &Connection) -> Result<QueryResult> {
+    // Symbolic refs always share their target's OID, so only direct refs count.
+    // Forks naturally share targets, so refs are only compared within a repository.
+    run_query(
+        conn,
+        "SELECT p.name AS repository,
+                r.id AS target,
+                COUNT(*) AS count,
+                group_concat(r.name, ',') AS refs
+         FROM ref_details r
+         JOIN repositories p ON p.repo_id = r.repo_id
+         WHERE r.kind = 'Direct'
+         GROUP BY r.repo_id, r.id
+         HAVING COUNT(*) > 1
+         ORDER BY count DESC, repository ASC, target ASC",
+    )
+}
+
+fn top_authors(conn: &Connection) -> Result<QueryResult> {
+    // Commits shared by several imported repositories count once.
+    run_query(
+        conn,
+        "SELECT author,
+                COUNT(DISTINCT id) AS commits,
+                datetime(MIN(date), 'unixepoch') AS first_commit,
+                datetime(MAX(date), 'unixepoch') AS last_commit
+         FROM commit_details
+         GROUP BY author
+         ORDER BY commits DESC, author ASC",
+    )
+}
+
+fn commits_per_month(conn: &Connection) -> Result<QueryResult> {
+    run_query(
+        conn,
+        "SELECT strftime('%Y-%m', date, 'unixepoch') AS month,
+                COUNT(DISTINCT id) AS commits,
+                COUNT(DISTINCT author) AS authors
+         FROM commit_details
+         GROUP BY month
+         ORDER BY month ASC",
+    )
+}
+
+fn largest_commits(conn: &Connection) -> Result<QueryResult> {
+    // commit_files is keyed by commit only, so commits are deduplicated
+    // before the join to avoid counting a shared commit's files twice.
+    run_query(
+        conn,
+        "SELECT c.id,
+                c.author,
+                datetime(c.date, 'unixepoch') AS date,
+                COUNT(*) AS files,
+                SUM(f.insertions) AS insertions,
+                SUM(f.deletions) AS deletions,
+                c.summary
+         FROM (SELECT DISTINCT id, author, date,
+                      substr(message, 1, instr(message || char(10), char(10)) - 1) AS summary
+               FROM commit_details) c
+         JOIN commit_files f ON f.commit_id = c.id
+         GROUP BY c.id
+         ORDER BY SUM(f.insertions) + SUM(f.deletions) DESC, c.id ASC",
+    )
+}
+
+fn merge_ratio(conn: &Connection) -> Result<QueryResult> {
+    run_query(
+        conn,
+        "SELECT p.name AS repository,
+                COUNT(*) AS commits,
+                SUM(c.parents > 1) AS merges,
+                ROUND(100.0 * SUM(c.parents > 1) / COUNT(*), 1) AS merge_percent
+         FROM (SELECT d.repo_id,
+                      (SELECT COUNT(*) FROM commit_relation r
+                       WHERE r.repo_id = d.repo_id AND r.child = d.id) AS parents
+               FROM commit_details d) c
+         JOIN repositories p ON p.repo_id = c.repo_id
+         GROUP BY c.repo_id
+         ORDER BY repository ASC",
+    )
+}
+
+fn orphaned_refs(conn: &Connection, resolve_ambiguous_refs: bool) -> Result<QueryResult> {
+    // Refs whose commit was never imported: outside a bounded walk, pointing
+    // at a non-commit object, or left over from history that was rewritten.
+    // Annotated tags point at a tag object, so they are peeled through `tags`.
+    run_query_with(
+        conn,
+        "SELECT repository, group_concat(ref, ',') AS ref, target
+         FROM (SELECT p.name AS repository,
+                      r.repo_id,
+                      r.name AS ref,
+                      COALESCE(t.target_oid, r.resolved_oid, r.id) AS target
+               FROM ref_details r
+               JOIN repositories p ON p.repo_id = r.repo_id
+               LEFT JOIN tags t ON t.repo_id = r.repo_id AND 'refs/tags/' || t.name = r.name
+               ORDER BY ref) o
+         WHERE NOT EXISTS (
+             SELECT 1 FROM commit_details c WHERE c.repo_id = o.repo_id AND c.id = o.target
+         )
+         GROUP BY repository, CASE WHEN ?1 THEN target ELSE ref END
+         ORDER BY repository ASC, ref ASC",
+        params![resolve_ambiguous_refs],
+    )
+}
+
+/// Files by the commits that changed them, then by lines changed.
+/// `is_generated` is NULL for files imported without `--detect-generated`,
+/// which `exclude_generated` keeps.
+fn hotspots(conn: &Connection, exclude_generated: bool) -> Result<QueryResult> {
+    run_query_with(
+        conn,
+        "SELECT f.path,
+                COUNT(*) AS commits,
+                COUNT(DISTINCT c.author) AS authors,
+                SUM(f.insertions) AS insertions,
+                SUM(f.deletions) AS deletions,
+                MAX(f.is_generated) AS is_generated
+         FROM commit_files f
+         JOIN (SELECT DISTINCT id, author FROM commit_details) c ON c.id = f.commit_id
+         WHERE NOT ?1 OR COALESCE(f.is_generated, 0) = 0
+         GROUP BY f.path
+         ORDER BY commits DESC, SUM(f.insertions) + SUM(f.deletions) DESC, f.path ASC",
+        params![exclude_generated],
+    )
+}
+
+/// How many code commits touched no test file, and up to `limit` (10 by
+/// default) of them, newest first. A code commit changes a source file:
+/// one matching `paths`, or without them one in a programming language,
+/// never a test or a generated file.
+fn untested_changes(
+    conn: &Connection,
+    paths: &[String],
+    limit: Option<usize>,
+) -> Result<QueryResult> {
+    let source = if paths.is_empty() {
+        let not_code: Vec<String> = NOT_CODE
+            .iter()
+            .map(|language| format!("'{}'", language))
+            .collect();
+        format!("f.language NOT IN ({})", not_code.join(", "))
+    } else {
+        let globs: Vec<String> = (2..paths.len() + 2)
+            .map(|i| format!("f.path GLOB ?{}", i))
+            .collect();
+        format!("({})", globs.join(" OR "))
+    };
+    let limit = limit.unwrap_or(10) as i64;
+    run_query_with(
+        conn,
+        &format!(
+            "WITH code AS (
+                 SELECT DISTINCT c.id, c.date, c.touches_tests
+                 FROM commit_details c
+                 JOIN commit_files f ON f.commit_id = c.id
+                 WHERE NOT f.is_test AND COALESCE(f.is_generated, 0) = 0 AND {}
+             )
+             SELECT COUNT(*) AS code_commits,
+                    COALESCE(SUM(NOT touches_tests), 0) AS untested,
+                    ROUND(100.0 * SUM(NOT touches_tests) / COUNT(*), 1) AS untested_percent,
+                    (SELECT group_concat(id, ',')
+                     FROM (SELECT id FROM code WHERE NOT touches_tests
+                           ORDER BY date DESC, id LIMIT ?1)) AS sample
+             FROM code",
+            source
+        ),
+        params_from_iter(
+            std::iter::once(Value::Integer(limit))
+                .chain(paths.iter().map(|path| Value::Text(path.clone()))),
+        ),
+    )
+}
+
+/// Every pair of authors who both changed a file, with how many files they
+/// share, most first. Files are told apart per repository.
+fn author_network(conn: &Connection, exclude_generated: bool) -> Result<QueryResult> {
+    run_query_with(
+        conn,
+        "WITH touched AS (
+             SELECT DISTINCT c.repo_id, c.author, f.path
+             FROM commit_files f
+             JOIN commit_details c ON c.id = f.commit_id
+             WHERE NOT ?1 OR COALESCE(f.is_generated, 0) = 0
+         )
+         SELECT a.author, b.author AS coauthor, COUNT(*) AS shared_files
+         FROM touched a
+         JOIN touched b ON b.repo_id = a.repo_id AND b.path = a.path AND b.author > a.author
+         GROUP BY a.author, b.author
+         ORDER BY shared_files DESC, a.author ASC, b.author ASC",
+        params![exclude_generated],
+    )
+}
+
+/// The `limit` (10 by default) commits with the highest impact_rank, which
+/// every import rescores.
+fn top_impact(conn: &Connection, limit: Option<usize>) -> Result<QueryResult> {
+    run_query_with(
+        conn,
+        "SELECT r.name AS repository,
+                c.id,
+                c.author,
+                datetime(c.date, 'unixepoch') AS date,
+                ROUND(c.impact_rank, 3) AS impact_rank,
+                substr(c.message, 1, instr(c.message || char(10), char(10)) - 1) AS summary
+         FROM commit_details c
+         JOIN repositories r ON r.repo_id = c.repo_id
+         WHERE c.impact_rank IS NOT NULL
+         ORDER BY c.impact_rank DESC, c.date DESC, c.id ASC
+         LIMIT ?1",
+        params![limit.unwrap_or(10) as i64],
+    )
+}
+
+/// Reflog entries (stored by `--with-reflog`) by weekday and hour in the
+/// time zone they were written in: when work landed locally, which
+/// rewritten commit dates can't show. A commit is logged by HEAD and its
+/// branch alike, so entries moving a repository to the same commit at the
+/// same second count once.
+fn push_activity(conn: &Connection) -> Result<QueryResult> {
+    run_query(
+        conn,
+        "SELECT substr('SunMonTueWedThuFriSat', 1 + 3 * strftime('%w', local, 'unixepoch'), 3)
+                    AS weekday,
+                CAST(strftime('%H', local, 'unixepoch') AS INTEGER) AS hour,
+                COUNT(*) AS entries
+         FROM (SELECT DISTINCT repo_id, new_oid, time, time + 60 * tz_offset AS local
+               FROM reflog)
+         GROUP BY strftime('%w', local, 'unixepoch'), hour
+         ORDER BY strftime('%w', local, 'unixepoch'), hour",
+    )
+}
+
+pub fn print_result(result: &QueryResult, format: &OutputFormat) {
+    match format {
+        OutputFormat::Table => print_table(result),
+        OutputFormat::Json => println!("{}", to_json(result)),
+        // Rejected at parse time for everything but export.
+        OutputFormat::Sql
+        | OutputFormat::Edgelist
+        | OutputFormat::Csv
+        | OutputFormat::Dot
+        | OutputFormat::GraphMl => unreachable!(),
+    }
+}
+
+fn print_table(result: &QueryResult) {
+    let cells: Vec<Vec<String>> = result
+        .rows
+        .iter()
+        .map(|row| row.iter().map(value_to_text).collect())
+        .collect();
+
+    let mut widths: Vec<usize> = result.columns.iter().map(|c| c.chars().count()).collect();
+    for row in &cells {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    let format_row = |row: &[String]| {
+        row.iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:<width$}", cell, width = widths[i]))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    };
+
+    println!("{}", format_row(&result.columns));
+    println!(
+        "{}",
+        widths
+            .iter()
+            .map(|w| "-".repeat(*w))
+            .collect::<Vec<_>>()
+            .join("-+-")
+    );
+    for row in &cells {
+        println!("{}", format_row(row));
+    }
+}
+
+fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("NULL"),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => f.to_string(),
+        // Multi-line messages would break the table layout.
+        Value::Text(s) => s.replace('\n', "\\n"),
+        Value::Blob(b) => format!("<{} bytes>", b.len()),
+    }
+}
+
+pub fn to_json(result: &QueryResult) -> String {
+    let objects: Vec<String> = result
+        .rows
+        .iter()
+        .map(|row| row_to_json(&result.columns, row))
+        .collect();
+
+    format!("[{}]", objects.join(","))
+}
+
+/// One row as a JSON object keyed by column name.
+pub fn row_to_json(columns: &[String], row: &[Value]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .zip(row)
+        .map(|(column, value)| format!("{}:{}", json_string(column), value_to_json(value)))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn value_to_json(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("null"),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) if f.is_finite() => f.to_string(),
+        Value::Real(_) => String::from("null"),
+        Value::Text(s) => json_string(s),
+        Value::Blob(b) => format!("{}", b.len()),
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
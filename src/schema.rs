@@ -0,0 +1,1048 @@
+use crate::error::{IngestError, IngestResult};
+use crate::info;
+use crate::language::{is_test_path, language};
+use crate::{record_file_identity, unix_now};
+use rusqlite::{params, Connection, Result};
+
+/// Version a database is at after every migration below has run.
+pub const SCHEMA_VERSION: i64 = 26;
+
+/// One step of the schema history. Steps are applied in order, each inside
+/// the same savepoint as the `schema_version` row recording it.
+struct Migration {
+    version: i64,
+    description: &'static str,
+    apply: fn(&Connection) -> Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        apply: initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "ref history",
+        apply: ref_history,
+    },
+    Migration {
+        version: 3,
+        description: "branch divergence",
+        apply: branch_divergence,
+    },
+    Migration {
+        version: 4,
+        description: "signature metadata",
+        apply: signatures,
+    },
+    Migration {
+        version: 5,
+        description: "submodules",
+        apply: submodules,
+    },
+    Migration {
+        version: 6,
+        description: "rollup tables",
+        apply: rollups,
+    },
+    Migration {
+        version: 7,
+        description: "full-text search",
+        apply: full_text_search,
+    },
+    Migration {
+        version: 8,
+        description: "embeddings",
+        apply: embeddings,
+    },
+    Migration {
+        version: 9,
+        description: "archived commits",
+        apply: archived_commits,
+    },
+    Migration {
+        version: 10,
+        description: "file identity",
+        apply: file_identity,
+    },
+    Migration {
+        version: 11,
+        description: "commit notes",
+        apply: commit_notes,
+    },
+    Migration {
+        version: 12,
+        description: "reflog",
+        apply: reflog,
+    },
+    Migration {
+        version: 13,
+        description: "stashes",
+        apply: stashes,
+    },
+    Migration {
+        version: 14,
+        description: "repository state in import runs",
+        apply: import_run_state,
+    },
+    Migration {
+        version: 15,
+        description: "canonical authors",
+        apply: authors,
+    },
+    Migration {
+        version: 16,
+        description: "file languages",
+        apply: file_languages,
+    },
+    Migration {
+        version: 17,
+        description: "line count snapshots",
+        apply: loc_snapshots,
+    },
+    Migration {
+        version: 18,
+        description: "merges",
+        apply: merges,
+    },
+    Migration {
+        version: 19,
+        description: "pull requests",
+        apply: pull_requests,
+    },
+    Migration {
+        version: 20,
+        description: "issue reference actions",
+        apply: issue_ref_actions,
+    },
+    Migration {
+        version: 21,
+        description: "partial import runs",
+        apply: import_run_complete,
+    },
+    Migration {
+        version: 22,
+        description: "generated files",
+        apply: generated_files,
+    },
+    Migration {
+        version: 23,
+        description: "remote tips",
+        apply: remote_tips,
+    },
+    Migration {
+        version: 24,
+        description: "test files",
+        apply: test_files,
+    },
+    Migration {
+        version: 25,
+        description: "binary files",
+        apply: binary_files,
+    },
+    Migration {
+        version: 26,
+        description: "impact rank",
+        apply: impact_rank,
+    },
+];
+
+const INITIAL_TABLES: &[&str] = &[
+    "CREATE TABLE repositories (
+        repo_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL UNIQUE,
+        path TEXT NOT NULL
+    )",
+    "CREATE TABLE commit_details (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        id TEXT NOT NULL,
+        author TEXT NOT NULL,
+        date INTEGER NOT NULL,
+        message TEXT NOT NULL,
+        author_is_committer INTEGER NOT NULL,
+        classified_type TEXT NOT NULL,
+        reverts_oid TEXT,
+        reverted_by TEXT,
+        log_author TEXT NOT NULL,
+        log_date TEXT NOT NULL,
+        subject_slug TEXT NOT NULL,
+        author_email TEXT NOT NULL,
+        committer_name TEXT NOT NULL,
+        committer_email TEXT NOT NULL,
+        author_time INTEGER NOT NULL,
+        author_tz_offset INTEGER NOT NULL,
+        commit_time INTEGER NOT NULL,
+        commit_tz_offset INTEGER NOT NULL,
+        cc_type TEXT,
+        cc_scope TEXT,
+        is_breaking INTEGER NOT NULL,
+        subject TEXT NOT NULL,
+        body TEXT NOT NULL,
+        PRIMARY KEY (repo_id, id)
+    )",
+    "CREATE TABLE commit_footers (
+        commit_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (commit_id, position)
+    )",
+    "CREATE TABLE commit_co_authors (
+        commit_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        email TEXT NOT NULL,
+        PRIMARY KEY (commit_id, position)
+    )",
+    "CREATE TABLE commit_issue_refs (
+        commit_id TEXT NOT NULL,
+        issue_ref TEXT NOT NULL,
+        PRIMARY KEY (commit_id, issue_ref)
+    )",
+    "CREATE TABLE commit_relation (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        parent TEXT NOT NULL,
+        child TEXT NOT NULL,
+        PRIMARY KEY (repo_id, parent, child)
+    )",
+    "CREATE TABLE repo_info (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (repo_id, key)
+    )",
+    "CREATE TABLE ref_details (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        name TEXT NOT NULL,
+        id TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        resolved_oid TEXT,
+        is_remote_head INTEGER NOT NULL,
+        PRIMARY KEY (repo_id, name, id)
+    )",
+    "CREATE TABLE tags (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        name TEXT NOT NULL,
+        target_oid TEXT,
+        tagger_name TEXT,
+        tagger_email TEXT,
+        tag_date INTEGER,
+        message TEXT,
+        PRIMARY KEY (repo_id, name)
+    )",
+    "CREATE TABLE commit_remote_reachability (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        commit_id TEXT NOT NULL,
+        remote_name TEXT NOT NULL,
+        reachable INTEGER NOT NULL,
+        PRIMARY KEY (repo_id, commit_id, remote_name)
+    )",
+    "CREATE TABLE tree_files (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        path TEXT NOT NULL,
+        blob_oid TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        mode INTEGER NOT NULL,
+        PRIMARY KEY (repo_id, path)
+    )",
+    "CREATE TABLE tree_entries (
+        commit_id TEXT NOT NULL,
+        path TEXT NOT NULL,
+        blob_oid TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        mode INTEGER NOT NULL,
+        PRIMARY KEY (commit_id, path)
+    )",
+    "CREATE TABLE blobs (
+        blob_oid TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        is_binary INTEGER NOT NULL,
+        content BLOB NOT NULL
+    )",
+    "CREATE TABLE file_blame (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        path TEXT NOT NULL,
+        start_line INTEGER NOT NULL,
+        line_count INTEGER NOT NULL,
+        commit_id TEXT NOT NULL,
+        author TEXT NOT NULL,
+        author_email TEXT NOT NULL,
+        PRIMARY KEY (repo_id, path, start_line)
+    )",
+    "CREATE TABLE sync_state (
+        repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+        ref_name TEXT NOT NULL,
+        oid TEXT NOT NULL,
+        PRIMARY KEY (repo_id, ref_name)
+    )",
+    "CREATE TABLE import_runs (
+        run_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL,
+        commits_added INTEGER NOT NULL,
+        refs_added INTEGER NOT NULL,
+        skipped INTEGER NOT NULL,
+        args TEXT NOT NULL
+    )",
+    "CREATE TABLE ingest_errors (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind TEXT NOT NULL,
+        object_id TEXT,
+        message TEXT NOT NULL,
+        recorded_at INTEGER NOT NULL
+    )",
+    "CREATE TABLE commit_files (
+        commit_id TEXT NOT NULL,
+        path TEXT NOT NULL,
+        old_path TEXT,
+        change_type TEXT NOT NULL,
+        insertions INTEGER NOT NULL,
+        deletions INTEGER NOT NULL,
+        PRIMARY KEY (commit_id, path)
+    )",
+    "CREATE TABLE commit_patches (
+        commit_id TEXT NOT NULL,
+        path TEXT NOT NULL,
+        patch TEXT NOT NULL,
+        PRIMARY KEY (commit_id, path)
+    )",
+    "CREATE TABLE commit_diffs (
+        commit_id TEXT PRIMARY KEY,
+        patch_path TEXT NOT NULL
+    )",
+];
+
+/// Highest migration applied to `conn`, 0 for new databases and for ones
+/// created before schema_version existed.
+pub fn schema_version(conn: &Connection) -> Result<i64> {
+    if !table_exists(conn, "schema_version")? {
+        return Ok(0);
+    }
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// Brings `conn` up to `SCHEMA_VERSION`, creating every table in an empty
+/// database. Returns the version it started from. A database written by a
+/// newer build is refused rather than modified.
+pub fn migrate(conn: &Connection) -> IngestResult<i64> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    let from = schema_version(conn)?;
+    if from > SCHEMA_VERSION {
+        return Err(IngestError::Schema(format!(
+            "database schema version {} is newer than this build supports ({})",
+            from, SCHEMA_VERSION
+        )));
+    }
+
+    // A new database is simply created at the latest version.
+    let fresh = from == 0 && !table_exists(conn, "commit_details")?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from) {
+        if !fresh && (from > 0 || migration.version > 1) {
+            info!(
+                "Upgrading database schema to version {}: {}",
+                migration.version, migration.description
+            );
+        }
+        // Plain SQL savepoints work on a shared connection and nest inside
+        // --single-transaction.
+        conn.execute_batch("SAVEPOINT migration")?;
+        let applied = (migration.apply)(conn).and_then(|()| {
+            conn.execute(
+                "INSERT INTO schema_version (version, description, applied_at)
+                 VALUES (?1, ?2, ?3)",
+                params![migration.version, migration.description, unix_now()],
+            )
+        });
+        match applied {
+            Ok(_) => conn.execute_batch("RELEASE migration")?,
+            Err(e) => {
+                conn.execute_batch("ROLLBACK TO migration; RELEASE migration")?;
+                return Err(e.into());
+            }
+        }
+    }
+    Ok(from)
+}
+
+/// Creates the tables, or reconciles a database from before schema_version
+/// existed: missing tables are created, and a table whose columns differ is
+/// rebuilt with its rows copied over.
+fn initial_schema(conn: &Connection) -> Result<()> {
+    if !table_exists(conn, "commit_details")? {
+        for sql in INITIAL_TABLES {
+            conn.execute(sql, [])?;
+        }
+        return Ok(());
+    }
+
+    info!("Upgrading a database created before schema versioning...");
+    // The target layout, read back from SQLite rather than parsed from the DDL.
+    let target = Connection::open_in_memory()?;
+    for sql in INITIAL_TABLES {
+        target.execute(sql, [])?;
+    }
+    let tables: Vec<String> = target
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY rowid")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_>>()?;
+
+    for (table, sql) in tables.iter().zip(INITIAL_TABLES) {
+        let wanted = columns(&target, table)?;
+        let existing = columns(conn, table)?;
+        if existing.is_empty() {
+            conn.execute(sql, [])?;
+        } else if existing != wanted {
+            rebuild_table(conn, table, sql, &wanted, &existing)?;
+        }
+    }
+    Ok(())
+}
+
+/// Every ref change seen by an import, stamped with the run's start time
+/// (import_runs.started_at). `change` is created, updated, forced (the old
+/// target isn't an ancestor of the new one) or deleted, whose `target` is
+/// the last one seen.
+fn ref_history(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE ref_history (
+            repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+            recorded_at INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            target TEXT NOT NULL,
+            previous_target TEXT,
+            change TEXT NOT NULL
+        );
+        CREATE INDEX ref_history_name ON ref_history (repo_id, name)",
+    )
+}
+
+/// Results of the diverge command, one row per pair of refs as given on the
+/// command line, replaced when the pair is compared again. `ahead` counts
+/// the `side = 'left'` commits, `behind` the `side = 'right'` ones.
+fn branch_divergence(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE branch_divergence (
+            repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+            left_ref TEXT NOT NULL,
+            right_ref TEXT NOT NULL,
+            left_oid TEXT NOT NULL,
+            right_oid TEXT NOT NULL,
+            merge_base TEXT,
+            ahead INTEGER NOT NULL,
+            behind INTEGER NOT NULL,
+            computed_at INTEGER NOT NULL,
+            PRIMARY KEY (repo_id, left_ref, right_ref)
+        );
+        CREATE TABLE branch_divergence_commits (
+            repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+            left_ref TEXT NOT NULL,
+            right_ref TEXT NOT NULL,
+            side TEXT NOT NULL,
+            commit_id TEXT NOT NULL,
+            PRIMARY KEY (repo_id, left_ref, right_ref, commit_id)
+        )",
+    )
+}
+
+/// signature_status is signed or unsigned, NULL for rows imported before
+/// this version until they are re-imported with `--on-conflict update`.
+/// signature_type is gpg, ssh or x509; signing_key the OpenPGP issuer or the
+/// SSH public key. Signatures are not verified.
+fn signatures(conn: &Connection) -> Result<()> {
+    for table in ["commit_details", "tags"] {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN signature_status TEXT;
+             ALTER TABLE {table} ADD COLUMN signature_type TEXT;
+             ALTER TABLE {table} ADD COLUMN signing_key TEXT;"
+        ))?;
+    }
+    Ok(())
+}
+
+/// Submodules listed in each repository's .gitmodules. `head_oid` is the
+/// commit the superproject's HEAD pins; `child_repo_id` the repository the
+/// submodule was imported as with `--recurse-submodules`.
+fn submodules(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE submodules (
+            repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+            path TEXT NOT NULL,
+            name TEXT NOT NULL,
+            url TEXT,
+            head_oid TEXT,
+            child_repo_id INTEGER REFERENCES repositories (repo_id),
+            PRIMARY KEY (repo_id, path)
+        )",
+    )
+}
+
+/// Pre-aggregated commits per author per month and lines changed per
+/// directory per quarter, by commit date. Triggers keep them in step with
+/// commit_details and commit_files however rows get there: a commit's files
+/// count once both its details row (one per repository) and its
+/// commit_files rows exist, whichever is inserted last. Files at the top
+/// level count under the directory `.`.
+fn rollups(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE author_month_rollup (
+            repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+            author TEXT NOT NULL,
+            month TEXT NOT NULL,
+            commits INTEGER NOT NULL,
+            PRIMARY KEY (repo_id, author, month)
+        );
+        CREATE TABLE directory_quarter_rollup (
+            repo_id INTEGER NOT NULL REFERENCES repositories (repo_id),
+            directory TEXT NOT NULL,
+            quarter TEXT NOT NULL,
+            files_changed INTEGER NOT NULL,
+            insertions INTEGER NOT NULL,
+            deletions INTEGER NOT NULL,
+            PRIMARY KEY (repo_id, directory, quarter)
+        )",
+    )?;
+
+    // Each statement reads c (repo_id, author, date) and f (path,
+    // insertions, deletions) from the row source it is given; `sign` is 1 to
+    // add a contribution and -1 to take it away.
+    let month = "strftime('%Y-%m', c.date, 'unixepoch')";
+    let quarter = "strftime('%Y', c.date, 'unixepoch') || '-Q' || \
+                   ((CAST(strftime('%m', c.date, 'unixepoch') AS INTEGER) + 2) / 3)";
+    // Everything up to the last '/'.
+    let directory =
+        "COALESCE(NULLIF(rtrim(rtrim(f.path, replace(f.path, '/', '')), '/'), ''), '.')";
+    let add_commit = |source: &str, sign: i32| {
+        format!(
+            "INSERT INTO author_month_rollup (repo_id, author, month, commits)
+             SELECT c.repo_id, c.author, {month}, {sign} {source}
+             ON CONFLICT (repo_id, author, month) DO UPDATE
+             SET commits = commits + excluded.commits;"
+        )
+    };
+    let add_files = |source: &str, sign: i32| {
+        format!(
+            "INSERT INTO directory_quarter_rollup
+                 (repo_id, directory, quarter, files_changed, insertions, deletions)
+             SELECT c.repo_id, {directory}, {quarter}, {sign},
+                    {sign} * f.insertions, {sign} * f.deletions
+             {source}
+             ON CONFLICT (repo_id, directory, quarter) DO UPDATE
+             SET files_changed = files_changed + excluded.files_changed,
+                 insertions = insertions + excluded.insertions,
+                 deletions = deletions + excluded.deletions;"
+        )
+    };
+    let commit_row = |row: &str| {
+        format!("FROM (SELECT {row}.repo_id AS repo_id, {row}.author AS author, {row}.date AS date) c WHERE true")
+    };
+    let files_of_commit = |row: &str| {
+        format!(
+            "FROM (SELECT {row}.repo_id AS repo_id, {row}.date AS date) c, commit_files f
+             WHERE f.commit_id = {row}.id"
+        )
+    };
+    let commits_of_file = |row: &str| {
+        format!(
+            "FROM commit_details c,
+                  (SELECT {row}.path AS path, {row}.insertions AS insertions, {row}.deletions AS deletions) f
+             WHERE c.id = {row}.commit_id"
+        )
+    };
+
+    // Backfill: the per-row statements, summed over everything stored.
+    conn.execute_batch(&format!(
+        "INSERT INTO author_month_rollup (repo_id, author, month, commits)
+         SELECT c.repo_id, c.author, {month}, COUNT(*) FROM commit_details c GROUP BY 1, 2, 3;
+         INSERT INTO directory_quarter_rollup
+             (repo_id, directory, quarter, files_changed, insertions, deletions)
+         SELECT c.repo_id, {directory}, {quarter}, COUNT(*), SUM(f.insertions), SUM(f.deletions)
+         FROM commit_details c JOIN commit_files f ON f.commit_id = c.id
+         GROUP BY 1, 2, 3;"
+    ))?;
+
+    let triggers = [
+        (
+            "rollup_commit_insert AFTER INSERT ON commit_details",
+            add_commit(&commit_row("NEW"), 1) + &add_files(&files_of_commit("NEW"), 1),
+        ),
+        (
+            "rollup_commit_delete AFTER DELETE ON commit_details",
+            add_commit(&commit_row("OLD"), -1) + &add_files(&files_of_commit("OLD"), -1),
+        ),
+        (
+            "rollup_commit_update AFTER UPDATE OF repo_id, author, date ON commit_details",
+            add_commit(&commit_row("OLD"), -1)
+                + &add_files(&files_of_commit("OLD"), -1)
+                + &add_commit(&commit_row("NEW"), 1)
+                + &add_files(&files_of_commit("NEW"), 1),
+        ),
+        (
+            "rollup_file_insert AFTER INSERT ON commit_files",
+            add_files(&commits_of_file("NEW"), 1),
+        ),
+        (
+            "rollup_file_delete AFTER DELETE ON commit_files",
+            add_files(&commits_of_file("OLD"), -1),
+        ),
+        (
+            "rollup_file_update AFTER UPDATE OF commit_id, path, insertions, deletions ON commit_files",
+            add_files(&commits_of_file("OLD"), -1) + &add_files(&commits_of_file("NEW"), 1),
+        ),
+    ];
+    for (trigger, body) in triggers {
+        conn.execute_batch(&format!("CREATE TRIGGER {trigger} BEGIN {body} END"))?;
+    }
+    Ok(())
+}
+
+/// FTS5 indexes over commit messages and stored patch text, external
+/// content tables keyed by the rowid of the row they index, so the text
+/// isn't stored twice. Triggers keep them in sync like the rollups. A
+/// manual VACUUM may renumber those rowids; `INSERT INTO commit_search
+/// (commit_search) VALUES ('rebuild')` re-indexes afterwards.
+fn full_text_search(conn: &Connection) -> Result<()> {
+    for (index, table, column) in [
+        ("commit_search", "commit_details", "message"),
+        ("patch_search", "commit_patches", "patch"),
+    ] {
+        let remove = format!(
+            "INSERT INTO {index} ({index}, rowid, {column}) VALUES ('delete', OLD.rowid, OLD.{column});"
+        );
+        let add =
+            format!("INSERT INTO {index} (rowid, {column}) VALUES (NEW.rowid, NEW.{column});");
+        conn.execute_batch(&format!(
+            "CREATE VIRTUAL TABLE {index} USING fts5 ({column}, content = '{table}', content_rowid = 'rowid');
+             INSERT INTO {index} ({index}) VALUES ('rebuild');
+             CREATE TRIGGER {index}_insert AFTER INSERT ON {table} BEGIN {add} END;
+             CREATE TRIGGER {index}_delete AFTER DELETE ON {table} BEGIN {remove} END;
+             CREATE TRIGGER {index}_update AFTER UPDATE OF {column} ON {table} BEGIN {remove} {add} END;"
+        ))?;
+    }
+    Ok(())
+}
+
+/// Vectors from `embed`, keyed by what they embed (a commit id or blob oid)
+/// and the model, since vectors of different models can't be compared.
+fn embeddings(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE embeddings (
+            kind TEXT NOT NULL,
+            object_id TEXT NOT NULL,
+            model TEXT NOT NULL,
+            dimensions INTEGER NOT NULL,
+            vector BLOB NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (kind, object_id, model)
+        )",
+    )
+}
+
+/// Set by `prune --unreachable --archive` on commits no ref reaches any
+/// more, instead of deleting them.
+fn archived_commits(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE commit_details ADD COLUMN archived_at INTEGER")
+}
+
+/// One row per path a repository's files have had; paths joined by renames
+/// share a file_id. Filled from the commit_files already stored, which only
+/// knows renames imported since rename detection was added.
+fn file_identity(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE file_identity (
+            repo_id INTEGER NOT NULL,
+            path TEXT NOT NULL,
+            file_id INTEGER NOT NULL,
+            PRIMARY KEY (repo_id, path)
+        );
+        CREATE INDEX file_identity_file ON file_identity (file_id);",
+    )?;
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT c.repo_id, f.path,
+                CASE WHEN f.change_type = 'renamed' THEN f.old_path END
+         FROM commit_files f JOIN commit_details c ON c.id = f.commit_id",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let path: String = row.get(1)?;
+        let old_path: Option<String> = row.get(2)?;
+        record_file_identity(conn, row.get(0)?, &path, old_path.as_deref())?;
+    }
+    Ok(())
+}
+
+/// git notes, one row per annotated object and notes ref (refs/notes/commits
+/// unless another was used). Replaced on every import, like submodules.
+fn commit_notes(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE commit_notes (
+            repo_id INTEGER NOT NULL,
+            notes_ref TEXT NOT NULL,
+            commit_id TEXT NOT NULL,
+            note_oid TEXT NOT NULL,
+            message TEXT NOT NULL,
+            PRIMARY KEY (repo_id, notes_ref, commit_id)
+        );
+        CREATE INDEX commit_notes_commit ON commit_notes (commit_id);",
+    )
+}
+
+/// HEAD and branch reflog entries from `--with-reflog`. old_oid is all
+/// zeros when the ref was created.
+fn reflog(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE reflog (
+            repo_id INTEGER NOT NULL,
+            ref_name TEXT NOT NULL,
+            old_oid TEXT NOT NULL,
+            new_oid TEXT NOT NULL,
+            message TEXT,
+            committer_name TEXT,
+            committer_email TEXT,
+            time INTEGER NOT NULL,
+            tz_offset INTEGER NOT NULL,
+            PRIMARY KEY (repo_id, ref_name, time, old_oid, new_oid)
+        )",
+    )
+}
+
+/// The stash list as of the last import; stash_files is only filled with
+/// `--stash-diffs`.
+fn stashes(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE stashes (
+            repo_id INTEGER NOT NULL,
+            stash_index INTEGER NOT NULL,
+            message TEXT NOT NULL,
+            commit_id TEXT NOT NULL,
+            PRIMARY KEY (repo_id, stash_index)
+        );
+        CREATE TABLE stash_files (
+            repo_id INTEGER NOT NULL,
+            stash_index INTEGER NOT NULL,
+            path TEXT NOT NULL,
+            old_path TEXT,
+            change_type TEXT NOT NULL,
+            insertions INTEGER NOT NULL,
+            deletions INTEGER NOT NULL,
+            patch TEXT,
+            PRIMARY KEY (repo_id, stash_index, path)
+        );",
+    )
+}
+
+/// The repository as each import found it: which repository, HEAD, the
+/// operation in progress (clean, merge, rebase, ...) and dirty file counts,
+/// NULL for bare repositories and runs from before this version.
+fn import_run_state(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE import_runs ADD COLUMN repo_id INTEGER;
+         ALTER TABLE import_runs ADD COLUMN head_ref TEXT;
+         ALTER TABLE import_runs ADD COLUMN head_oid TEXT;
+         ALTER TABLE import_runs ADD COLUMN head_detached INTEGER;
+         ALTER TABLE import_runs ADD COLUMN operation TEXT;
+         ALTER TABLE import_runs ADD COLUMN staged_files INTEGER;
+         ALTER TABLE import_runs ADD COLUMN modified_files INTEGER;
+         ALTER TABLE import_runs ADD COLUMN untracked_files INTEGER;",
+    )
+}
+
+/// One row per person after .mailmap resolution; commit_details.author_id
+/// points at it. Filled for existing commits by their repository's next
+/// import.
+fn authors(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE authors (
+            author_id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            email TEXT NOT NULL,
+            UNIQUE (name, email)
+        );
+        ALTER TABLE commit_details ADD COLUMN author_id INTEGER REFERENCES authors (author_id);
+        CREATE INDEX commit_details_author_id ON commit_details (author_id);",
+    )
+}
+
+/// The language of each changed file, from its name (see language.rs);
+/// NULL for files it doesn't recognise.
+fn file_languages(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE commit_files ADD COLUMN language TEXT")?;
+    let rows: Vec<(i64, String)> = conn
+        .prepare("SELECT rowid, path FROM commit_files")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    let mut update = conn.prepare("UPDATE commit_files SET language = ?2 WHERE rowid = ?1")?;
+    for (rowid, path) in rows {
+        if let Some(language) = language(&path) {
+            update.execute(params![rowid, language])?;
+        }
+    }
+    Ok(())
+}
+
+/// Line counts of the tree at each tag or `--loc-ref`, one row per language
+/// (NULL when unrecognised) and top-level directory. code_lines leaves out
+/// blank lines; binary files aren't counted.
+fn loc_snapshots(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE loc_snapshots (
+            repo_id INTEGER NOT NULL,
+            ref_name TEXT NOT NULL,
+            commit_id TEXT NOT NULL,
+            commit_time INTEGER NOT NULL,
+            language TEXT,
+            directory TEXT NOT NULL,
+            files INTEGER NOT NULL,
+            lines INTEGER NOT NULL,
+            code_lines INTEGER NOT NULL,
+            bytes INTEGER NOT NULL,
+            taken_at INTEGER NOT NULL
+        );
+        CREATE INDEX loc_snapshots_ref ON loc_snapshots (repo_id, ref_name);",
+    )
+}
+
+/// One row per merge commit; mainline_parent is the first parent, and
+/// merge_commits lists the commits only the other parents reach. Filled for
+/// existing merges by their repository's next import.
+fn merges(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE merges (
+            repo_id INTEGER NOT NULL,
+            commit_id TEXT NOT NULL,
+            parent_count INTEGER NOT NULL,
+            mainline_parent TEXT NOT NULL,
+            merged_branch TEXT,
+            target_branch TEXT,
+            introduced_commits INTEGER NOT NULL,
+            PRIMARY KEY (repo_id, commit_id)
+        );
+        CREATE TABLE merge_commits (
+            repo_id INTEGER NOT NULL,
+            merge_id TEXT NOT NULL,
+            commit_id TEXT NOT NULL,
+            PRIMARY KEY (repo_id, merge_id, commit_id)
+        );
+        CREATE INDEX merge_commits_commit ON merge_commits (commit_id);",
+    )
+}
+
+/// Pull and merge requests fetched by `enrich`. merge_commit_id joins them
+/// to commit_details and merges; reviewers and labels are JSON arrays.
+fn pull_requests(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE pull_requests (
+            forge TEXT NOT NULL,
+            project TEXT NOT NULL,
+            number INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            state TEXT NOT NULL,
+            author TEXT,
+            reviewers TEXT NOT NULL,
+            labels TEXT NOT NULL,
+            created_at INTEGER,
+            merged_at INTEGER,
+            merge_commit_id TEXT,
+            head_branch TEXT,
+            base_branch TEXT,
+            url TEXT,
+            fetched_at INTEGER NOT NULL,
+            PRIMARY KEY (forge, project, number)
+        );
+        CREATE INDEX pull_requests_merge_commit ON pull_requests (merge_commit_id);",
+    )
+}
+
+/// The lowercased trailer key (`fixes`, `closes`, `refs`) an issue was
+/// referenced from, NULL for mentions elsewhere in the message. Existing
+/// rows take the first trailer whose value contains the reference.
+fn issue_ref_actions(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE commit_issue_refs ADD COLUMN action TEXT;
+        UPDATE commit_issue_refs SET action = (
+            SELECT lower(f.key) FROM commit_footers f
+            WHERE f.commit_id = commit_issue_refs.commit_id
+              AND instr(f.value, commit_issue_refs.issue_ref) > 0
+            ORDER BY f.position LIMIT 1
+        );
+        CREATE INDEX commit_issue_refs_issue ON commit_issue_refs (issue_ref);",
+    )
+}
+
+/// Whether an import left nothing reachable from its tip unimported; 0
+/// for bounded runs and ones stopped by the time budget or size limit, which
+/// the next run resumes. NULL for runs from before this version.
+fn import_run_complete(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE import_runs ADD COLUMN complete INTEGER;")
+}
+
+/// Whether a changed file looks generated (see generated.rs), set by
+/// imports with `--detect-generated`. NULL for files imported without it.
+fn generated_files(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE commit_files ADD COLUMN is_generated INTEGER;")
+}
+
+/// The tracking-ref tips (sorted OIDs, comma-separated) each remote's
+/// commit_remote_reachability rows were computed against. Existing rows
+/// can't be matched to tips, so they are dropped and recomputed by each
+/// repository's next import.
+fn remote_tips(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE remote_tips (
+            repo_id INTEGER NOT NULL,
+            remote_name TEXT NOT NULL,
+            tips TEXT NOT NULL,
+            PRIMARY KEY (repo_id, remote_name)
+        );
+        DELETE FROM commit_remote_reachability;",
+    )
+}
+
+/// Whether a changed file is a test (see `language::is_test_path`), and
+/// whether a commit changed any, filled in for the rows already stored.
+fn test_files(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE commit_files ADD COLUMN is_test INTEGER;
+         ALTER TABLE commit_details ADD COLUMN touches_tests INTEGER;",
+    )?;
+    let rows: Vec<(i64, String)> = conn
+        .prepare("SELECT rowid, path FROM commit_files")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    let mut update = conn.prepare("UPDATE commit_files SET is_test = ?2 WHERE rowid = ?1")?;
+    for (rowid, path) in rows {
+        update.execute(params![rowid, is_test_path(&path)])?;
+    }
+    conn.execute_batch(
+        "UPDATE commit_details SET touches_tests = EXISTS (
+             SELECT 1 FROM commit_files f WHERE f.commit_id = commit_details.id AND f.is_test
+         );",
+    )
+}
+
+/// Whether git saw a changed file as binary, and how many of a commit's
+/// files were. NULL for commits imported before this version until they
+/// are re-imported with `--on-conflict update`.
+fn binary_files(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE commit_files ADD COLUMN is_binary INTEGER;
+         ALTER TABLE commit_details ADD COLUMN binary_files_changed INTEGER;",
+    )
+}
+
+/// Each commit's blend of recency, churn and breadth, rescored by every
+/// import, so NULL until the repository is next imported.
+fn impact_rank(conn: &Connection) -> Result<()> {
+    conn.execute("ALTER TABLE commit_details ADD COLUMN impact_rank REAL", [])
+        .map(|_| ())
+}
+
+/// Recreates `table` from `sql` and copies the columns both layouts share.
+/// New NOT NULL columns get 0 or an empty string; derived columns such as
+/// `subject` are refreshed by re-importing with `--on-conflict update`.
+/// Databases from before multi-repository support get one repository row
+/// that their existing rows are assigned to.
+fn rebuild_table(
+    conn: &Connection,
+    table: &str,
+    sql: &str,
+    wanted: &[Column],
+    existing: &[Column],
+) -> Result<()> {
+    let legacy = format!("{}_legacy", table);
+    // Without the legacy behaviour, renaming would also repoint other
+    // tables' REFERENCES clauses at the table that is about to be dropped.
+    conn.execute_batch(&format!(
+        "PRAGMA legacy_alter_table = ON; ALTER TABLE {} RENAME TO {}; PRAGMA legacy_alter_table = OFF",
+        table, legacy
+    ))?;
+    conn.execute(sql, [])?;
+
+    let mut names = Vec::new();
+    let mut values = Vec::new();
+    for column in wanted {
+        let value = if existing.iter().any(|c| c.name == column.name) {
+            column.name.clone()
+        } else if column.name == "repo_id" {
+            legacy_repo_id(conn)?.to_string()
+        } else if !column.not_null {
+            continue;
+        } else if column.decl_type == "INTEGER" {
+            String::from("0")
+        } else {
+            String::from("''")
+        };
+        names.push(column.name.as_str());
+        values.push(value);
+    }
+    // Old primary keys were at most as strict as the new ones, but an
+    // unexpected duplicate shouldn't abort the whole upgrade.
+    conn.execute_batch(&format!(
+        "INSERT OR IGNORE INTO {} ({}) SELECT {} FROM {}; DROP TABLE {}",
+        table,
+        names.join(", "),
+        values.join(", "),
+        legacy,
+        legacy
+    ))
+}
+
+/// The repository rows of a single-repository database belong to; created
+/// as `default` (the same fallback `repository_name` uses) when missing.
+/// Re-import with `--name default` to keep adding to it.
+fn legacy_repo_id(conn: &Connection) -> Result<i64> {
+    let existing = conn.query_row("SELECT MIN(repo_id) FROM repositories", [], |row| {
+        row.get::<_, Option<i64>>(0)
+    })?;
+    match existing {
+        Some(repo_id) => Ok(repo_id),
+        None => {
+            conn.execute(
+                "INSERT INTO repositories (name, path) VALUES ('default', '')",
+                [],
+            )?;
+            info!(
+                "Existing rows were assigned to a repository named 'default'; \
+                 re-import with --name default to keep adding to it."
+            );
+            Ok(conn.last_insert_rowid())
+        }
+    }
+}
+
+#[derive(PartialEq)]
+struct Column {
+    name: String,
+    decl_type: String,
+    not_null: bool,
+    primary_key: i64,
+}
+
+fn columns(conn: &Connection, table: &str) -> Result<Vec<Column>> {
+    conn.prepare("SELECT name, type, \"notnull\", pk FROM pragma_table_info(?1) ORDER BY cid")?
+        .query_map(params![table], |row| {
+            Ok(Column {
+                name: row.get(0)?,
+                decl_type: row.get(1)?,
+                not_null: row.get(2)?,
+                primary_key: row.get(3)?,
+            })
+        })?
+        .collect()
+}
+
+fn table_exists(conn: &Connection, table: &str) -> Result<bool> {
+    conn.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?
+        .exists(params![table])
+}
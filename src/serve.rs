@@ -0,0 +1,542 @@
+use crate::cli::OutputFormat;
+use crate::graph::{self, GraphOptions};
+use crate::http;
+use crate::json::Json;
+use crate::logfmt::parse_date;
+use crate::query::{self, QueryResult};
+use crate::{debug, info, warn};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// `/commits` page size when no `limit` is given, and the most it may ask for.
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// How many requests are answered at once. Further connections wait,
+/// first in a queue as long again and then in the listen backlog.
+const WORKERS: usize = 8;
+/// How long a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A response: status, content type and body.
+type Response = (u16, &'static str, Vec<u8>);
+
+/// Answers read-only HTTP requests against the database until the process
+/// is stopped. Requests are answered by `WORKERS` threads, each opening a
+/// read-only connection per request, so a running import is never blocked.
+///
+/// - `GET /commits?repo=&author=&since=&until=&limit=&offset=`
+/// - `GET /commits/{oid}`, an abbreviated OID works when unambiguous
+/// - `GET /refs?repo=`
+/// - `GET /search?q=&patches=1&limit=`
+/// - `GET /graph?format=dot|graphml&merges_only=1&ref=`
+pub fn serve(db_path: &str, address: &str) -> Result<(), Box<dyn Error>> {
+    // Fail now rather than on the first request if the file isn't a database.
+    query::open_read_only(db_path)?
+        .query_row("SELECT COUNT(*) FROM sqlite_master", [], |_| Ok(()))?;
+    let listener = TcpListener::bind(address)?;
+    info!("Serving {} on http://{}", db_path, listener.local_addr()?);
+    serve_on(listener, db_path);
+    Ok(())
+}
+
+/// Hands the listener's connections to the worker threads, waiting while
+/// they are all busy and the queue is full.
+fn serve_on(listener: TcpListener, db_path: &str) {
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(WORKERS);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..WORKERS {
+        let receiver = Arc::clone(&receiver);
+        let db_path = db_path.to_string();
+        thread::spawn(move || loop {
+            // The lock is only held while waiting, not while answering.
+            let stream = match receiver.lock().map(|receiver| receiver.recv()) {
+                Ok(Ok(stream)) => stream,
+                _ => return,
+            };
+            if let Err(e) = handle(&db_path, stream) {
+                warn!("request failed: {}", e);
+            }
+        });
+    }
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        if sender.send(stream).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle(db_path: &str, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    // A client that never finishes its request would hold a worker forever.
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let (request_line, _) = http::read_head(&mut BufReader::new(&stream))?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+
+    let (status, content_type, body) = if method != "GET" {
+        error(405, "only GET is supported")
+    } else {
+        match query::open_read_only(db_path) {
+            Ok(conn) => route(&conn, target),
+            Err(e) => error(500, &e.to_string()),
+        }
+    };
+    debug!("{} {} -> {}", method, target, status);
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(())
+}
+
+fn route(conn: &Connection, target: &str) -> Response {
+    let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+    let params = parse_query(query_string);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let flag = |name: &str| param(name).is_some_and(|v| v != "0" && v != "false");
+
+    let result = match path.trim_end_matches('/') {
+        "/commits" => commits(conn, &param),
+        "/refs" => query::run_query_with(
+            conn,
+            "SELECT r.name AS repo, d.name, d.kind, d.id, d.resolved_oid
+             FROM ref_details d JOIN repositories r USING (repo_id)
+             WHERE ?1 IS NULL OR r.name = ?1
+             ORDER BY r.name, d.name",
+            params![param("repo")],
+        )
+        .map(|result| rows(&result))
+        .map_err(|e| (500, e.to_string())),
+        "/search" => match param("q") {
+            None => Err((400, String::from("search requires q"))),
+            Some(q) => query::check_search_index(conn)
+                .map_err(|e| (500, e))
+                .and_then(|_| {
+                    query::search(conn, q, flag("patches")).map_err(|e| (400, e.to_string()))
+                })
+                .map(|mut result| {
+                    if let Some(limit) = param("limit").and_then(|l| l.parse().ok()) {
+                        result.rows.truncate(limit);
+                    }
+                    rows(&result)
+                }),
+        },
+        "/graph" => {
+            let (format, content_type) = match param("format").unwrap_or("dot") {
+                "dot" => (OutputFormat::Dot, "text/vnd.graphviz; charset=utf-8"),
+                "graphml" => (OutputFormat::GraphMl, "application/graphml+xml"),
+                _ => return error(400, "format must be dot or graphml"),
+            };
+            let options = GraphOptions {
+                merges_only: flag("merges_only"),
+                ref_name: param("ref").map(str::to_string),
+            };
+            let mut body = Vec::new();
+            return match graph::write_graph(conn, &format, &mut body, &options) {
+                Ok(()) => (200, content_type, body),
+                Err(e) => error(400, &e.to_string()),
+            };
+        }
+        _ => match path.strip_prefix("/commits/") {
+            Some(oid) => commit(conn, oid),
+            None => Err((404, format!("no such endpoint: {}", path))),
+        },
+    };
+    match result {
+        Ok(json) => (200, "application/json", json.to_json().into_bytes()),
+        Err((status, message)) => error(status, &message),
+    }
+}
+
+/// A JSON body, or an HTTP status and error message.
+pub(crate) type ApiResult = Result<Json, (u16, String)>;
+
+fn commits<'a>(conn: &Connection, param: &dyn Fn(&str) -> Option<&'a str>) -> ApiResult {
+    let date = |name: &str| match param(name) {
+        None => Ok(None),
+        Some(value) => parse_date(value)
+            .map(Some)
+            .ok_or_else(|| (400, format!("{} must be YYYY-MM-DD or UNIX seconds", name))),
+    };
+    let number = |name: &str, default: i64| match param(name) {
+        None => Ok(default),
+        Some(value) => value
+            .parse::<i64>()
+            .map_err(|_| (400, format!("{} must be a number", name))),
+    };
+    let limit = number("limit", DEFAULT_LIMIT)?.clamp(0, MAX_LIMIT);
+    query::run_query_with(
+        conn,
+        "SELECT c.id, r.name AS repo, c.author, c.author_email, c.date, c.subject
+         FROM commit_details c JOIN repositories r USING (repo_id)
+         WHERE (?1 IS NULL OR r.name = ?1)
+           AND (?2 IS NULL OR c.author = ?2 COLLATE NOCASE)
+           AND (?3 IS NULL OR c.date >= ?3)
+           AND (?4 IS NULL OR c.date < ?4)
+         ORDER BY c.date DESC, c.id
+         LIMIT ?5 OFFSET ?6",
+        params![
+            param("repo"),
+            param("author"),
+            date("since")?,
+            date("until")?,
+            limit,
+            number("offset", 0)?
+        ],
+    )
+    .map(|result| rows(&result))
+    .map_err(|e| (500, e.to_string()))
+}
+
+/// One commit with its parents, changed files, notes and the repositories
+/// that contain it.
+pub(crate) fn commit(conn: &Connection, oid: &str) -> ApiResult {
+    if oid.len() < 4 || !oid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err((400, format!("not a commit id: {}", oid)));
+    }
+    let internal = |e: rusqlite::Error| (500, e.to_string());
+    let matches = query::run_query_with(
+        conn,
+        "SELECT DISTINCT id FROM commit_details WHERE id >= ?1 AND id < ?1 || 'g' LIMIT 2",
+        params![oid.to_ascii_lowercase()],
+    )
+    .map_err(internal)?;
+    let id = match matches.rows.as_slice() {
+        [] => return Err((404, format!("no commit {}", oid))),
+        [row] => match &row[0] {
+            Value::Text(id) => id.clone(),
+            _ => return Err((500, String::from("commit id is not text"))),
+        },
+        _ => return Err((400, format!("{} is ambiguous", oid))),
+    };
+
+    let details = query::run_query_with(
+        conn,
+        "SELECT id, author, author_email, committer_name, committer_email, date, subject, body,
+                classified_type, reverts_oid, reverted_by
+         FROM commit_details WHERE id = ?1 LIMIT 1",
+        params![id],
+    )
+    .map_err(internal)?;
+    let mut members = match rows(&details) {
+        Json::Array(mut items) => match items.pop() {
+            Some(Json::Object(members)) => members,
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    for (name, sql) in [
+        (
+            "repositories",
+            "SELECT r.name FROM commit_details c JOIN repositories r USING (repo_id)
+             WHERE c.id = ?1 ORDER BY r.name",
+        ),
+        (
+            "parents",
+            "SELECT DISTINCT parent FROM commit_relation WHERE child = ?1 ORDER BY parent",
+        ),
+    ] {
+        let result = query::run_query_with(conn, sql, params![id]).map_err(internal)?;
+        let values = result
+            .rows
+            .into_iter()
+            .map(|row| value_json(&row[0]))
+            .collect();
+        members.push((name.to_string(), Json::Array(values)));
+    }
+    let files = query::run_query_with(
+        conn,
+        "SELECT path, old_path, change_type, insertions, deletions
+         FROM commit_files WHERE commit_id = ?1 ORDER BY path",
+        params![id],
+    )
+    .map_err(internal)?;
+    members.push((String::from("files"), rows(&files)));
+    // Databases from before notes were imported have no table for them.
+    let has_notes = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE name = 'commit_notes'")
+        .and_then(|mut stmt| stmt.exists([]))
+        .map_err(internal)?;
+    if has_notes {
+        let notes = query::run_query_with(
+            conn,
+            "SELECT DISTINCT notes_ref, message FROM commit_notes WHERE commit_id = ?1
+             ORDER BY notes_ref",
+            params![id],
+        )
+        .map_err(internal)?;
+        members.push((String::from("notes"), rows(&notes)));
+    }
+    Ok(Json::Object(members))
+}
+
+/// Rows as an array of objects keyed by column name.
+pub(crate) fn rows(result: &QueryResult) -> Json {
+    Json::Array(
+        result
+            .rows
+            .iter()
+            .map(|row| {
+                Json::Object(
+                    result
+                        .columns
+                        .iter()
+                        .zip(row)
+                        .map(|(column, value)| (column.clone(), value_json(value)))
+                        .collect(),
+                )
+            })
+            .collect(),
+    )
+}
+
+fn value_json(value: &Value) -> Json {
+    match value {
+        Value::Null => Json::Null,
+        Value::Integer(i) => Json::Number(*i as f64),
+        Value::Real(f) => Json::Number(*f),
+        Value::Text(s) => Json::String(s.clone()),
+        Value::Blob(b) => Json::Number(b.len() as f64),
+    }
+}
+
+fn error(status: u16, message: &str) -> Response {
+    let body = Json::Object(vec![(
+        String::from("error"),
+        Json::String(message.to_string()),
+    )]);
+    (status, "application/json", body.to_json().into_bytes())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Splits a query string into decoded pairs. A key without `=` has an
+/// empty value and empty pairs are skipped.
+fn parse_query(query_string: &str) -> Vec<(String, String)> {
+    query_string
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_component(key), decode_component(value))
+        })
+        .collect()
+}
+
+/// Undoes URL percent-encoding, with `+` as a space as in form data.
+fn decode_component(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => match text
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    out.push(byte);
+                    i += 2;
+                }
+                None => out.push(b'%'),
+            },
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+    use std::io::Read;
+
+    /// A database with one repository, `app`, holding `commits` commits
+    /// by Jane Doe, one a day from 2023-11-14.
+    fn database(conn: &Connection, commits: i64) {
+        schema::migrate(conn).unwrap();
+        conn.execute_batch(&format!(
+            "INSERT INTO repositories (name, path) VALUES ('app', '/app');
+             WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {})
+             INSERT INTO commit_details (
+                 repo_id, id, author, date, message, author_is_committer, classified_type,
+                 log_author, log_date, subject_slug, author_email, committer_name,
+                 committer_email, author_time, author_tz_offset, commit_time,
+                 commit_tz_offset, is_breaking, subject, body)
+             SELECT 1, printf('%040x', i), 'Jane Doe', 1699920000 + 86400 * i, 'commit ' || i,
+                    1, 'other', 'Jane Doe', '', '', 'jane@example.com', 'Jane Doe',
+                    'jane@example.com', 1699920000 + 86400 * i, 0,
+                    1699920000 + 86400 * i, 0, 0, 'commit ' || i, ''
+             FROM n;",
+            commits
+        ))
+        .unwrap();
+    }
+
+    fn get(conn: &Connection, target: &str) -> (u16, Json) {
+        let (status, _, body) = route(conn, target);
+        (
+            status,
+            Json::parse(&String::from_utf8(body).unwrap()).unwrap(),
+        )
+    }
+
+    fn commit_count(conn: &Connection, target: &str) -> usize {
+        match get(conn, target) {
+            (200, json) => json.as_array().unwrap().len(),
+            (status, json) => panic!("{} -> {} {}", target, status, json.to_json()),
+        }
+    }
+
+    #[test]
+    fn clamps_the_commit_page_size() {
+        let conn = Connection::open_in_memory().unwrap();
+        database(&conn, 1005);
+        let cases = [
+            ("/commits", 100),
+            ("/commits?limit=5000", 1000),
+            ("/commits?limit=-3", 0),
+            ("/commits?limit=2&offset=1004", 1),
+            ("/commits/?limit=7", 7),
+        ];
+        for (target, expected) in cases {
+            assert_eq!(commit_count(&conn, target), expected, "{}", target);
+        }
+    }
+
+    #[test]
+    fn reads_query_parameters() {
+        let conn = Connection::open_in_memory().unwrap();
+        database(&conn, 5);
+        let cases = [
+            ("/commits?author=jane+doe", 5),
+            ("/commits?&author=Jane%20Doe&&unknown", 5),
+            ("/commits?repo=%61pp", 5),
+            ("/commits?repo=other", 0),
+            ("/commits?since=2023-11-17&until=1700265600", 1),
+        ];
+        for (target, expected) in cases {
+            assert_eq!(commit_count(&conn, target), expected, "{}", target);
+        }
+        let (status, json) = get(&conn, &format!("/commits/{:040x}", 3));
+        assert_eq!(status, 200);
+        assert_eq!(json.get("subject").and_then(Json::as_str), Some("commit 3"));
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let conn = Connection::open_in_memory().unwrap();
+        database(&conn, 1);
+        let cases = [
+            ("/commits?limit=ten", 400, "limit must be a number"),
+            ("/commits?offset=", 400, "offset must be a number"),
+            (
+                "/commits?since=yesterday",
+                400,
+                "since must be YYYY-MM-DD or UNIX seconds",
+            ),
+            ("/search", 400, "search requires q"),
+            ("/graph?format=png", 400, "format must be dot or graphml"),
+            ("/commits/xyz", 400, "not a commit id: xyz"),
+            ("/commits/abcd", 404, "no commit abcd"),
+            ("/nothing", 404, "no such endpoint: /nothing"),
+        ];
+        for (target, status, message) in cases {
+            let (got, json) = get(&conn, target);
+            assert_eq!(got, status, "{}", target);
+            assert_eq!(json.get("error").and_then(Json::as_str), Some(message));
+        }
+    }
+
+    #[test]
+    fn decodes_query_strings() {
+        let cases: &[(&str, &[(&str, &str)])] = &[
+            ("", &[]),
+            ("a=1&&b", &[("a", "1"), ("b", "")]),
+            ("q=a+b%2Bc", &[("q", "a b+c")]),
+            ("q=%E2%9C%93", &[("q", "✓")]),
+            (
+                "q=100%&r=%zz&s=%4",
+                &[("q", "100%"), ("r", "%zz"), ("s", "%4")],
+            ),
+            ("k%3D=v=w", &[("k=", "v=w")]),
+        ];
+        for (query_string, expected) in cases {
+            let expected: Vec<(String, String)> = expected
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            assert_eq!(parse_query(query_string), expected, "{}", query_string);
+        }
+    }
+
+    #[test]
+    fn answers_more_connections_than_workers() {
+        let path = std::env::temp_dir().join(format!(
+            "git_info_llama-serve-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        database(&Connection::open(&path).unwrap(), 3);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let db_path = path.to_str().unwrap().to_string();
+        thread::spawn(move || serve_on(listener, &db_path));
+
+        let request = |request: &'static str| {
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(address).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            })
+        };
+        let clients: Vec<_> = (0..WORKERS * 3)
+            .map(|_| request("GET /commits?limit=2 HTTP/1.1\r\n\r\n"))
+            .collect();
+        for client in clients {
+            let response = client.join().unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert_eq!(response.matches("\"author\":\"Jane Doe\"").count(), 2);
+        }
+        let response = request("POST /commits HTTP/1.1\r\n\r\n").join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
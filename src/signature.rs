@@ -0,0 +1,194 @@
+/// A commit or tag signature, identified but not verified: checking it would
+/// need the signers' keys, so only the format and the key it names are kept.
+pub struct Signature {
+    /// gpg, ssh or x509.
+    pub kind: String,
+    /// OpenPGP issuer fingerprint (or key ID for signatures that only carry
+    /// one), or the SSH public key as in an allowed_signers file.
+    pub key: Option<String>,
+}
+
+/// Parses an armored signature such as a commit's gpgsig header.
+pub fn parse(armored: &str) -> Option<Signature> {
+    let begin = armored.find("-----BEGIN ")?;
+    let armored = &armored[begin..];
+    let label = armored["-----BEGIN ".len()..].split("-----").next()?;
+    let (kind, key) = match label {
+        "PGP SIGNATURE" => ("gpg", decode_armor(armored).and_then(|b| pgp_issuer(&b))),
+        "SSH SIGNATURE" => (
+            "ssh",
+            decode_armor(armored).and_then(|b| ssh_public_key(&b)),
+        ),
+        "SIGNED MESSAGE" | "PKCS7" | "CMS" => ("x509", None),
+        _ => return None,
+    };
+    Some(Signature {
+        kind: kind.to_string(),
+        key,
+    })
+}
+
+/// The signature git appends to a signed tag's message, if any.
+pub fn from_tag_message(message: &str) -> Option<Signature> {
+    [
+        "-----BEGIN PGP SIGNATURE-----",
+        "-----BEGIN SSH SIGNATURE-----",
+        "-----BEGIN SIGNED MESSAGE-----",
+    ]
+    .iter()
+    .filter_map(|marker| message.find(marker))
+    .min()
+    .and_then(|at| parse(&message[at..]))
+}
+
+/// The base64 body between the BEGIN and END lines, skipping armor headers
+/// and the OpenPGP `=` checksum line.
+fn decode_armor(armored: &str) -> Option<Vec<u8>> {
+    let lines: Vec<&str> = armored
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .take_while(|line| !line.starts_with("-----END"))
+        .collect();
+    // Armor headers ("Version: ...") end at a blank line, which SSH
+    // signatures don't have.
+    let data = match lines.iter().position(|line| line.is_empty()) {
+        Some(blank) => &lines[blank + 1..],
+        None => &lines[..],
+    };
+    let body: String = data
+        .iter()
+        .filter(|line| !line.starts_with('='))
+        .copied()
+        .collect();
+    decode_base64(&body)
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|&c| c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// The issuer of the first OpenPGP signature packet (RFC 4880 5.2): the
+/// issuer fingerprint subpacket when present, else the 8-byte key ID.
+fn pgp_issuer(packets: &[u8]) -> Option<String> {
+    let tag = *packets.first()?;
+    if tag & 0x80 == 0 {
+        return None;
+    }
+    // Old and new packet headers differ in where the length lives; only the
+    // body matters here, so its length is taken as "the rest".
+    let (packet_type, header_len) = if tag & 0x40 != 0 {
+        let len_octets = match *packets.get(1)? {
+            0..=191 => 1,
+            192..=223 => 2,
+            _ => 5,
+        };
+        (tag & 0x3f, 1 + len_octets)
+    } else {
+        (tag >> 2 & 0x0f, 1 + [1, 2, 4, 0][(tag & 0x03) as usize])
+    };
+    if packet_type != 2 {
+        return None;
+    }
+    let body = packets.get(header_len..)?;
+    match *body.first()? {
+        // Version 3: the key ID sits at a fixed offset.
+        3 => body.get(7..15).map(hex),
+        version @ 4..=6 => {
+            let size_len = if version == 6 { 4 } else { 2 };
+            let mut rest = body.get(4..)?;
+            let mut key_id = None;
+            // Hashed subpackets, then unhashed ones.
+            for _ in 0..2 {
+                let size = rest
+                    .get(..size_len)?
+                    .iter()
+                    .fold(0usize, |n, &b| n << 8 | b as usize);
+                let mut area = rest.get(size_len..size_len + size)?;
+                rest = &rest[size_len + size..];
+                while !area.is_empty() {
+                    let (len, skip) = match area[0] {
+                        n @ 0..=191 => (n as usize, 1),
+                        n @ 192..=254 => {
+                            (((n as usize - 192) << 8) + *area.get(1)? as usize + 192, 2)
+                        }
+                        255 => (
+                            area.get(1..5)?
+                                .iter()
+                                .fold(0usize, |n, &b| n << 8 | b as usize),
+                            5,
+                        ),
+                    };
+                    let subpacket = area.get(skip..skip + len)?;
+                    area = &area[skip + len..];
+                    match subpacket.first().map(|t| t & 0x7f) {
+                        Some(33) => return subpacket.get(2..).map(hex),
+                        Some(16) => key_id = subpacket.get(1..9).map(hex),
+                        _ => {}
+                    }
+                }
+            }
+            key_id
+        }
+        _ => None,
+    }
+}
+
+/// The signer's public key from an SSHSIG blob (PROTOCOL.sshsig), formatted
+/// as `<type> <base64>`.
+fn ssh_public_key(blob: &[u8]) -> Option<String> {
+    let rest = blob.strip_prefix(b"SSHSIG")?.get(4..)?;
+    let key = ssh_string(rest)?;
+    let key_type = std::str::from_utf8(ssh_string(key)?).ok()?;
+    Some(format!("{} {}", key_type, encode_base64(key)))
+}
+
+/// A length-prefixed SSH wire format string.
+fn ssh_string(bytes: &[u8]) -> Option<&[u8]> {
+    let len = bytes
+        .get(..4)?
+        .iter()
+        .fold(0usize, |n, &b| n << 8 | b as usize);
+    bytes.get(4..4 + len)
+}
@@ -0,0 +1,127 @@
+#![allow(dead_code)]
+
+use git2::{Repository, Signature, Time};
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, process};
+
+/// A fresh directory under the system temp dir, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "git_info_llama-{}-{}-{}",
+            name,
+            process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Commits `files` (path and contents) on top of HEAD, one second after the
+/// previous commit so dates are ordered.
+pub fn commit(repo: &Repository, message: &str, files: &[(&str, &str)]) -> git2::Oid {
+    commit_as(repo, "Jane Doe", "jane@example.com", message, files)
+}
+
+/// `commit` by someone else.
+pub fn commit_as(
+    repo: &Repository,
+    name: &str,
+    email: &str,
+    message: &str,
+    files: &[(&str, &str)],
+) -> git2::Oid {
+    let workdir = repo.workdir().unwrap().to_path_buf();
+    let mut index = repo.index().unwrap();
+    for (path, contents) in files {
+        let full = workdir.join(path);
+        fs::create_dir_all(full.parent().unwrap()).unwrap();
+        fs::write(&full, contents).unwrap();
+        index.add_path(Path::new(path)).unwrap();
+    }
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let seconds = 1_700_000_000
+        + parent
+            .as_ref()
+            .map_or(0, |p| p.time().seconds() - 1_699_999_999);
+    let signature = Signature::new(name, email, &Time::new(seconds, 0)).unwrap();
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )
+    .unwrap()
+}
+
+/// A repository at `dir/name` with `count` commits, each changing one file.
+pub fn linear_repo(dir: &TempDir, name: &str, count: usize) -> Repository {
+    let repo = Repository::init(dir.join(name)).unwrap();
+    for i in 0..count {
+        commit(
+            &repo,
+            &format!("commit {}", i),
+            &[("file.txt", &i.to_string())],
+        );
+    }
+    repo
+}
+
+/// Runs the binary with `args`, failing the test when it exits non-zero.
+pub fn run(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_git_info_llama"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Runs the binary with `args`, expecting it to fail, and returns its stderr.
+pub fn run_failing(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_git_info_llama"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?} succeeded", args);
+    String::from_utf8(output.stderr).unwrap()
+}
+
+pub fn count(db: &Path, sql: &str) -> i64 {
+    Connection::open(db)
+        .unwrap()
+        .query_row(sql, [], |row| row.get(0))
+        .unwrap()
+}
@@ -0,0 +1,732 @@
+mod common;
+
+use common::{count, linear_repo, run, run_failing, TempDir};
+use std::fs;
+
+const COMMITS: &str = "SELECT COUNT(*) FROM commit_details";
+
+/// A bounded run imports the tip, which must not make the next run think
+/// the history is complete.
+fn plain_run_finishes_after(bound: &[&str]) {
+    let dir = TempDir::new("bounded");
+    linear_repo(&dir, "repo", 120);
+    let repo = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (repo, db_arg) = (repo.to_str().unwrap(), db.to_str().unwrap());
+
+    let mut args = vec!["--repo", repo, "--db", db_arg, "--quiet"];
+    args.extend_from_slice(bound);
+    run(&args);
+    assert!(count(&db, COMMITS) < 120);
+
+    run(&["--repo", repo, "--db", db_arg, "--quiet"]);
+    assert_eq!(count(&db, COMMITS), 120);
+}
+
+#[test]
+fn plain_run_after_max_walk_depth_imports_the_rest() {
+    plain_run_finishes_after(&["--max-walk-depth", "10"]);
+}
+
+#[test]
+fn plain_run_after_limit_imports_the_rest() {
+    plain_run_finishes_after(&["--limit", "10"]);
+}
+
+#[test]
+fn refresh_refs_only_finishes_a_bounded_walk() {
+    let dir = TempDir::new("refresh");
+    linear_repo(&dir, "repo", 30);
+    let repo = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (repo, db) = (repo.to_str().unwrap(), db.to_str().unwrap());
+
+    run(&["--repo", repo, "--db", db, "--quiet", "--limit", "5"]);
+    run(&["--repo", repo, "--db", db, "--quiet", "--refresh-refs-only"]);
+    assert_eq!(count(dir.join("db.sqlite").as_path(), COMMITS), 30);
+}
+
+#[test]
+fn unchanged_repository_takes_the_refs_only_path() {
+    let dir = TempDir::new("unchanged");
+    linear_repo(&dir, "repo", 3);
+    let repo = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (repo, db) = (repo.to_str().unwrap(), db.to_str().unwrap());
+
+    run(&["--repo", repo, "--db", db]);
+    let output = run(&["--repo", repo, "--db", db]);
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("No new commits"), "{}", log);
+}
+
+#[test]
+fn check_drift_compares_each_repository_with_its_own_import() {
+    let dir = TempDir::new("drift");
+    for name in ["one", "two"] {
+        let repo = git2::Repository::init(dir.join(name)).unwrap();
+        common::commit(&repo, name, &[("a.rs", name)]);
+    }
+    let (one, two) = (dir.join("one"), dir.join("two"));
+    let db = dir.join("db.sqlite");
+    let (one, two, db_arg) = (
+        one.to_str().unwrap(),
+        two.to_str().unwrap(),
+        db.to_str().unwrap(),
+    );
+    run(&["--repo", one, "--db", db_arg]);
+    run(&["--repo", two, "--db", db_arg]);
+    assert_eq!(
+        count(&db, "SELECT COUNT(DISTINCT repo_id) FROM repo_info"),
+        2
+    );
+
+    let output = run(&["--repo", one, "--db", db_arg, "--check-drift"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "Refs unchanged since last import."
+    );
+    let repo = git2::Repository::open(one).unwrap();
+    common::commit(&repo, "later", &[("a.rs", "later")]);
+    run_failing(&["--repo", one, "--db", db_arg, "--check-drift"]);
+}
+
+#[test]
+fn freshness_measures_each_repository_against_its_own_import() {
+    let dir = TempDir::new("freshness");
+    let db = dir.join("db.sqlite");
+    let db_arg = db.to_str().unwrap();
+    for name in ["one", "two"] {
+        let repo = git2::Repository::init(dir.join(name)).unwrap();
+        common::commit(&repo, name, &[("a.rs", name)]);
+        let path = dir.join(name);
+        run(&["--repo", path.to_str().unwrap(), "--db", db_arg]);
+    }
+    // Both tips are from 1_700_000_000; "two" was imported ten days later.
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    conn.execute(
+        "UPDATE repo_info SET value = CAST(1700000000 + 86400 * 10 * repo_id AS TEXT)
+         WHERE key = 'imported_at'",
+        [],
+    )
+    .unwrap();
+    drop(conn);
+
+    let output = run(&["stats", "freshness", "--db", db_arg, "--format", "json"]);
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.contains("\"age_days\":10}"), "{}", report);
+    assert!(report.contains("\"age_days\":20}"), "{}", report);
+    assert!(report.contains("\"repository\":\"one\",\"imported_at\":\"2023-11-24 22:13:20\""));
+}
+
+#[test]
+fn parquet_export_fails_as_unsupported() {
+    let dir = TempDir::new("parquet");
+    let db = dir.join("db.sqlite");
+    let error = run_failing(&[
+        "export",
+        "--db",
+        db.to_str().unwrap(),
+        "--format",
+        "parquet",
+        "--out",
+        dir.join("out").to_str().unwrap(),
+    ]);
+    assert!(
+        error.contains("--format parquet is not supported in this build"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn path_filter_imports_only_commits_touching_matching_files() {
+    let dir = TempDir::new("path-filter");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    common::commit(&repo, "code", &[("src/a.rs", "1")]);
+    common::commit(&repo, "docs", &[("docs/a.md", "1")]);
+    common::commit(&repo, "both", &[("src/b.rs", "1"), ("docs/b.md", "1")]);
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db_arg) = (path.to_str().unwrap(), db.to_str().unwrap());
+
+    let output = run(&[
+        "--repo",
+        path,
+        "--db",
+        db_arg,
+        "--path",
+        "src/*",
+        "--dry-run",
+        "--format",
+        "json",
+    ]);
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("\"commits\":2"));
+    run(&["--repo", path, "--db", db_arg, "--quiet", "--path", "src/*"]);
+    assert_eq!(count(&db, COMMITS), 2);
+    assert_eq!(
+        count(
+            &db,
+            "SELECT COUNT(*) FROM commit_files WHERE path GLOB 'src/*'"
+        ),
+        2
+    );
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM commit_files"), 2);
+}
+
+#[test]
+fn run_after_time_budget_imports_the_rest() {
+    let dir = TempDir::new("budget");
+    linear_repo(&dir, "repo", 120);
+    let repo = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (repo, db_arg) = (repo.to_str().unwrap(), db.to_str().unwrap());
+
+    // One chunk is always stored, then the spent budget stops the walk.
+    run(&[
+        "--repo",
+        repo,
+        "--db",
+        db_arg,
+        "--quiet",
+        "--time-budget-secs",
+        "0",
+    ]);
+    assert_eq!(count(&db, COMMITS), 50);
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM sync_state"), 0);
+    assert_eq!(count(&db, "SELECT complete FROM import_runs"), 0);
+
+    run(&["--repo", repo, "--db", db_arg, "--quiet"]);
+    assert_eq!(count(&db, COMMITS), 120);
+    assert_eq!(
+        count(
+            &db,
+            "SELECT complete FROM import_runs ORDER BY run_id DESC LIMIT 1"
+        ),
+        1
+    );
+}
+
+#[test]
+fn root_commit_reports_every_file_as_added() {
+    let dir = TempDir::new("root");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    common::commit(
+        &repo,
+        "initial",
+        &[
+            ("README.md", "one\ntwo\n"),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    );
+    let db = dir.join("db.sqlite");
+    run(&[
+        "--repo",
+        dir.join("repo").to_str().unwrap(),
+        "--db",
+        db.to_str().unwrap(),
+        "--quiet",
+    ]);
+
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    let files: Vec<(String, String, i64, i64)> = conn
+        .prepare("SELECT path, change_type, insertions, deletions FROM commit_files ORDER BY path")
+        .unwrap()
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert_eq!(
+        files,
+        [
+            (String::from("README.md"), String::from("added"), 2, 0),
+            (String::from("src/main.rs"), String::from("added"), 1, 0),
+        ]
+    );
+}
+
+#[test]
+fn detect_generated_flags_generated_files() {
+    let dir = TempDir::new("generated");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    common::commit(
+        &repo,
+        "initial",
+        &[
+            ("Cargo.lock", "# lockfile\n"),
+            ("dist/app.js", "bundle\n"),
+            ("src/api.rs", "// @generated by a tool\nfn api() {}\n"),
+            ("src/main.rs", "fn main() {}\n"),
+        ],
+    );
+    let repo = dir.join("repo");
+    let (repo, db) = (repo.to_str().unwrap(), dir.join("db.sqlite"));
+    let generated = "SELECT group_concat(path) FROM \
+                     (SELECT path FROM commit_files WHERE is_generated ORDER BY path)";
+
+    run(&["--repo", repo, "--db", db.to_str().unwrap(), "--quiet"]);
+    assert_eq!(
+        count(
+            &db,
+            "SELECT COUNT(*) FROM commit_files WHERE is_generated IS NULL"
+        ),
+        4
+    );
+
+    let db = dir.join("paths.sqlite");
+    let db_arg = db.to_str().unwrap();
+    run(&[
+        "--repo",
+        repo,
+        "--db",
+        db_arg,
+        "--quiet",
+        "--detect-generated",
+    ]);
+    let paths: String = rusqlite::Connection::open(&db)
+        .unwrap()
+        .query_row(generated, [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(paths, "Cargo.lock,dist/app.js");
+
+    let db = dir.join("markers.sqlite");
+    let db_arg = db.to_str().unwrap();
+    run(&[
+        "--repo",
+        repo,
+        "--db",
+        db_arg,
+        "--quiet",
+        "--generated-markers",
+    ]);
+    let paths: String = rusqlite::Connection::open(&db)
+        .unwrap()
+        .query_row(generated, [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(paths, "Cargo.lock,dist/app.js,src/api.rs");
+
+    let output = run(&[
+        "stats",
+        "hotspots",
+        "--db",
+        db_arg,
+        "--exclude-generated",
+        "--format",
+        "json",
+    ]);
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("\"path\":\"src/main.rs\""), "{}", report);
+    assert!(!report.contains("Cargo.lock"), "{}", report);
+}
+
+#[test]
+fn remote_reachability_walks_only_what_moved() {
+    let dir = TempDir::new("reachability");
+    let repo = linear_repo(&dir, "repo", 3);
+    repo.remote("origin", "https://example.com/repo.git")
+        .unwrap();
+    let pushed = repo.head().unwrap().target().unwrap();
+    repo.reference("refs/remotes/origin/main", pushed, true, "")
+        .unwrap();
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db_arg) = (path.to_str().unwrap(), db.to_str().unwrap());
+    let reachable = "SELECT COUNT(*) FROM commit_remote_reachability WHERE reachable = 1";
+    let rows = "SELECT COUNT(*) FROM commit_remote_reachability";
+    // Survives only as long as the stored rows aren't recomputed.
+    let marked = "SELECT COUNT(*) FROM commit_remote_reachability WHERE reachable = 2";
+
+    run(&["--repo", path, "--db", db_arg, "--quiet"]);
+    assert_eq!(count(&db, reachable), 3);
+    rusqlite::Connection::open(&db)
+        .unwrap()
+        .execute(
+            "UPDATE commit_remote_reachability SET reachable = 2 WHERE commit_id = ?1",
+            [pushed.to_string()],
+        )
+        .unwrap();
+
+    // Local commits only add their own rows.
+    common::commit(&repo, "local 1", &[("file.txt", "a")]);
+    common::commit(&repo, "local 2", &[("file.txt", "b")]);
+    run(&["--repo", path, "--db", db_arg, "--quiet"]);
+    assert_eq!(count(&db, rows), 5);
+    assert_eq!(count(&db, reachable), 2);
+    assert_eq!(count(&db, marked), 1);
+
+    // Pushing moves the tracking ref forward: only the pushed commits are
+    // walked.
+    let head = repo.head().unwrap().target().unwrap();
+    repo.reference("refs/remotes/origin/main", head, true, "")
+        .unwrap();
+    run(&["--repo", path, "--db", db_arg, "--quiet"]);
+    assert_eq!(count(&db, reachable), 4);
+    assert_eq!(count(&db, marked), 1);
+
+    // A force push to an older commit recomputes the remote.
+    repo.reference("refs/remotes/origin/main", pushed, true, "")
+        .unwrap();
+    run(&["--repo", path, "--db", db_arg, "--quiet"]);
+    assert_eq!(count(&db, reachable), 3);
+    assert_eq!(count(&db, marked), 0);
+
+    repo.remote_delete("origin").unwrap();
+    run(&["--repo", path, "--db", db_arg, "--quiet"]);
+    assert_eq!(count(&db, rows), 0);
+}
+
+#[test]
+fn untested_changes_counts_code_commits_without_tests() {
+    let dir = TempDir::new("untested");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    common::commit(&repo, "add lib", &[("src/lib.rs", "1")]);
+    common::commit(
+        &repo,
+        "add tests",
+        &[("src/lib.rs", "2"), ("tests/lib.rs", "2")],
+    );
+    common::commit(&repo, "docs", &[("README.md", "3")]);
+    let fix = common::commit(&repo, "fix", &[("src/lib.rs", "4")]);
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db) = (path.to_str().unwrap(), db.to_str().unwrap());
+    run(&["--repo", path, "--db", db, "--quiet"]);
+
+    let report = |args: &[&str]| {
+        let mut full = vec!["stats", "untested-changes", "--db", db, "--format", "json"];
+        full.extend_from_slice(args);
+        String::from_utf8(run(&full).stdout).unwrap()
+    };
+    assert_eq!(
+        report(&["--limit", "1"]).trim(),
+        format!(
+            "[{{\"code_commits\":3,\"untested\":2,\"untested_percent\":66.7,\"sample\":\"{}\"}}]",
+            fix
+        )
+    );
+    assert!(report(&["--path", "*.md"]).contains("\"code_commits\":1,\"untested\":1,"));
+}
+
+#[test]
+fn ownership_export_lists_files_an_author_leads() {
+    let dir = TempDir::new("ownership");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    let bob = |message: &str, files: &[(&str, &str)]| {
+        common::commit_as(&repo, "Bob Roe", "bob@example.com", message, files)
+    };
+    common::commit(&repo, "a1", &[("a.rs", "1\n")]);
+    common::commit(&repo, "a2", &[("a.rs", "1\n2\n")]);
+    bob("b1", &[("b.rs", "1\n")]);
+    bob("b2", &[("b.rs", "1\n2\n")]);
+    common::commit(&repo, "b3", &[("b.rs", "1\n2\n3\n")]);
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db) = (path.to_str().unwrap(), db.to_str().unwrap());
+    run(&["--repo", path, "--db", db, "--quiet"]);
+    run(&["blame", "--repo", path, "--db", db]);
+
+    let owned = |author: &str| {
+        let output = run(&["export", "ownership", "--author", author, "--db", db]);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let jane = owned("JANE");
+    assert!(jane.contains("\"path\":\"a.rs\""), "{}", jane);
+    assert!(jane.contains("\"share_percent\":100,"), "{}", jane);
+    assert!(!jane.contains("b.rs"), "{}", jane);
+    let bob = owned("bob@");
+    assert!(bob.contains("\"path\":\"b.rs\""), "{}", bob);
+    assert!(bob.contains("\"share_percent\":66.7"), "{}", bob);
+    assert!(!bob.contains("a.rs"), "{}", bob);
+}
+
+#[test]
+fn author_network_pairs_authors_of_shared_files() {
+    let dir = TempDir::new("network");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    common::commit(&repo, "jane", &[("a.rs", "1"), ("b.rs", "1")]);
+    common::commit_as(&repo, "Bob Roe", "bob@example.com", "bob", &[("a.rs", "2")]);
+    common::commit_as(&repo, "Bob Roe", "bob@example.com", "bob", &[("b.rs", "2")]);
+    common::commit_as(&repo, "Ann Poe", "ann@example.com", "ann", &[("b.rs", "3")]);
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db) = (path.to_str().unwrap(), db.to_str().unwrap());
+    run(&["--repo", path, "--db", db, "--quiet"]);
+
+    let report = |format: &str| {
+        let output = run(&["stats", "author-network", "--db", db, "--format", format]);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        report("json").trim(),
+        "{\"Ann Poe\":[{\"author\":\"Bob Roe\",\"shared_files\":1},\
+         {\"author\":\"Jane Doe\",\"shared_files\":1}],\
+         \"Bob Roe\":[{\"author\":\"Jane Doe\",\"shared_files\":2},\
+         {\"author\":\"Ann Poe\",\"shared_files\":1}],\
+         \"Jane Doe\":[{\"author\":\"Bob Roe\",\"shared_files\":2},\
+         {\"author\":\"Ann Poe\",\"shared_files\":1}]}"
+    );
+    assert!(report("dot").contains("\"Bob Roe\" -- \"Jane Doe\" [weight=2, label=\"2\"];"));
+}
+
+#[test]
+fn binary_files_are_counted_per_commit() {
+    let dir = TempDir::new("binary");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    common::commit(
+        &repo,
+        "assets",
+        &[
+            ("logo.png", "PNG\0\u{1}\u{2}"),
+            ("icon.ico", "\0\0\u{1}"),
+            ("README.md", "text\n"),
+        ],
+    );
+    common::commit(&repo, "text", &[("README.md", "more text\n")]);
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    run(&[
+        "--repo",
+        path.to_str().unwrap(),
+        "--db",
+        db.to_str().unwrap(),
+        "--quiet",
+    ]);
+
+    let binary_files = |subject: &str| {
+        count(
+            &db,
+            &format!(
+                "SELECT binary_files_changed FROM commit_details WHERE subject = '{}'",
+                subject
+            ),
+        )
+    };
+    assert_eq!(binary_files("assets"), 2);
+    assert_eq!(binary_files("text"), 0);
+    assert_eq!(
+        count(&db, "SELECT COUNT(*) FROM commit_files WHERE is_binary"),
+        2
+    );
+}
+
+#[test]
+fn resolve_ambiguous_refs_lists_branches_sharing_a_target_once() {
+    let dir = TempDir::new("ambiguous-refs");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    let first = common::commit(&repo, "first", &[("a.rs", "1")]);
+    common::commit(&repo, "second", &[("a.rs", "2")]);
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    repo.branch("release", &head, false).unwrap();
+    repo.branch("old", &repo.find_commit(first).unwrap(), false)
+        .unwrap();
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db_arg) = (path.to_str().unwrap(), db.to_str().unwrap());
+    run(&["--repo", path, "--db", db_arg, "--quiet"]);
+
+    let freshness = |extra: &[&str]| {
+        let mut args = vec!["stats", "freshness", "--db", db_arg, "--format", "json"];
+        args.extend_from_slice(extra);
+        String::from_utf8(run(&args).stdout).unwrap()
+    };
+    assert_eq!(freshness(&[]).matches("\"branch\"").count(), 3);
+    let resolved = freshness(&["--resolve-ambiguous-refs"]);
+    assert_eq!(resolved.matches("\"branch\"").count(), 2, "{}", resolved);
+    let shared = format!(
+        "\"branch\":\"{},refs/heads/release\"",
+        repo.head().unwrap().name().unwrap()
+    );
+    assert!(resolved.contains(&shared), "{}", resolved);
+    assert!(resolved.contains("\"branch\":\"refs/heads/old\""));
+}
+
+#[test]
+fn push_activity_buckets_reflog_entries_by_local_time() {
+    let dir = TempDir::new("push-activity");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    common::commit(&repo, "one", &[("a.rs", "1")]);
+    common::commit(&repo, "two", &[("a.rs", "2")]);
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db) = (path.to_str().unwrap(), db.to_str().unwrap());
+    run(&["--repo", path, "--db", db, "--quiet", "--with-reflog"]);
+
+    // 2023-11-14 22:13 UTC is just past midnight on Wednesday at UTC+2.
+    // HEAD and the branch both log each commit, which counts once.
+    let conn = rusqlite::Connection::open(db).unwrap();
+    conn.execute("UPDATE reflog SET time = 1700000000, tz_offset = 120", [])
+        .unwrap();
+    drop(conn);
+    let output = run(&["stats", "push-activity", "--db", db, "--format", "json"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "[{\"weekday\":\"Wed\",\"hour\":0,\"entries\":2}]"
+    );
+}
+
+#[test]
+fn select_columns_limits_exported_fields() {
+    let dir = TempDir::new("select-columns");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    common::commit(&repo, "first", &[("a.rs", "1")]);
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let csv = dir.join("csv");
+    let (path, db) = (path.to_str().unwrap(), db.to_str().unwrap());
+    run(&["--repo", path, "--db", db, "--quiet"]);
+
+    let output = run(&[
+        "export",
+        "--db",
+        db,
+        "--format",
+        "json",
+        "--select-columns",
+        "subject, author",
+    ]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        "{\"record\":\"commit\",\"subject\":\"first\",\"author\":\"Jane Doe\"}"
+    );
+
+    run(&[
+        "export",
+        "--db",
+        db,
+        "--format",
+        "csv",
+        "--out",
+        csv.to_str().unwrap(),
+        "--select-columns",
+        "author_email,subject",
+    ]);
+    assert_eq!(
+        fs::read_to_string(csv.join("commit_details.csv")).unwrap(),
+        "author_email,subject\r\njane@example.com,first\r\n"
+    );
+    assert!(!csv.join("commit_relation.csv").exists());
+
+    let error = run_failing(&[
+        "export",
+        "--db",
+        db,
+        "--format",
+        "json",
+        "--select-columns",
+        "id,summary",
+    ]);
+    assert!(error.contains("Unknown column(s) for --select-columns: summary"));
+    let error = run_failing(&["export", "--db", db, "--select-columns", "id"]);
+    assert!(error.contains("--select-columns only applies to export --format json or csv"));
+}
+
+#[test]
+fn top_impact_ranks_by_recency_churn_and_breadth() {
+    let dir = TempDir::new("impact");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    let wide = "line\n".repeat(50);
+    common::commit(&repo, "small", &[("a.rs", "1\n")]);
+    common::commit(
+        &repo,
+        "wide",
+        &[("x/a.rs", &wide), ("y/a.rs", &wide), ("z/a.rs", &wide)],
+    );
+    common::commit(&repo, "latest", &[("a.rs", "2\n")]);
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db_arg) = (path.to_str().unwrap(), db.to_str().unwrap());
+
+    let top = |weights: &[&str]| {
+        let mut args = vec!["--repo", path, "--db", db_arg, "--quiet"];
+        args.extend_from_slice(weights);
+        run(&args);
+        let output = run(&[
+            "stats",
+            "top-impact",
+            "1",
+            "--db",
+            db_arg,
+            "--format",
+            "json",
+        ]);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let ranked = top(&[]);
+    assert_eq!(ranked.matches("\"id\"").count(), 1);
+    assert!(ranked.contains("\"summary\":\"wide\""), "{}", ranked);
+    assert_eq!(
+        count(
+            &db,
+            "SELECT COUNT(*) FROM commit_details WHERE impact_rank BETWEEN 0 AND 1"
+        ),
+        3
+    );
+
+    // Reweighting takes effect on the next import, even with nothing new.
+    let ranked = top(&["--churn-weight", "0", "--directories-weight", "0"]);
+    assert!(ranked.contains("\"summary\":\"latest\""), "{}", ranked);
+    assert_eq!(
+        count(
+            &db,
+            "SELECT COUNT(*) FROM commit_details WHERE impact_rank = 1"
+        ),
+        1
+    );
+
+    // Nothing new and the same weights: the pass is skipped.
+    rusqlite::Connection::open(&db)
+        .unwrap()
+        .execute("UPDATE commit_details SET impact_rank = NULL", [])
+        .unwrap();
+    top(&["--churn-weight", "0", "--directories-weight", "0"]);
+    assert_eq!(
+        count(
+            &db,
+            "SELECT COUNT(*) FROM commit_details WHERE impact_rank IS NULL"
+        ),
+        3
+    );
+}
+
+#[test]
+fn load_commit_returns_the_issue_refs_found_at_import() {
+    let dir = TempDir::new("load-commit");
+    let repo = git2::Repository::init(dir.join("repo")).unwrap();
+    let oid = common::commit(
+        &repo,
+        "fix: login\n\nSee #7.\n\nRefs: PROJ-42",
+        &[("a.rs", "1")],
+    );
+    let path = dir.join("repo");
+    let db = dir.join("db.sqlite");
+    let (path, db_arg) = (path.to_str().unwrap(), db.to_str().unwrap());
+    run(&[
+        "--repo",
+        path,
+        "--db",
+        db_arg,
+        "--quiet",
+        "--issue-pattern",
+        r"PROJ-\d+",
+    ]);
+
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    let commit = git_info_llama::load_commit(&conn, &oid.to_string())
+        .unwrap()
+        .unwrap();
+    // Reparsing with the built-in patterns would find #7 instead.
+    assert_eq!(
+        commit.parsed.issue_refs,
+        vec![("PROJ-42".to_string(), Some("refs".to_string()))]
+    );
+    assert_eq!(
+        commit.parsed.footers,
+        vec![("Refs".to_string(), "PROJ-42".to_string())]
+    );
+    assert_eq!(commit.parsed.cc_type.as_deref(), Some("fix"));
+    assert_eq!(commit.parsed.subject, "login");
+}